@@ -0,0 +1,89 @@
+use std::process::Command;
+use std::error::Error;
+use anyhow::anyhow;
+use serde::Deserialize;
+use crate::config::Configs;
+use crate::secret::Secret;
+use crate::transport;
+
+// Authority of Azure AD tokens
+const AUTHORITY: &str = "https://login.microsoftonline.com";
+
+// Scope of OAuth tokens for Blob Storage
+const STORAGE_SCOPE: &str = "https://storage.azure.com/.default";
+
+// Resource of OAuth tokens for Blob Storage, as Azure CLI takes it
+const STORAGE_RESOURCE: &str = "https://storage.azure.com/";
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    access_token: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct CliToken {
+    access_token: String,
+}
+
+#[derive(Deserialize)]
+struct ErrorResponse {
+    error: String,
+    #[serde(default)]
+    error_description: String,
+}
+
+// Get an OAuth access token for Blob Storage by the client ID and secret of the service principal
+// (client credentials flow). The token is valid for about an hour.
+pub fn service_principal_token(cfg: &Configs) -> Result<Secret, Box<dyn Error + Send + Sync>> {
+    if cfg.tenant_id.is_empty() || cfg.client_id.is_empty() || cfg.client_secret.is_empty() {
+        return Err(anyhow!("tenant_id, client_id and client_secret are required for AAD authentication").into());
+    }
+
+    // The same CA certificates as the storage, since proxies intercept Azure AD as well
+    let mut builder = reqwest::blocking::Client::builder()
+        .danger_accept_invalid_certs(cfg.insecure.unwrap_or(false));
+    for cert in transport::root_certificates(cfg)? {
+        builder = builder.add_root_certificate(cert);
+    }
+
+    let url = format!("{}/{}/oauth2/v2.0/token", AUTHORITY, cfg.tenant_id);
+    let res = builder.build()?
+        .post(&url)
+        .form(&[
+            ("grant_type", "client_credentials"),
+            ("client_id", cfg.client_id.as_str()),
+            ("client_secret", cfg.client_secret.expose()),
+            ("scope", STORAGE_SCOPE),
+        ])
+        .send()
+        .map_err(|e| anyhow!("Cannot get AAD token: {}", e))?;
+
+    let status = res.status();
+    let body = res.bytes()?;
+    if !status.is_success() {
+        return Err(match serde_json::from_slice::<ErrorResponse>(&body) {
+            Ok(e) => anyhow!("Cannot get AAD token: {} {}", e.error, e.error_description.lines().next().unwrap_or("")),
+            Err(_) => anyhow!("Cannot get AAD token: {}", status)
+        }.into());
+    }
+    let token: TokenResponse = serde_json::from_slice(&body)
+        .map_err(|e| anyhow!("Invalid AAD token response: {}", e))?;
+    Ok(token.access_token.into())
+}
+
+// Get an OAuth access token for Blob Storage of the user logged in by Azure CLI (az login),
+// for ad-hoc use by developers without configuring keys
+pub fn azure_cli_token() -> Result<Secret, Box<dyn Error + Send + Sync>> {
+    let output = Command::new("az")
+        .args(&["account", "get-access-token", "--resource", STORAGE_RESOURCE, "--output", "json"])
+        .output()
+        .map_err(|e| anyhow!("Cannot run Azure CLI (az): {}", e))?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(anyhow!("Cannot get a token by Azure CLI. Log in by az login: {}", stderr.trim()).into());
+    }
+    let token: CliToken = serde_json::from_slice(&output.stdout)
+        .map_err(|e| anyhow!("Invalid Azure CLI token: {}", e))?;
+    Ok(token.access_token.into())
+}
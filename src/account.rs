@@ -0,0 +1,57 @@
+use azure_storage::core::prelude::*;
+
+use std::error::Error;
+
+// Defaults of transfers, which depend on the account type
+#[derive(Clone, Copy, Debug)]
+pub struct TransferDefaults {
+    pub block_size: u64,
+    pub concurrency: usize,
+}
+
+pub const STANDARD: TransferDefaults = TransferDefaults { block_size: 4 * 1024 * 1024, concurrency: 4 };
+
+// Premium block blob accounts have lower latency and higher throughput per request
+pub const PREMIUM: TransferDefaults = TransferDefaults { block_size: 16 * 1024 * 1024, concurrency: 16 };
+
+// Low memory profile for devices with 128-256 MB of memory, used regardless of the account type and other settings
+pub const LOW_MEMORY: TransferDefaults = TransferDefaults { block_size: 1024 * 1024, concurrency: 1 };
+
+// SKU and kind of the storage account
+pub struct AccountInfo {
+    pub sku_name: String,
+    pub account_kind: String,
+}
+
+impl AccountInfo {
+    pub async fn get(storage_client: &StorageClient) -> Result<AccountInfo, Box<dyn Error + Send + Sync>> {
+        let res = storage_client
+            .storage_account_client()
+            .get_account_information()
+            .execute()
+            .await?;
+        Ok(AccountInfo { sku_name: res.sku_name, account_kind: res.account_kind })
+    }
+
+    pub fn is_premium_block_blob(&self) -> bool {
+        self.account_kind == "BlockBlobStorage" && self.sku_name.starts_with("Premium")
+    }
+
+    pub fn transfer_defaults(&self) -> TransferDefaults {
+        if self.is_premium_block_blob() { PREMIUM } else { STANDARD }
+    }
+}
+
+// Transfer defaults for the account. Standard ones are used if the account type cannot be got,
+// such as by a SAS token without the permission.
+pub async fn transfer_defaults(storage_client: &StorageClient, debug: bool) -> TransferDefaults {
+    match AccountInfo::get(storage_client).await {
+        Ok(info) => info.transfer_defaults(),
+        Err(e) => {
+            if debug {
+                println!("Cannot get account information: {}", e);
+            }
+            STANDARD
+        }
+    }
+}
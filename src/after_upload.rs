@@ -0,0 +1,160 @@
+use std::fs::{self, OpenOptions};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::error::Error;
+use anyhow::anyhow;
+use crate::hash::HashAlgorithm;
+use crate::manifest::ManifestEntry;
+
+// What to do with local files after they are put, so that devices with small storage reclaim the space
+// once the data is safely in the storage
+#[derive(Clone, Debug, PartialEq)]
+pub enum AfterUpload {
+    Keep,
+    Delete,
+    // Move to the directory, keeping the path relative to the local directory put
+    Move(PathBuf),
+    // Truncate to 0 bytes, keeping the file for the process which writes it
+    Truncate,
+}
+
+impl FromStr for AfterUpload {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "keep" => Ok(AfterUpload::Keep),
+            "delete" => Ok(AfterUpload::Delete),
+            "truncate" => Ok(AfterUpload::Truncate),
+            _ => match s.strip_prefix("move:") {
+                Some(dir) if !dir.is_empty() => Ok(AfterUpload::Move(PathBuf::from(dir))),
+                _ => Err(anyhow!("Invalid after upload '{}'. Specify keep, delete, move:<dir> or truncate", s))
+            }
+        }
+    }
+}
+
+impl AfterUpload {
+    // Check it for syncing up the local directory, where files left would be put again,
+    // or their blobs deleted by --delete
+    pub fn check_sync(&self, local_dir: &Path, delete: bool) -> Result<(), Box<dyn Error + Send + Sync>> {
+        match self {
+            AfterUpload::Keep => Ok(()),
+            AfterUpload::Truncate => Err(anyhow!("--after-upload=truncate is not supported in sync mode").into()),
+            AfterUpload::Move(dir) if dir.starts_with(local_dir) =>
+                Err(anyhow!("Cannot move files under the local directory after upload").into()),
+            _ if delete => Err(anyhow!("--after-upload is not supported with --delete").into()),
+            _ => Ok(())
+        }
+    }
+
+    // Apply to the local files of the entries, which must already be verified by read-back.
+    // Files whose size or SHA-256 changed since they were put are kept, since they have data not in the blobs.
+    // The hash is compared too, since a file rewritten in place may have the same size.
+    pub fn apply(&self, entries: &[ManifestEntry], root: &Path, debug: bool)
+        -> Result<(), Box<dyn Error + Send + Sync>>
+    {
+        if *self == AfterUpload::Keep {
+            return Ok(());
+        }
+        for entry in entries.iter() {
+            let path = Path::new(&entry.local);
+            let size = fs::metadata(path).map_err(|e| anyhow!("Cannot read {:?}: {}", path, e))?.len();
+            if size != entry.size || HashAlgorithm::Sha256.file_digest(path)? != entry.sha256 {
+                eprintln!("{} is modified since put, kept", path.display());
+                continue;
+            }
+            match self {
+                AfterUpload::Keep => {},
+                AfterUpload::Delete => {
+                    fs::remove_file(path).map_err(|e| anyhow!("Cannot delete {:?}: {}", path, e))?;
+                },
+                AfterUpload::Move(dir) => {
+                    let relative = match path.strip_prefix(root) {
+                        Ok(relative) if relative.file_name().is_some() => relative,
+                        _ => Path::new(path.file_name().ok_or(anyhow!("Cannot extract filename from {:?}", path))?)
+                    };
+                    let target = dir.join(relative);
+                    if let Some(parent) = target.parent() {
+                        fs::create_dir_all(parent).map_err(|e| anyhow!("Cannot create {:?}: {}", parent, e))?;
+                    }
+                    fs::rename(path, &target)
+                        .map_err(|e| anyhow!("Cannot move {:?} to {:?}: {}", path, target, e))?;
+                },
+                AfterUpload::Truncate => {
+                    OpenOptions::new().write(true).open(path)
+                        .and_then(|file| file.set_len(0))
+                        .map_err(|e| anyhow!("Cannot truncate {:?}: {}", path, e))?;
+                }
+            }
+            if debug {
+                println!(" after upload {:?}: {}", self, path.display());
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    #[test]
+    fn from_str_policies() {
+        assert_eq!("keep".parse::<AfterUpload>().unwrap(), AfterUpload::Keep);
+        assert_eq!("delete".parse::<AfterUpload>().unwrap(), AfterUpload::Delete);
+        assert_eq!("truncate".parse::<AfterUpload>().unwrap(), AfterUpload::Truncate);
+        assert_eq!("move:/var/uploaded".parse::<AfterUpload>().unwrap(), AfterUpload::Move("/var/uploaded".into()));
+        assert!("move:".parse::<AfterUpload>().is_err());
+        assert!("remove".parse::<AfterUpload>().is_err());
+    }
+
+    // Directory with a file put, and the entry of the file
+    fn put_file(data: &[u8]) -> (PathBuf, ManifestEntry) {
+        let root = env::temp_dir().join(format!("azst-after-upload-{}", uuid::Uuid::new_v4()));
+        let path = root.join("logs").join("a.log");
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, data).unwrap();
+        let entry = ManifestEntry {
+            local: path.display().to_string(),
+            size: data.len() as u64,
+            sha256: HashAlgorithm::Sha256.digest(data),
+            ..Default::default()
+        };
+        (root, entry)
+    }
+
+    #[test]
+    fn apply_to_files_put() {
+        let (root, entry) = put_file(b"line 1\n");
+        AfterUpload::Truncate.apply(&[entry.clone()], &root, false).unwrap();
+        assert_eq!(fs::metadata(&entry.local).unwrap().len(), 0);
+
+        let (root, entry) = put_file(b"line 1\n");
+        let dir = root.join("uploaded");
+        AfterUpload::Move(dir.clone()).apply(&[entry.clone()], &root, false).unwrap();
+        assert!(!Path::new(&entry.local).exists());
+        assert_eq!(fs::read(dir.join("logs").join("a.log")).unwrap(), b"line 1\n");
+
+        let (root, entry) = put_file(b"line 1\n");
+        AfterUpload::Delete.apply(&[entry.clone()], &root, false).unwrap();
+        assert!(!Path::new(&entry.local).exists());
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn apply_keeps_files_modified_since_put() {
+        // Appended
+        let (root, entry) = put_file(b"line 1\n");
+        fs::write(&entry.local, b"line 1\nline 2\n").unwrap();
+        AfterUpload::Delete.apply(&[entry.clone()], &root, false).unwrap();
+        assert!(Path::new(&entry.local).exists());
+
+        // Rewritten in place with the same size
+        fs::write(&entry.local, b"line 9\n").unwrap();
+        AfterUpload::Delete.apply(&[entry.clone()], &root, false).unwrap();
+        assert_eq!(fs::read(&entry.local).unwrap(), b"line 9\n");
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
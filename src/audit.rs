@@ -0,0 +1,241 @@
+use azure_storage::blob::prelude::*;
+use azure_storage::core::prelude::*;
+
+use std::io::prelude::*;
+use std::io::BufReader;
+use std::fs::{File, OpenOptions};
+use std::env;
+use std::path::PathBuf;
+use std::error::Error;
+use std::fmt;
+use serde::Serialize;
+use chrono::Utc;
+use hmac::{Hmac, Mac, NewMac};
+use sha2::{Digest, Sha256};
+use anyhow::anyhow;
+use tokio::sync::Mutex;
+use crate::config::Configs;
+use crate::secret::Secret;
+use crate::tail;
+use crate::transport;
+use crate::upload;
+
+// Audit log of mutating operations.
+// Each record is a JSON line which contains the SHA-256 hash of the previous record in the local audit file,
+// or in the audit blob if no local file is configured, and is signed by HMAC-SHA256 with the audit key
+// if configured, so that modifications can be detected.
+// Records are written one at a time, since the hash of a record depends on the last one written.
+pub struct AuditLog {
+    file: Option<PathBuf>,
+    blob: Option<(String, String)>,
+    key: Secret,
+    user: String,
+    account: String,
+    client_request_id: String,
+    // Hash of the last record, found on the first record and kept, so that the log is not read on every record
+    last_hash: Mutex<Option<String>>,
+}
+
+#[derive(Serialize)]
+struct Record<'a> {
+    time: String,
+    user: &'a str,
+    account: &'a str,
+    operation: &'a str,
+    container: &'a str,
+    blob: &'a str,
+    request_id: String,
+    client_request_id: &'a str,
+    prev: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    sig: Option<String>,
+}
+
+impl AuditLog {
+    pub fn new(cfg: &Configs) -> Result<AuditLog, Box<dyn Error + Send + Sync>> {
+        // audit_blob is specified as "<container>/<blob>"
+        let blob = match cfg.audit_blob.as_str() {
+            "" => None,
+            v => {
+                let (container, blob) = v.split_once('/')
+                    .ok_or(anyhow!("audit_blob must be specified as <container>/<blob>"))?;
+                Some((container.to_string(), blob.to_string()))
+            }
+        };
+
+        Ok(AuditLog {
+            file: if cfg.audit_file.is_empty() { None } else { Some(PathBuf::from(&cfg.audit_file)) },
+            blob,
+            key: cfg.audit_key.clone(),
+            user: env::var("USER").or_else(|_| env::var("USERNAME")).unwrap_or_default(),
+            account: cfg.storage_account.clone(),
+            client_request_id: cfg.client_request_id.clone(),
+            last_hash: Mutex::new(None),
+        })
+    }
+
+    // Record a mutating operation to the audit file and the audit blob
+    pub async fn record(&self, storage_client: &StorageClient, operation: &str, container: &str, blob: &str,
+        request_id: &dyn fmt::Display) -> Result<(), Box<dyn Error + Send + Sync>>
+    {
+        if self.file.is_none() && self.blob.is_none() {
+            return Ok(());
+        }
+        let mut last_hash = self.last_hash.lock().await;
+        if last_hash.is_none() {
+            *last_hash = Some(self.find_last_hash(storage_client).await?);
+        }
+
+        let mut record = Record {
+            time: Utc::now().to_rfc3339(),
+            user: &self.user,
+            account: &self.account,
+            operation,
+            container,
+            blob,
+            request_id: request_id.to_string(),
+            client_request_id: &self.client_request_id,
+            prev: last_hash.clone().unwrap_or_default(),
+            sig: None,
+        };
+        if !self.key.is_empty() {
+            let mut mac = Hmac::<Sha256>::new_from_slice(self.key.expose().as_bytes())
+                .map_err(|_| anyhow!("Invalid audit key"))?;
+            mac.update(serde_json::to_string(&record)?.as_bytes());
+            record.sig = Some(base64::encode(mac.finalize().into_bytes()));
+        }
+        let line = serde_json::to_string(&record)?;
+        let hash = hash(line.as_bytes());
+        let line = line + "\n";
+
+        if let Some(path) = &self.file {
+            let mut options = OpenOptions::new();
+            options.append(true).create(true);
+            #[cfg(unix)]
+            std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+            options.open(path)?.write_all(line.as_bytes())?;
+            *last_hash = Some(hash.clone());
+        }
+
+        if let Some((container, blob)) = &self.blob {
+            let blob_client = storage_client
+                .as_container_client(container.as_str())
+                .as_blob_client(blob.as_str());
+
+            // Create the audit blob on the first record
+            match blob_client.append_block(line.clone().into_bytes()).execute().await {
+                Ok(_) => {},
+                Err(e) if transport::is_not_found(e.as_ref()) => {
+                    upload::create_append_blob(storage_client, container, blob, None).await?;
+                    blob_client.append_block(line.into_bytes()).execute().await?;
+                },
+                Err(e) => return Err(e)
+            }
+            *last_hash = Some(hash);
+        }
+        Ok(())
+    }
+
+    // SHA-256 hash of the last record in the local audit file, or at the end of the audit blob
+    // if no local file is configured. Empty if there is no record yet.
+    async fn find_last_hash(&self, storage_client: &StorageClient) -> Result<String, Box<dyn Error + Send + Sync>> {
+        if let Some(path) = &self.file {
+            if !path.exists() {
+                return Ok(String::new());
+            }
+            let mut last = None;
+            for line in BufReader::new(File::open(path)?).lines() {
+                last = Some(line?);
+            }
+            return Ok(last.map(|last| hash(last.as_bytes())).unwrap_or_default());
+        }
+
+        let (container, blob) = match &self.blob {
+            Some(blob) => blob,
+            None => return Ok(String::new())
+        };
+        let blob_client = storage_client.as_container_client(container.as_str()).as_blob_client(blob.as_str());
+        let size = match blob_client.get_properties().execute().await {
+            Ok(res) => res.blob.properties.content_length,
+            Err(e) if transport::is_not_found(e.as_ref()) => 0,
+            Err(e) => return Err(e)
+        };
+        if size == 0 {
+            return Ok(String::new());
+        }
+        Ok(hash(&tail::last_line(&blob_client, size).await?))
+    }
+}
+
+fn hash(record: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(record))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::sync::Arc;
+
+    const KEY: &str = "Eby8vdM02xNOcqFlqUwJPLlmEtlCDXJ1OUzFT50uSRZ6IFsuFq2UVErCz4I6tq/K1SZFPTOtr/KBHBeksoGMGw==";
+
+    // Storage client which is never used, since no audit blob is configured
+    fn storage_client() -> Arc<StorageClient> {
+        let cfg = Configs::default();
+        let http_client = transport::new_http_client(&cfg, "devstoreaccount1", &KEY.into()).unwrap();
+        StorageAccountClient::new_access_key(http_client, "devstoreaccount1", KEY).as_storage_client()
+    }
+
+    #[tokio::test]
+    async fn record_chains_hashes() {
+        let path = env::temp_dir().join(format!("azst-audit-{}.log", uuid::Uuid::new_v4()));
+        let cfg = Configs {
+            audit_file: path.to_string_lossy().into_owned(),
+            audit_key: "audit key".into(),
+            ..Default::default()
+        };
+        let audit = AuditLog::new(&cfg).unwrap();
+        let storage_client = storage_client();
+        audit.record(&storage_client, "put", "logs", "a.txt", &"request-1").await.unwrap();
+        audit.record(&storage_client, "delete", "logs", "a.txt", &"request-2").await.unwrap();
+
+        let content = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        let lines: Vec<_> = content.lines().collect();
+        assert_eq!(lines.len(), 2);
+        let first: serde_json::Value = serde_json::from_str(lines[0]).unwrap();
+        let second: serde_json::Value = serde_json::from_str(lines[1]).unwrap();
+        assert_eq!(first["prev"], "");
+        assert_eq!(first["operation"], "put");
+        assert_eq!(second["prev"], format!("{:x}", Sha256::digest(lines[0].as_bytes())));
+        assert_eq!(second["request_id"], "request-2");
+
+        // The signature is made over the line without the last field, sig
+        let unsigned = format!("{}}}", &lines[1][..lines[1].find(",\"sig\":").unwrap()]);
+        let mut mac = Hmac::<Sha256>::new_from_slice(b"audit key").unwrap();
+        mac.update(unsigned.as_bytes());
+        assert_eq!(second["sig"], base64::encode(mac.finalize().into_bytes()));
+    }
+
+    #[tokio::test]
+    async fn record_continues_chain_of_existing_file() {
+        let path = env::temp_dir().join(format!("azst-audit-{}.log", uuid::Uuid::new_v4()));
+        let cfg = Configs { audit_file: path.to_string_lossy().into_owned(), ..Default::default() };
+        let storage_client = storage_client();
+        AuditLog::new(&cfg).unwrap().record(&storage_client, "put", "logs", "a.txt", &"request-1").await.unwrap();
+
+        // Another run finds the last record in the file once, and chains the following records
+        let audit = AuditLog::new(&cfg).unwrap();
+        audit.record(&storage_client, "put", "logs", "b.txt", &"request-2").await.unwrap();
+        audit.record(&storage_client, "put", "logs", "c.txt", &"request-3").await.unwrap();
+
+        let content = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        let lines: Vec<_> = content.lines().collect();
+        assert_eq!(lines.len(), 3);
+        for pair in lines.windows(2) {
+            let record: serde_json::Value = serde_json::from_str(pair[1]).unwrap();
+            assert_eq!(record["prev"], hash(pair[0].as_bytes()));
+        }
+    }
+}
@@ -0,0 +1,71 @@
+use azure_storage::blob::prelude::*;
+use azure_storage::core::prelude::*;
+
+use std::error::Error;
+use std::time::Instant;
+use bytes::Bytes;
+use futures::stream::{self, StreamExt, TryStreamExt};
+use rand::RngCore;
+
+// Measure upload throughput for each combination of block sizes and concurrency levels,
+// by uploading random data as a scratch blob in the container, and print the best one.
+pub async fn bench(storage_client: &StorageClient, container: &str, size: u64, block_sizes: &[u64],
+    concurrency_levels: &[usize]) -> Result<(), Box<dyn Error + Send + Sync>>
+{
+    let mut data = vec![0u8; size as usize];
+    rand::thread_rng().fill_bytes(&mut data);
+    let data = Bytes::from(data);
+
+    let blob = format!("azure-storage-bench-{}", uuid::Uuid::new_v4());
+    let blob_client = storage_client
+        .as_container_client(container)
+        .as_blob_client(blob.as_str());
+
+    println!("Uploading {} bytes to '{}/{}'", size, container, blob);
+    println!(" {:>10} {:>11} {:>10}", "block size", "concurrency", "MB/s");
+
+    let mut best: Option<(u64, usize, f64)> = None;
+    for &block_size in block_sizes {
+        for &concurrency in concurrency_levels {
+            let start = Instant::now();
+            upload_blocks(&blob_client, &data, block_size, concurrency).await?;
+            let throughput = size as f64 / start.elapsed().as_secs_f64() / 1_000_000.0;
+            println!(" {:>10} {:>11} {:>10.2}", block_size, concurrency, throughput);
+
+            if best.map_or(true, |(_, _, best)| throughput > best) {
+                best = Some((block_size, concurrency, throughput));
+            }
+        }
+    }
+    blob_client.delete().execute().await?;
+
+    if let Some((block_size, concurrency, throughput)) = best {
+        println!("Best: block size {}, concurrency {} ({:.2} MB/s)", block_size, concurrency, throughput);
+    }
+    Ok(())
+}
+
+// Upload data by blocks of the size with the concurrency, and commit them
+async fn upload_blocks(blob_client: &BlobClient, data: &Bytes, block_size: u64, concurrency: usize)
+    -> Result<(), Box<dyn Error + Send + Sync>>
+{
+    let block_size = block_size.max(1) as usize;
+    let block_ids: Vec<BlockId> = (0..(data.len() + block_size - 1) / block_size)
+        .map(|i| BlockId::new(format!("{:08}", i).into_bytes()))
+        .collect();
+
+    stream::iter(block_ids.iter().enumerate())
+        .map(|(i, block_id)| {
+            let end = ((i + 1) * block_size).min(data.len());
+            blob_client.put_block(block_id.clone(), data.slice(i * block_size..end)).execute()
+        })
+        .buffer_unordered(concurrency.max(1))
+        .try_collect::<Vec<_>>()
+        .await?;
+
+    let block_list = BlockList {
+        blocks: block_ids.into_iter().map(BlobBlockType::new_uncommitted).collect()
+    };
+    blob_client.put_block_list(&block_list).execute().await?;
+    Ok(())
+}
@@ -0,0 +1,75 @@
+use azure_core::prelude::*;
+use azure_storage::blob::prelude::*;
+use azure_storage::core::prelude::*;
+
+use std::error::Error;
+use anyhow::anyhow;
+use rand::seq::index;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use crate::audit::AuditLog;
+
+// Suffix of the sidecar blob with the hashes of the blocks of a blob
+pub const SUFFIX: &str = ".blocks";
+
+// SHA-256 hashes of the blocks a blob is put by, recorded in the sidecar "<blob>.blocks",
+// so that a few blocks can be verified by ranged reads instead of getting the whole blob
+#[derive(Serialize, Deserialize, Default, Debug)]
+#[serde(default)]
+pub struct BlockHashes {
+    pub size: u64,
+    pub block_size: u64,
+    pub sha256: Vec<String>,
+}
+
+impl BlockHashes {
+    pub fn new(size: u64, block_size: u64) -> BlockHashes {
+        BlockHashes { size, block_size, sha256: Vec::new() }
+    }
+
+    pub fn add(&mut self, block: &[u8]) {
+        self.sha256.push(format!("{:x}", Sha256::digest(block)));
+    }
+
+    // Put the hashes as the sidecar of the blob, after the blob is committed
+    pub async fn put(&self, storage_client: &StorageClient, container: &str, blob: &str, audit: &AuditLog)
+        -> Result<(), Box<dyn Error + Send + Sync>>
+    {
+        let sidecar = format!("{}{}", blob, SUFFIX);
+        let res = storage_client
+            .as_container_client(container)
+            .as_blob_client(sidecar.as_str())
+            .put_block_blob(serde_json::to_vec(self)?)
+            .execute()
+            .await?;
+        audit.record(storage_client, "put", container, &sidecar, &res.request_id).await?;
+        Ok(())
+    }
+}
+
+// Check randomly sampled blocks of the blob by the hashes in its sidecar, by ranged reads of the blocks only.
+// Returns the indexes of the blocks which do not match.
+pub async fn spot_check(blob_client: &BlobClient, sidecar_client: &BlobClient, size: u64, samples: usize)
+    -> Result<Vec<usize>, Box<dyn Error + Send + Sync>>
+{
+    let data = sidecar_client.get().execute().await?.data;
+    let hashes: BlockHashes = serde_json::from_slice(&data)
+        .map_err(|e| anyhow!("Cannot parse block hashes: {}", e))?;
+    if hashes.size != size || hashes.block_size == 0 {
+        return Err(anyhow!("Block hashes are of {} bytes, but the blob is {} bytes", hashes.size, size).into());
+    }
+
+    let count = hashes.sha256.len();
+    let mut indexes = index::sample(&mut rand::thread_rng(), count, samples.min(count)).into_vec();
+    indexes.sort_unstable();
+    let mut mismatched = Vec::new();
+    for i in indexes {
+        let start = i as u64 * hashes.block_size;
+        let end = (start + hashes.block_size).min(size);
+        let block = blob_client.get().range(Range::new(start, end)).execute().await?.data;
+        if format!("{:x}", Sha256::digest(&block)) != hashes.sha256[i] {
+            mismatched.push(i);
+        }
+    }
+    Ok(mismatched)
+}
@@ -0,0 +1,135 @@
+use azure_core::prelude::*;
+use azure_storage::blob::prelude::*;
+use azure_storage::core::prelude::*;
+
+use std::collections::HashMap;
+use std::error::Error;
+use anyhow::anyhow;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use crate::audit::AuditLog;
+use crate::transport;
+use crate::upload;
+
+// Metadata of the head of the chain, and the length of the blob covered by it
+const CHAIN: &str = "azst_chain";
+const CHAIN_LENGTH: &str = "azst_chain_length";
+
+// Record of an append in "<blob>.chain"
+#[derive(Serialize, Deserialize, Debug)]
+struct Link {
+    offset: u64,
+    length: u64,
+    chain: String,
+}
+
+// Next head of the chain: SHA-256 of the previous head followed by the appended block
+fn next(prev: &str, data: &[u8]) -> String {
+    let mut sha256 = Sha256::new();
+    sha256.update(prev.as_bytes());
+    sha256.update(data);
+    format!("{:x}", sha256.finalize())
+}
+
+fn chain_blob(blob: &str) -> String {
+    format!("{}.chain", blob)
+}
+
+// Head of the chain and the length covered by it, from the metadata of the blob
+fn head(metadata: Option<&HashMap<String, String>>) -> (String, u64) {
+    let get = |name| metadata.and_then(|metadata| metadata.get(name)).cloned().unwrap_or_default();
+    (get(CHAIN), get(CHAIN_LENGTH).parse().unwrap_or(0))
+}
+
+// Append a block to the append blob, maintaining the rolling hash chain of appends.
+// Each append is recorded in "<blob>.chain" with the new head, and the head is kept in the metadata of the blob,
+// so that tampering or appends without the chain are detected by verify. Assumes a single writer per blob.
+pub async fn append(storage_client: &StorageClient, container: &str, blob: &str, data: Vec<u8>, audit: &AuditLog,
+    debug: bool) -> Result<(), Box<dyn Error + Send + Sync>>
+{
+    let container_client = storage_client.as_container_client(container);
+    let blob_client = container_client.as_blob_client(blob);
+    let properties = blob_client.get_properties().execute().await?.blob;
+    let (prev, chained) = head(properties.metadata.as_ref());
+    let offset = properties.properties.content_length;
+    if chained != offset {
+        return Err(anyhow!("Chain of '{}/{}' covers {} of {} bytes. Appended without the chain?",
+            container, blob, chained, offset).into());
+    }
+
+    let link = Link { offset, length: data.len() as u64, chain: next(&prev, &data) };
+    if debug {
+        println!("chain = {:?}", link);
+    }
+
+    let md5 = md5::compute(&data).into();
+    let res = blob_client.append_block(data).hash(&md5).execute().await?;
+    audit.record(storage_client, "append", container, blob, &res.request_id).await?;
+
+    // Record the link, creating the chain blob on the first append
+    let line = serde_json::to_string(&link)? + "\n";
+    let link_client = container_client.as_blob_client(chain_blob(blob));
+    if let Err(e) = link_client.append_block(line.clone().into_bytes()).execute().await {
+        if !transport::is_not_found(e.as_ref()) {
+            return Err(e);
+        }
+        upload::create_append_blob(storage_client, container, &chain_blob(blob), Some(audit)).await?;
+        link_client.append_block(line.into_bytes()).execute().await?;
+    }
+
+    // Move the head, keeping other metadata
+    let mut metadata = Metadata::new();
+    for (name, value) in properties.metadata.unwrap_or_default() {
+        metadata.insert(name, value);
+    }
+    metadata.insert(CHAIN, link.chain);
+    metadata.insert(CHAIN_LENGTH, (link.offset + link.length).to_string());
+    blob_client.set_metadata().metadata(&metadata).execute().await?;
+    Ok(())
+}
+
+// Verify the content of the append blob by the chain recorded in "<blob>.chain" and the head in the metadata.
+// Fails if any block is modified, or data is appended without the chain.
+pub async fn verify(storage_client: &StorageClient, container: &str, blob: &str, data: &[u8],
+    metadata: Option<&HashMap<String, String>>) -> Result<(), Box<dyn Error + Send + Sync>>
+{
+    let links = storage_client
+        .as_container_client(container)
+        .as_blob_client(chain_blob(blob))
+        .get()
+        .execute()
+        .await
+        .map_err(|e| anyhow!("Cannot get the chain of '{}/{}': {}", container, blob, e))?
+        .data;
+
+    let (head, chained) = head(metadata);
+    let mut prev = String::new();
+    let mut end = 0;
+    let mut head_found = chained == 0;
+    for line in String::from_utf8_lossy(&links).lines().filter(|line| !line.trim().is_empty()) {
+        let link: Link = serde_json::from_str(line).map_err(|e| anyhow!("Invalid chain record '{}': {}", line, e))?;
+        if link.offset != end {
+            return Err(anyhow!("Chain is broken at offset {}, expected {}", link.offset, end).into());
+        }
+        // Links beyond the data are appended after it is got
+        if link.offset + link.length > data.len() as u64 {
+            break;
+        }
+        let block = &data[link.offset as usize..(link.offset + link.length) as usize];
+        prev = next(&prev, block);
+        if prev != link.chain {
+            return Err(anyhow!("Block at offset {} ({} bytes) is modified", link.offset, link.length).into());
+        }
+        end = link.offset + link.length;
+        if end == chained {
+            head_found = prev == head;
+        }
+    }
+    if !head_found {
+        return Err(anyhow!("Chain does not reach the head in the metadata").into());
+    }
+    if end != data.len() as u64 {
+        return Err(anyhow!("{} bytes at offset {} are appended without the chain", data.len() as u64 - end, end).into());
+    }
+    Ok(())
+}
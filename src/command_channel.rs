@@ -0,0 +1,109 @@
+use azure_storage::blob::prelude::*;
+use azure_storage::core::prelude::*;
+
+use std::sync::Arc;
+use std::error::Error;
+use std::process::Stdio;
+use serde::{Deserialize, Serialize};
+use chrono::Utc;
+use tokio::process::Command;
+use tokio::time::{self, Duration};
+use crate::audit::AuditLog;
+use crate::reload::Reload;
+use crate::transport;
+
+// Result of a command, written to the response blob
+#[derive(Serialize, Deserialize, Default, Debug)]
+#[serde(default)]
+struct CommandResponse {
+    command_etag: String,
+    time: String,
+    exit_code: Option<i32>,
+    stdout: String,
+    stderr: String,
+}
+
+// Poll the command blob at the interval, and run it by the shell when it is updated.
+// The result is written to the response blob with the ETag of the command, so that
+// the same command is never run twice even after restart. Polls only once if the interval is 0.
+// The config is reloaded on SIGHUP before the next poll.
+pub async fn poll_commands(storage_client: &Arc<StorageClient>, container: &str, blob: &str, response_blob: &str,
+    interval: u64, reload: &mut Reload, audit: &AuditLog, debug: bool) -> Result<(), Box<dyn Error + Send + Sync>>
+{
+    let mut storage_client = storage_client.clone();
+    let mut command_client = storage_client.as_container_client(container).as_blob_client(blob);
+    let mut response_client = storage_client.as_container_client(container).as_blob_client(response_blob);
+
+    // ETag of the command handled last time
+    let mut last_etag = match response_client.get().execute().await {
+        Ok(res) => serde_json::from_slice::<CommandResponse>(&res.data).unwrap_or_default().command_etag,
+        Err(e) if transport::is_not_found(e.as_ref()) => String::new(),
+        Err(e) => return Err(e)
+    };
+
+    loop {
+        if let Some(reloaded) = reload.poll().await {
+            storage_client = reloaded;
+            command_client = storage_client.as_container_client(container).as_blob_client(blob);
+            response_client = storage_client.as_container_client(container).as_blob_client(response_blob);
+        }
+
+        let res = match command_client.get().execute().await {
+            Ok(res) => Some(res),
+            Err(e) if transport::is_not_found(e.as_ref()) => None,
+            Err(e) if interval > 0 => {
+                eprintln!("Cannot get command blob: {}", e);
+                None
+            },
+            Err(e) => return Err(e)
+        };
+
+        if let Some(res) = res.filter(|res| res.blob.properties.etag.to_string() != last_etag) {
+            let etag = res.blob.properties.etag.to_string();
+            if debug {
+                println!("command (etag {}) = {:?}", etag, String::from_utf8_lossy(&res.data));
+            }
+
+            let script = String::from_utf8_lossy(&res.data).into_owned();
+            let response = run_command(&script, &etag).await;
+            println!("Command {} exited with {:?}", etag, response.exit_code);
+
+            let res = response_client
+                .put_block_blob(serde_json::to_vec_pretty(&response)?)
+                .execute()
+                .await?;
+            audit.record(&storage_client, "poll-commands", container, response_blob, &res.request_id).await?;
+            last_etag = etag;
+        }
+
+        if interval == 0 {
+            return Ok(());
+        }
+        time::sleep(Duration::from_secs(interval)).await;
+    }
+}
+
+// Run a command script by the shell and collect the result
+async fn run_command(script: &str, etag: &str) -> CommandResponse {
+    let mut response = CommandResponse {
+        command_etag: etag.into(),
+        time: Utc::now().to_rfc3339(),
+        ..Default::default()
+    };
+
+    let output = Command::new("sh")
+        .arg("-c")
+        .arg(script)
+        .stdin(Stdio::null())
+        .output()
+        .await;
+    match output {
+        Ok(output) => {
+            response.exit_code = output.status.code();
+            response.stdout = String::from_utf8_lossy(&output.stdout).into_owned();
+            response.stderr = String::from_utf8_lossy(&output.stderr).into_owned();
+        },
+        Err(e) => response.stderr = format!("Cannot run command: {}", e)
+    }
+    response
+}
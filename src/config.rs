@@ -0,0 +1,876 @@
+use std::io::prelude::*;
+use std::collections::BTreeMap;
+use std::io;
+use std::fs::{self, OpenOptions};
+use std::env;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::error::Error;
+use serde::{Deserialize, Serialize};
+use anyhow::anyhow;
+use crate::secret::Secret;
+use azst::naming::{DefaultNaming, NamingPolicy};
+use azst::transform::{self, Pipeline};
+
+// Blob endpoint, account and key of the local storage emulator (Azurite), which are well-known
+const EMULATOR_ENDPOINT: &str = "http://127.0.0.1:10000/devstoreaccount1";
+const EMULATOR_ACCOUNT: &str = "devstoreaccount1";
+const EMULATOR_KEY: &str = "Eby8vdM02xNOcqFlqUwJPLlmEtlCDXJ1OUzFT50uSRZ6IFsuFq2UVErCz4I6tq/K1SZFPTOtr/KBHBeksoGMGw==";
+
+// Endpoint suffix of the public cloud, and of the national clouds by --cloud
+const PUBLIC_SUFFIX: &str = "core.windows.net";
+const CLOUD_SUFFIXES: &[(&str, &str)] = &[
+    ("public", PUBLIC_SUFFIX),
+    ("usgov", "core.usgovcloudapi.net"),
+    ("china", "core.chinacloudapi.cn"),
+    ("germany", "core.cloudapi.de"),
+];
+
+// Sections of config files grouping parameters, which are the same as the parameters of the same names at the top level
+const SECTIONS: &[(&str, &[&str])] = &[
+    ("auth", &["storage_account", "endpoint", "cloud", "endpoint_suffix", "blob_endpoint", "storage_master_key",
+        "sas_token", "connection_string", "auth", "tenant_id", "client_id", "client_secret", "credential_command",
+        "signing_command"]),
+    ("transfer", &["max_requests_per_second", "limit_rate", "retries", "retry_backoff", "retry_jitter",
+        "retry_status_codes", "start_jitter", "warmup", "low_memory", "ip_version", "tls_min_version", "ca_cert",
+        "insecure", "tls_client_cert", "tls_client_key", "resolve", "headers", "api_version", "block_size",
+        "concurrency"]),
+    ("defaults", &["local", "container", "device_id", "inbox", "outbox", "public_access", "hash_algorithm",
+        "hash_cache"]),
+];
+
+// System wide configuration directory, except on Windows
+const SYSTEM_CONFIG_DIR: &str = "/etc/azure-storage";
+
+// Name of the directory of this tool under the platform config directories
+const APP_DIR: &str = "azure-storage";
+
+// Prefix of environment variables of config parameters, such as AZST_CONTAINER for container
+const ENV_PREFIX: &str = "AZST_";
+
+// Names of config files in the config directories, in order of lookup.
+// The first one found is loaded. azure-storage.json is of older versions.
+const CONFIG_NAMES: &[&str] = &["config.json", "config.toml", "config.yaml", "config.yml", "azure-storage.json"];
+
+// A config parameter which a non-empty one of an upper layer overwrites
+trait Merge {
+    fn merge(&mut self, upper: Self);
+}
+
+impl Merge for String {
+    fn merge(&mut self, upper: Self) {
+        if !upper.is_empty() {
+            *self = upper;
+        }
+    }
+}
+
+impl Merge for Secret {
+    fn merge(&mut self, upper: Self) {
+        if !upper.is_empty() {
+            *self = upper;
+        }
+    }
+}
+
+impl<T> Merge for Option<T> {
+    fn merge(&mut self, upper: Self) {
+        if upper.is_some() {
+            *self = upper;
+        }
+    }
+}
+
+// Lists are replaced as a whole, not appended to
+impl<T> Merge for Vec<T> {
+    fn merge(&mut self, upper: Self) {
+        if !upper.is_empty() {
+            *self = upper;
+        }
+    }
+}
+
+// Profiles of the same name in upper layers replace whole profiles
+impl Merge for BTreeMap<String, Configs> {
+    fn merge(&mut self, upper: Self) {
+        self.extend(upper);
+    }
+}
+
+// Define the config parameters with merge() over all of them, so that a new parameter cannot be missed by merge()
+macro_rules! configs {
+    ($(#[$meta:meta])* pub struct $name:ident { $($(#[$field_meta:meta])* pub $field:ident: $ty:ty,)* }) => {
+        $(#[$meta])*
+        pub struct $name {
+            $($(#[$field_meta])* pub $field: $ty,)*
+        }
+
+        impl $name {
+            // Overwrite config parameters by non-empty parameters of an upper layer
+            pub fn merge(&mut self, upper: $name) {
+                $(Merge::merge(&mut self.$field, upper.$field);)*
+            }
+        }
+    };
+}
+
+configs! {
+    #[derive(Deserialize, Serialize, Default, Debug, Clone)]
+    #[serde(default)]
+    pub struct Configs {
+        pub storage_account: String,
+        pub endpoint: String,
+        pub cloud: String,
+        pub endpoint_suffix: String,
+        pub blob_endpoint: String,
+        pub storage_master_key: Secret,
+        pub sas_token: Secret,
+        pub connection_string: Secret,
+        pub auth: String,
+        pub tenant_id: String,
+        pub client_id: String,
+        pub client_secret: Secret,
+        pub credential_command: String,
+        pub signing_command: String,
+        pub local: String,
+        pub container: String,
+        pub device_id: String,
+        pub inbox: String,
+        pub outbox: String,
+        pub audit_file: String,
+        pub audit_blob: String,
+        pub audit_key: Secret,
+        pub max_requests_per_second: Option<f64>,
+        pub limit_rate: Option<u64>,
+        pub retries: Option<u32>,
+        pub retry_backoff: Option<f64>,
+        pub retry_jitter: Option<f64>,
+        pub retry_status_codes: Vec<u16>,
+        pub start_jitter: Option<u64>,
+        pub sd_notify: Option<bool>,
+        pub warmup: Option<bool>,
+        pub low_memory: Option<bool>,
+        pub ip_version: String,
+        pub tls_min_version: String,
+        pub ca_cert: String,
+        pub insecure: Option<bool>,
+        pub tls_client_cert: String,
+        pub tls_client_key: String,
+        pub resolve: Vec<String>,
+        pub headers: Vec<String>,
+        pub api_version: String,
+        pub block_size: Option<u64>,
+        pub concurrency: Option<usize>,
+        pub hash_cache: String,
+        pub hash_algorithm: String,
+        pub verify_key: String,
+        pub sign_key: String,
+        pub sign_command: String,
+        pub transforms: Vec<String>,
+        pub encryption_key: Secret,
+        pub redact_patterns: Vec<String>,
+        pub naming_plugin: String,
+        pub replicas: Vec<Replica>,
+        pub append_only_containers: Vec<String>,
+        pub quotas: Vec<String>,
+        pub quota_warn: Option<bool>,
+        pub quota_cache: String,
+        pub public_access: String,
+        pub profiles: BTreeMap<String, Configs>,
+        // ID of this invocation sent with all requests. Not read from config files.
+        #[serde(skip)]
+        pub client_request_id: String,
+        // Failures injected into requests for resilience testing. Not read from config files.
+        #[serde(skip)]
+        pub inject_faults: String,
+    }
+}
+
+// Another storage account and container files are put to as a replica with --replicate.
+// The container of the put is used if container is blank.
+#[derive(Deserialize, Serialize, Default, Debug, Clone)]
+#[serde(default)]
+pub struct Replica {
+    pub storage_account: String,
+    pub storage_master_key: Secret,
+    pub container: String,
+}
+
+impl Configs {
+    // Read config parameters from a file. Returns None if the file does not exist.
+    // A file which exists but cannot be read or parsed is an error, not silently ignored.
+    pub fn from_file(path: &Path) -> Result<Option<Configs>, Box<dyn Error + Send + Sync>> {
+        let mut value = match read_value(path)? {
+            Some(value) => value,
+            None => return Ok(None)
+        };
+        flatten_sections(&mut value).map_err(|e| anyhow!("Cannot parse config file {:?}: {}", path, e))?;
+        serde_json::from_value(value)
+            .map(Some)
+            .map_err(|e| anyhow!("Cannot parse config file {:?}: {}", path, e).into())
+    }
+
+    // Read config parameters from environment variables: AZST_<PARAMETER> of each parameter, such as AZST_CONTAINER,
+    // and the variables of Azure tools such as STORAGE_ACCOUNT, which AZST_* overwrite.
+    // Lists are comma separated or JSON arrays, and profiles and replicas are JSON.
+    pub fn from_env() -> Result<Configs, Box<dyn Error + Send + Sync>> {
+        let mut cfg = Configs {
+            storage_account: env::var("STORAGE_ACCOUNT").unwrap_or_default(),
+            storage_master_key: env::var("STORAGE_MASTER_KEY").unwrap_or_default().into(),
+            sas_token: env::var("AZURE_STORAGE_SAS_TOKEN").unwrap_or_default().into(),
+            connection_string: env::var("AZURE_STORAGE_CONNECTION_STRING").unwrap_or_default().into(),
+            tenant_id: env::var("AZURE_TENANT_ID").unwrap_or_default(),
+            client_id: env::var("AZURE_CLIENT_ID").unwrap_or_default(),
+            client_secret: env::var("AZURE_CLIENT_SECRET").unwrap_or_default().into(),
+            ..Default::default()
+        };
+
+        let mut params = serde_json::Map::new();
+        if let serde_json::Value::Object(defaults) = serde_json::to_value(Configs::default())? {
+            for (key, default) in defaults {
+                let name = format!("{}{}", ENV_PREFIX, key.to_uppercase());
+                if let Ok(value) = env::var(&name) {
+                    let value = env_value(&key, &value, &default).map_err(|e| anyhow!("Invalid {}: {}", name, e))?;
+                    params.insert(key, value);
+                }
+            }
+        }
+        cfg.merge(serde_json::from_value(serde_json::Value::Object(params))?);
+        Ok(cfg)
+    }
+
+    // URL of the blob endpoint of the account if not the default one of the public cloud: the blob endpoint
+    // set explicitly, such as a custom domain or a private link, or the one in a national cloud or with the endpoint suffix.
+    // Requests are signed by the path, which has no room for a prefix before the container.
+    pub fn blob_endpoint_url(&self) -> Result<Option<String>, Box<dyn Error + Send + Sync>> {
+        if !self.blob_endpoint.is_empty() {
+            let url = url::Url::parse(&self.blob_endpoint)
+                .map_err(|e| anyhow!("Invalid blob endpoint '{}': {}", self.blob_endpoint, e))?;
+            if !matches!(url.scheme(), "https" | "http") || url.host_str().is_none() {
+                return Err(anyhow!("Invalid blob endpoint '{}'. Specify as https://<host>", self.blob_endpoint).into());
+            }
+            if url.path() != "/" || url.query().is_some() {
+                return Err(anyhow!("Blob endpoint '{}' must not have a path or a query", self.blob_endpoint).into());
+            }
+            return Ok(Some(self.blob_endpoint.trim_end_matches('/').to_string()));
+        }
+
+        let suffix = match self.endpoint_suffix.as_str() {
+            "" => CLOUD_SUFFIXES.iter()
+                .find(|(cloud, _)| *cloud == self.cloud || (self.cloud.is_empty() && *cloud == "public"))
+                .map(|(_, suffix)| *suffix)
+                .ok_or(anyhow!("Unknown cloud '{}'. Specify public, usgov, china or germany", self.cloud))?,
+            suffix => suffix.trim_start_matches('.')
+        };
+        match suffix {
+            PUBLIC_SUFFIX => Ok(None),
+            suffix => Ok(Some(format!("https://{}.blob.{}", self.storage_account, suffix)))
+        }
+    }
+
+    // Apply the account, key, SAS token and endpoint in the connection string, without overriding parameters set
+    // by other ways. "UseDevelopmentStorage=true" is the local storage emulator, and so is BlobEndpoint
+    // with the account in the path.
+    // With the endpoint of the emulator, the account is taken from the path of the endpoint,
+    // and the well-known key of the emulator is used for its account.
+    fn resolve_endpoint(&mut self) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let connection_string = self.connection_string.expose().to_string();
+        for pair in connection_string.split(';').map(str::trim).filter(|v| !v.is_empty()) {
+            let (name, value) = pair.split_once('=')
+                .ok_or(anyhow!("Invalid connection string. Specify as <name>=<value>;..."))?;
+            match name {
+                "UseDevelopmentStorage" if value.eq_ignore_ascii_case("true") && self.endpoint.is_empty() =>
+                    self.endpoint = EMULATOR_ENDPOINT.into(),
+                "AccountName" if self.storage_account.is_empty() => self.storage_account = value.into(),
+                "AccountKey" if self.storage_master_key.is_empty() => self.storage_master_key = value.into(),
+                "SharedAccessSignature" if self.sas_token.is_empty() => self.sas_token = value.into(),
+                "EndpointSuffix" if self.endpoint_suffix.is_empty() => self.endpoint_suffix = value.into(),
+                "BlobEndpoint" if self.endpoint.is_empty() && self.blob_endpoint.is_empty() => {
+                    match url::Url::parse(value).map(|url| url.path().trim_matches('/').is_empty()) {
+                        Ok(false) => self.endpoint = value.into(),
+                        _ => self.blob_endpoint = value.into()
+                    }
+                },
+                _ => {}
+            }
+        }
+
+        if self.endpoint.is_empty() {
+            return Ok(());
+        }
+        let url = url::Url::parse(&self.endpoint).map_err(|e| anyhow!("Invalid endpoint '{}': {}", self.endpoint, e))?;
+        if self.storage_account.is_empty() {
+            self.storage_account = url.path_segments()
+                .and_then(|mut segments| segments.find(|v| !v.is_empty()))
+                .unwrap_or_default()
+                .to_string();
+        }
+        if self.storage_account == EMULATOR_ACCOUNT && self.storage_master_key.is_empty() && self.sas_token.is_empty() {
+            self.storage_master_key = EMULATOR_KEY.into();
+        }
+        Ok(())
+    }
+
+    // Pipeline of the transforms applied to blob content on put and reversed on get
+    pub fn transform_pipeline(&self) -> Result<Pipeline, Box<dyn Error + Send + Sync>> {
+        let mut pipeline = Pipeline::new();
+        for name in self.transforms.iter() {
+            match name.as_str() {
+                "gzip" => pipeline.push(Box::new(transform::Gzip)),
+                "encrypt" => {
+                    let key = base64::decode(self.encryption_key.expose())
+                        .map_err(|_| anyhow!("encryption_key is not base64"))?;
+                    pipeline.push(Box::new(transform::Encrypt::new(&key)?));
+                },
+                "redact" => pipeline.push(Box::new(transform::Redact::new(&self.redact_patterns)?)),
+                #[cfg(feature = "wasm")]
+                v if v.starts_with("wasm:") =>
+                    pipeline.push(Box::new(azst::wasm::WasmPlugin::from_file(Path::new(&v["wasm:".len()..]))?)),
+                #[cfg(not(feature = "wasm"))]
+                v if v.starts_with("wasm:") => return Err(wasm_disabled(v)),
+                _ => return Err(anyhow!("Unknown transform '{}'", name).into())
+            }
+        }
+        Ok(pipeline)
+    }
+
+    // Naming policy of blobs uploaded from a directory
+    pub fn naming_policy(&self) -> Result<Box<dyn NamingPolicy>, Box<dyn Error + Send + Sync>> {
+        match self.naming_plugin.as_str() {
+            "" => Ok(Box::new(DefaultNaming)),
+            #[cfg(feature = "wasm")]
+            path => Ok(Box::new(azst::wasm::WasmPlugin::from_file(Path::new(path))?)),
+            #[cfg(not(feature = "wasm"))]
+            path => Err(wasm_disabled(path))
+        }
+    }
+
+    // Get the master key from stdout of the credential command, if no master key or SAS token is set.
+    // Not needed by AAD or Azure CLI authentication.
+    pub fn resolve_credentials(&mut self) -> Result<(), Box<dyn Error + Send + Sync>> {
+        self.resolve_endpoint()?;
+        if self.auth == "aad" || self.auth == "azure-cli" {
+            return Ok(());
+        }
+        if self.storage_master_key.is_empty() && self.sas_token.is_empty() && !self.credential_command.is_empty() {
+            self.storage_master_key = run_command(&self.credential_command, None)
+                .map_err(|e| anyhow!("credential_command: {}", e))?
+                .into();
+        }
+        Ok(())
+    }
+}
+
+// WASM plugins are available only if built with the wasm feature
+#[cfg(not(feature = "wasm"))]
+fn wasm_disabled(plugin: &str) -> Box<dyn Error + Send + Sync> {
+    anyhow!("Cannot load WASM plugin '{}'. Build with --features wasm", plugin).into()
+}
+
+// Config files in order of precedence from lowest to highest.
+// The file specified by --config (current directory by default) has the highest precedence.
+pub fn config_files(config: &str) -> Vec<PathBuf> {
+    let mut files: Vec<PathBuf> = system_config_dir().into_iter().chain(user_config_dir()).map(find_config).collect();
+    files.push(PathBuf::from(config));
+    files
+}
+
+// Per-user config file, which is the default destination of config-init
+pub fn user_config_file() -> Option<PathBuf> {
+    user_config_dir().map(find_config)
+}
+
+// Per-user config directory: $XDG_CONFIG_HOME/azure-storage, %APPDATA%\azure-storage on Windows,
+// or ~/.config/azure-storage
+fn user_config_dir() -> Option<PathBuf> {
+    // Relative paths in XDG_CONFIG_HOME are invalid by the XDG Base Directory Specification
+    if let Some(dir) = env::var_os("XDG_CONFIG_HOME").map(PathBuf::from).filter(|dir| dir.is_absolute()) {
+        return Some(dir.join(APP_DIR));
+    }
+    if let Some(dir) = env::var_os("APPDATA").filter(|_| cfg!(windows)) {
+        return Some(PathBuf::from(dir).join(APP_DIR));
+    }
+    env::var_os("HOME").map(|home| PathBuf::from(home).join(".config").join(APP_DIR))
+}
+
+// System wide config directory: %PROGRAMDATA%\azure-storage on Windows, or /etc/azure-storage
+fn system_config_dir() -> Option<PathBuf> {
+    match cfg!(windows) {
+        true => env::var_os("PROGRAMDATA").map(|dir| PathBuf::from(dir).join(APP_DIR)),
+        false => Some(PathBuf::from(SYSTEM_CONFIG_DIR))
+    }
+}
+
+// Config file in the directory by the first name found, or config.json if none is found
+fn find_config(dir: PathBuf) -> PathBuf {
+    CONFIG_NAMES.iter()
+        .map(|name| dir.join(name))
+        .find(|path| path.exists())
+        .unwrap_or_else(|| dir.join(CONFIG_NAMES[0]))
+}
+
+// Load all config layers and merge them, the profile if selected, and then environment variables.
+// Command line options > Environment variables > Profile > Config file > User config file > System config file
+pub fn load(config: &str, profile: Option<&str>, debug: bool) -> Result<Configs, Box<dyn Error + Send + Sync>> {
+    let mut cfg = Configs::default();
+    for path in config_files(config) {
+        if let Some(layer) = Configs::from_file(&path)? {
+            if debug {
+                println!("config loaded from {:?}", path);
+            }
+            cfg.merge(layer);
+        }
+    }
+
+    // Profiles are selected also from AZST_PROFILES
+    let mut env = Configs::from_env()?;
+    cfg.profiles.extend(std::mem::take(&mut env.profiles));
+
+    let mut profiles = std::mem::take(&mut cfg.profiles);
+    if let Some(profile) = profile {
+        let layer = profiles.remove(profile).ok_or_else(|| match profiles.is_empty() {
+            true => anyhow!("Profile '{}' is not found. No profiles are configured", profile),
+            false => anyhow!("Profile '{}' is not found. Valid profiles are: {}", profile,
+                profiles.keys().cloned().collect::<Vec<_>>().join(", "))
+        })?;
+        if debug {
+            println!("profile {} loaded", profile);
+        }
+        cfg.merge(layer);
+        cfg.profiles.clear();
+    }
+    cfg.merge(env);
+    Ok(cfg)
+}
+
+// Read a config file as a JSON value. The format is TOML or YAML by the extension (.toml, .yaml or .yml),
+// or JSON otherwise. Returns None if the file does not exist.
+fn read_value(path: &Path) -> Result<Option<serde_json::Value>, Box<dyn Error + Send + Sync>> {
+    let text = match fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(anyhow!("Cannot open config file {:?}: {}", path, e).into())
+    };
+    let value = match path.extension().and_then(|v| v.to_str()) {
+        Some("toml") => toml::from_str(&text).map_err(|e| anyhow!("Cannot parse config file {:?}: {}", path, e))?,
+        Some("yaml" | "yml") => serde_yaml::from_str(&text)
+            .map_err(|e| anyhow!("Cannot parse config file {:?}: {}", path, e))?,
+        _ => serde_json::from_str(&text).map_err(|e| anyhow!("Cannot parse config file {:?}: {}", path, e))?
+    };
+    Ok(Some(value))
+}
+
+// Value of a config parameter from an environment variable, typed as the default value of the parameter.
+// Items of comma separated lists are tried as strings first, and then as numbers.
+fn env_value(key: &str, value: &str, default: &serde_json::Value) -> Result<serde_json::Value, String> {
+    use serde_json::Value;
+    let parse = |v: &str| serde_json::from_str(v).unwrap_or_else(|_| Value::String(v.into()));
+    let candidates = match default {
+        Value::String(_) => vec![Value::String(value.into())],
+        Value::Array(_) if !value.trim_start().starts_with('[') => {
+            let items: Vec<&str> = value.split(',').map(str::trim).filter(|v| !v.is_empty()).collect();
+            vec![Value::Array(items.iter().map(|v| Value::String(v.to_string())).collect()),
+                Value::Array(items.iter().map(|v| parse(v)).collect())]
+        },
+        _ => vec![parse(value)]
+    };
+
+    let mut error = String::new();
+    for candidate in candidates {
+        let mut param = serde_json::Map::new();
+        param.insert(key.into(), candidate.clone());
+        match serde_json::from_value::<Configs>(Value::Object(param)) {
+            Ok(_) => return Ok(candidate),
+            Err(e) => error = e.to_string()
+        }
+    }
+    Err(error)
+}
+
+// Move the parameters in the sections to the top level, also in profiles. "auth" is a section
+// if it is a table, or the authentication parameter otherwise.
+fn flatten_sections(value: &mut serde_json::Value) -> Result<(), String> {
+    let object = match value.as_object_mut() {
+        Some(object) => object,
+        None => return Ok(())
+    };
+    for (section, fields) in SECTIONS.iter() {
+        let params = match object.get(*section) {
+            Some(serde_json::Value::Object(params)) => params.clone(),
+            _ => continue
+        };
+        object.remove(*section);
+        for (key, param) in params {
+            if !fields.contains(&key.as_str()) {
+                return Err(format!("unknown field '{}' in section '{}'. Valid fields are: {}", key, section,
+                    fields.join(", ")));
+            }
+            if object.contains_key(&key) {
+                return Err(format!("'{}' is set both in section '{}' and at the top level", key, section));
+            }
+            object.insert(key, param);
+        }
+    }
+    if let Some(serde_json::Value::Object(profiles)) = object.get_mut("profiles") {
+        for profile in profiles.values_mut() {
+            flatten_sections(profile)?;
+        }
+    }
+    Ok(())
+}
+
+// Check a config file for syntax errors, unknown fields and invalid values.
+// Returns the list of problems. A config file which does not exist has no problem.
+pub fn validate_file(path: &Path) -> Vec<String> {
+    let mut value = match read_value(path) {
+        Ok(Some(value)) => value,
+        Ok(None) => return vec![],
+        Err(e) => return vec![e.to_string()]
+    };
+    if let Err(e) = flatten_sections(&mut value) {
+        return vec![format!("{}: {}", path.display(), e)];
+    }
+
+    let mut problems = Vec::new();
+    let fields = known_fields();
+    if let Some(object) = value.as_object() {
+        for key in object.keys().filter(|key| !key.starts_with('_') && !fields.contains(key)) {
+            problems.push(format!("{}: unknown field '{}'. Valid fields are: {}", path.display(), key, fields.join(", ")));
+        }
+    }
+    if let Err(e) = serde_json::from_value::<Configs>(value) {
+        problems.push(format!("{}: {}", path.display(), e));
+    }
+    problems
+}
+
+// Run a helper command by the shell and take its stdout, such as a credential or a signature.
+// The input is given to stdin if any. Otherwise stdin and stderr are left to the terminal,
+// so that the command can prompt the user.
+pub fn run_command(command: &str, input: Option<&[u8]>) -> Result<String, Box<dyn Error + Send + Sync>> {
+    #[cfg(unix)]
+    let mut shell = Command::new("sh");
+    #[cfg(unix)]
+    shell.arg("-c");
+    #[cfg(windows)]
+    let mut shell = Command::new("cmd");
+    #[cfg(windows)]
+    shell.arg("/C");
+
+    let mut child = shell.arg(command)
+        .stdin(if input.is_some() { Stdio::piped() } else { Stdio::inherit() })
+        .stdout(Stdio::piped())
+        .stderr(Stdio::inherit())
+        .spawn()
+        .map_err(|e| anyhow!("Cannot run command: {}", e))?;
+    if let (Some(input), Some(mut stdin)) = (input, child.stdin.take()) {
+        stdin.write_all(input)?;
+    }
+
+    let output = child.wait_with_output()?;
+    if !output.status.success() {
+        return Err(anyhow!("Command failed with {}", output.status).into());
+    }
+
+    let stdout = String::from_utf8(output.stdout)
+        .map_err(|_| anyhow!("Command output is not UTF-8"))?;
+    let stdout = stdout.trim();
+    if stdout.is_empty() {
+        return Err(anyhow!("Command output is empty").into());
+    }
+    Ok(stdout.to_string())
+}
+
+// Names of the config parameters, taken from the serialized default config
+fn known_fields() -> Vec<String> {
+    match serde_json::to_value(Configs::default()) {
+        Ok(serde_json::Value::Object(object)) => object.keys().cloned().collect(),
+        _ => vec![]
+    }
+}
+
+// Print problems found by validation, and fail if any
+pub fn report(problems: &[String]) -> Result<(), Box<dyn Error + Send + Sync>> {
+    if problems.is_empty() {
+        println!("Config is valid");
+        return Ok(());
+    }
+    for problem in problems {
+        println!(" {}", problem);
+    }
+    Err(anyhow!("{} problems found in config", problems.len()).into())
+}
+
+// Descriptions of the config parameters written to the config file template.
+// JSON has no comments, so they are written as "_comment" which is ignored on load.
+const TEMPLATE_COMMENT: &[&str] = &[
+    "storage_account: Storage account name. STORAGE_ACCOUNT environment variable is used if blank.",
+    "endpoint: Blob endpoint URL of the storage emulator (Azurite) with the account in the path, such as http://127.0.0.1:10000/devstoreaccount1. Not used if blank.",
+    "cloud: Azure cloud of the account, public, usgov, china or germany. public if blank.",
+    "endpoint_suffix: Endpoint suffix of the account, such as core.usgovcloudapi.net, instead of the one of the cloud. Not used if blank.",
+    "blob_endpoint: Blob endpoint URL, such as https://storage.example.com for a custom domain or a private link, instead of the one of the account. Not used if blank.",
+    "storage_master_key: Storage master key. STORAGE_MASTER_KEY environment variable is used if blank.",
+    "sas_token: SAS token used instead of the master key if set. AZURE_STORAGE_SAS_TOKEN environment variable is used if blank.",
+    "connection_string: Connection string, such as UseDevelopmentStorage=true for Azurite. AZURE_STORAGE_CONNECTION_STRING environment variable is used if blank.",
+    "auth: Authentication, key (master key, SAS token or signing command), aad (Azure AD service principal) or azure-cli (user logged in by Azure CLI). key if blank.",
+    "tenant_id: Azure AD tenant ID of the service principal. AZURE_TENANT_ID environment variable is used if blank.",
+    "client_id: Client ID of the service principal. AZURE_CLIENT_ID environment variable is used if blank.",
+    "client_secret: Client secret of the service principal. AZURE_CLIENT_SECRET environment variable is used if blank.",
+    "credential_command: Command which prints the storage master key, used if storage_master_key is blank.",
+    "signing_command: Command which signs SAS tokens with the master key kept outside, used if no master key is available.",
+    "local: Default local file or directory path to put or get.",
+    "container: Container used if --container is not specified.",
+    "device_id: ID of this device in the inbox and outbox prefixes.",
+    "inbox: Prefix of the inbox of this device, with {device_id} replaced. devices/{device_id}/inbox/ if blank.",
+    "outbox: Prefix of the outbox of this device, with {device_id} replaced. devices/{device_id}/outbox/ if blank.",
+    "audit_file: Local file to record mutating operations. Not recorded if blank.",
+    "audit_blob: Append blob to record mutating operations, as <container>/<blob>. Not recorded if blank.",
+    "audit_key: Key to sign audit records by HMAC-SHA256. Not signed if blank.",
+    "max_requests_per_second: Maximum number of requests per second. Not limited if null.",
+    "limit_rate: Maximum throughput of uploads and downloads in bytes per second. Not limited if null.",
+    "retries: Maximum number of retries of a request failed transiently. 3 if null, not retried if 0.",
+    "retry_backoff: Delay before the first retry in seconds, doubled on each retry. 1 if null.",
+    "retry_jitter: Ratio (0 to 1) of the delay randomly reduced, to spread retries of devices. 0.5 if null.",
+    "retry_status_codes: List of HTTP status codes retried. 408, 429, 500, 502, 503 and 504 if empty.",
+    "start_jitter: Wait for a random time up to the seconds before starting. No wait if null.",
+    "sd_notify: Notify readiness and watchdog to systemd if true.",
+    "warmup: Resolve DNS once and connect to the storage before starting operations if true.",
+    "low_memory: Transfer by small blocks one by one regardless of other settings if true, for devices with little memory.",
+    "ip_version: IP version to connect by, 4, 6 or auto (race IPv6 and IPv4). auto if blank.",
+    "tls_min_version: Minimum TLS version to connect by, 1.2 or 1.3. TLS below 1.2 is never used. 1.2 if blank.",
+    "ca_cert: PEM file of root CA certificates trusted in addition to the built-in ones, such as of a TLS-intercepting proxy. Not used if blank.",
+    "insecure: Skip verification of server certificates if true. Only for troubleshooting, since connections can be intercepted.",
+    "tls_client_cert: PEM file of the client certificate (and its chain) presented to gateways which require mutual TLS. Not used if blank.",
+    "tls_client_key: PEM file of the private key of the client certificate, if not in tls_client_cert.",
+    "resolve: List of <host>:<ip> to connect to the host by the IP address instead of DNS. IPv6 may be in brackets.",
+    "headers: List of <name>: <value> headers added to all requests.",
+    "api_version: Service version (x-ms-version) of requests, as YYYY-MM-DD. The version of the SDK if blank.",
+    "block_size: Size of blocks to put, or ranges to get, files larger than it by in bytes. Depends on the account type if null.",
+    "concurrency: Maximum number of concurrent requests. Depends on the account type if null.",
+    "hash_cache: File to cache hashes of local files across runs, to skip hashing unchanged files. Not cached if blank.",
+    "hash_algorithm: Hash algorithm to compare local files with blobs, md5, sha256 or blake3. md5 if blank.",
+    "verify_key: Minisign public key to verify signatures of downloaded blobs.",
+    "sign_key: Minisign secret key file (without password) to sign uploaded blobs.",
+    "sign_command: Command which prints the minisign signature of stdin, used instead of sign_key.",
+    "transforms: List of transforms applied to blob content on put and reversed on get, in order. gzip, encrypt, redact or wasm:<path>.",
+    "encryption_key: Base64 encoded 32 bytes key of the encrypt transform.",
+    "redact_patterns: List of regular expressions replaced by [REDACTED] by the redact transform.",
+    "naming_plugin: WASM module which names blobs uploaded from a directory. <prefix><relative path> if blank.",
+    "replicas: List of {storage_account, storage_master_key, container} files are also put to with --replicate.",
+    "append_only_containers: List of containers where deletes, puts over blobs, copies and page writes are refused. Appends are allowed.",
+    "quotas: List of <container>:<size> of the maximum total size of blobs in the container checked before uploads, such as logs:10G.",
+    "quota_warn: Only warn instead of refusing uploads which would exceed the quota if true.",
+    "quota_cache: File to cache the usage of containers with quotas for an hour. Containers are listed on every upload if blank.",
+    "public_access: Public access level of containers created by --create-container-if-missing, none, blob or container. none if blank.",
+    "profiles: Named sets of config parameters selected by --profile, such as {\"prod\": {\"storage_account\": \"...\"}}, which overwrite parameters of config files.",
+];
+
+// Write a config file template filled with the config parameters.
+// The file is readable only by the owner since it may contain the master key.
+pub fn write_template(path: &Path, cfg: &Configs) -> Result<(), Box<dyn Error + Send + Sync>> {
+    if path.exists() {
+        return Err(anyhow!("Config file {:?} already exists", path).into());
+    }
+    if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        fs::create_dir_all(dir)?;
+    }
+
+    let template = serde_json::json!({
+        "_comment": TEMPLATE_COMMENT,
+        "storage_account": cfg.storage_account,
+        "endpoint": cfg.endpoint,
+        "cloud": cfg.cloud,
+        "endpoint_suffix": cfg.endpoint_suffix,
+        "blob_endpoint": cfg.blob_endpoint,
+        "storage_master_key": cfg.storage_master_key.expose(),
+        "sas_token": cfg.sas_token.expose(),
+        "connection_string": cfg.connection_string.expose(),
+        "auth": cfg.auth,
+        "tenant_id": cfg.tenant_id,
+        "client_id": cfg.client_id,
+        "client_secret": cfg.client_secret.expose(),
+        "credential_command": cfg.credential_command,
+        "signing_command": cfg.signing_command,
+        "local": cfg.local,
+        "container": cfg.container,
+        "device_id": cfg.device_id,
+        "inbox": cfg.inbox,
+        "outbox": cfg.outbox,
+        "audit_file": cfg.audit_file,
+        "audit_blob": cfg.audit_blob,
+        "audit_key": cfg.audit_key.expose(),
+        "max_requests_per_second": cfg.max_requests_per_second,
+        "limit_rate": cfg.limit_rate,
+        "retries": cfg.retries,
+        "retry_backoff": cfg.retry_backoff,
+        "retry_jitter": cfg.retry_jitter,
+        "retry_status_codes": cfg.retry_status_codes,
+        "start_jitter": cfg.start_jitter,
+        "sd_notify": cfg.sd_notify,
+        "warmup": cfg.warmup,
+        "low_memory": cfg.low_memory,
+        "ip_version": cfg.ip_version,
+        "tls_min_version": cfg.tls_min_version,
+        "ca_cert": cfg.ca_cert,
+        "insecure": cfg.insecure,
+        "tls_client_cert": cfg.tls_client_cert,
+        "tls_client_key": cfg.tls_client_key,
+        "resolve": cfg.resolve,
+        "headers": cfg.headers,
+        "api_version": cfg.api_version,
+        "block_size": cfg.block_size,
+        "concurrency": cfg.concurrency,
+        "hash_cache": cfg.hash_cache,
+        "hash_algorithm": cfg.hash_algorithm,
+        "verify_key": cfg.verify_key,
+        "sign_key": cfg.sign_key,
+        "sign_command": cfg.sign_command,
+        "transforms": cfg.transforms,
+        "encryption_key": cfg.encryption_key.expose(),
+        "redact_patterns": cfg.redact_patterns,
+        "naming_plugin": cfg.naming_plugin,
+        "replicas": cfg.replicas.iter()
+            .map(|replica| serde_json::json!({
+                "storage_account": replica.storage_account,
+                "storage_master_key": replica.storage_master_key.expose(),
+                "container": replica.container,
+            }))
+            .collect::<Vec<_>>(),
+        "append_only_containers": cfg.append_only_containers,
+        "quotas": cfg.quotas,
+        "quota_warn": cfg.quota_warn,
+        "quota_cache": cfg.quota_cache,
+        "public_access": cfg.public_access,
+        "profiles": {},
+    });
+
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let mut file = options.open(path)?;
+    serde_json::to_writer_pretty(&mut file, &template)?;
+    writeln!(file)?;
+    Ok(())
+}
+
+// Ask config parameters on the terminal. The credentials asked are only the ones the authentication method needs.
+pub fn prompt() -> Result<Configs, Box<dyn Error + Send + Sync>> {
+    let mut cfg = Configs {
+        storage_account: prompt_line("Storage account")?,
+        ..Default::default()
+    };
+
+    let method = loop {
+        match prompt_line("Authentication method (key, sas, aad or azure-cli) [key]")?.as_str() {
+            "" => break "key".to_string(),
+            v @ ("key" | "sas" | "aad" | "azure-cli") => break v.to_string(),
+            v => eprintln!("Invalid authentication method '{}'", v)
+        }
+    };
+    match method.as_str() {
+        "key" => cfg.storage_master_key = prompt_secret("Storage master key")?,
+        "sas" => cfg.sas_token = prompt_secret("SAS token")?,
+        "aad" => {
+            cfg.auth = method;
+            cfg.tenant_id = prompt_line("Tenant ID")?;
+            cfg.client_id = prompt_line("Client ID")?;
+            cfg.client_secret = prompt_secret("Client secret")?;
+        },
+        // Logged in by az login
+        _ => cfg.auth = method
+    }
+
+    cfg.local = prompt_line("Default local path (optional)")?;
+    Ok(cfg)
+}
+
+fn prompt_line(message: &str) -> Result<String, Box<dyn Error + Send + Sync>> {
+    print!("{}: ", message);
+    io::stdout().flush()?;
+    let mut line = String::new();
+    io::stdin().read_line(&mut line)?;
+    Ok(line.trim().to_string())
+}
+
+// Read a secret without echoing it to the terminal, so that it is not left on the screen or in recordings
+fn prompt_secret(message: &str) -> Result<Secret, Box<dyn Error + Send + Sync>> {
+    let line = rpassword::prompt_password_stdout(&format!("{}: ", message))?;
+    Ok(line.trim().into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn merge_overwrites_by_non_empty_parameters() {
+        let mut cfg = Configs {
+            storage_account: "lower".into(),
+            container: "logs".into(),
+            retries: Some(3),
+            headers: vec!["x-a: 1".into()],
+            ..Default::default()
+        };
+        cfg.merge(Configs {
+            storage_account: "upper".into(),
+            storage_master_key: "key".into(),
+            retries: Some(5),
+            ..Default::default()
+        });
+        assert_eq!(cfg.storage_account, "upper");
+        assert_eq!(cfg.storage_master_key.expose(), "key");
+        assert_eq!(cfg.container, "logs");
+        assert_eq!(cfg.retries, Some(5));
+        assert_eq!(cfg.headers, vec!["x-a: 1".to_string()]);
+    }
+
+    #[test]
+    fn merge_replaces_whole_profiles() {
+        let profile = |account: &str, container: &str| Configs {
+            storage_account: account.into(),
+            container: container.into(),
+            ..Default::default()
+        };
+        let mut cfg = Configs::default();
+        cfg.profiles.insert("dev".into(), profile("devaccount", "test"));
+        cfg.profiles.insert("prod".into(), profile("prodaccount", "backup"));
+        let mut upper = Configs::default();
+        upper.profiles.insert("dev".into(), profile("otheraccount", ""));
+        cfg.merge(upper);
+        assert_eq!(cfg.profiles["dev"].storage_account, "otheraccount");
+        assert_eq!(cfg.profiles["dev"].container, "");
+        assert_eq!(cfg.profiles["prod"].storage_account, "prodaccount");
+    }
+
+    #[test]
+    fn flatten_sections_moves_parameters_to_top_level() {
+        let mut value = json!({
+            "auth": {"storage_account": "id", "sas_token": "sv=..."},
+            "transfer": {"retries": 5},
+            "local": "/tmp",
+            "profiles": {"prod": {"defaults": {"container": "backup"}}}
+        });
+        flatten_sections(&mut value).unwrap();
+        assert_eq!(value, json!({
+            "storage_account": "id",
+            "sas_token": "sv=...",
+            "retries": 5,
+            "local": "/tmp",
+            "profiles": {"prod": {"container": "backup"}}
+        }));
+    }
+
+    #[test]
+    fn flatten_sections_rejects_unknown_and_duplicate_fields() {
+        let mut unknown = json!({"auth": {"container": "test"}});
+        assert!(flatten_sections(&mut unknown).unwrap_err().contains("unknown field 'container' in section 'auth'"));
+
+        let mut duplicate = json!({"storage_account": "a", "auth": {"storage_account": "b"}});
+        assert!(flatten_sections(&mut duplicate).unwrap_err().contains("set both in section 'auth' and at the top level"));
+
+        let mut in_profile = json!({"profiles": {"dev": {"transfer": {"local": "/tmp"}}}});
+        assert!(flatten_sections(&mut in_profile).is_err());
+    }
+}
@@ -0,0 +1,116 @@
+use azure_core::prelude::*;
+use azure_storage::blob::prelude::*;
+use azure_storage::core::prelude::*;
+
+use std::path::Path;
+use std::error::Error;
+use anyhow::anyhow;
+use azst::Pipeline;
+use futures::stream::{self, StreamExt};
+use http::StatusCode;
+use crate::audit::AuditLog;
+use crate::download;
+use crate::progress::Progress;
+use crate::transport;
+
+// Prefixes of the inbox and outbox of each device in a container, as the convention of messaging with a fleet.
+// {device_id} is replaced by the ID of the device.
+pub const DEFAULT_INBOX: &str = "devices/{device_id}/inbox/";
+pub const DEFAULT_OUTBOX: &str = "devices/{device_id}/outbox/";
+
+// Prefix of the inbox or outbox of the device by the template, or by the default if blank
+pub fn prefix(template: &str, default: &str, device_id: &str) -> Result<String, Box<dyn Error + Send + Sync>> {
+    if device_id.is_empty() {
+        return Err(anyhow!("No device ID specified. Set device_id in a config file or --device-id").into());
+    }
+    if device_id.contains('/') || device_id == "." || device_id == ".." {
+        return Err(anyhow!("Invalid device ID '{}'", device_id).into());
+    }
+    let template = if template.is_empty() { default } else { template };
+    Ok(template.replace("{device_id}", device_id))
+}
+
+// Get the messages in the inbox into the directory, named by the path relative to the inbox, and delete them
+// from the inbox once written and verified, so that each message is received once. Messages which fail, or are
+// put again while receiving, are left in the inbox for the next run. Fails after trying all messages if any failed.
+pub async fn pull_inbox(storage_client: &StorageClient, container: &str, inbox: &str, dir: &Path,
+    transforms: &Pipeline, chunk_size: u64, verify: bool, concurrency: usize, dry_run: bool, progress: &Progress,
+    audit: &AuditLog)
+    -> Result<(), Box<dyn Error + Send + Sync>>
+{
+    let blobs = crate::list_all_blobs(storage_client, container, Some(inbox)).await?;
+    let mut messages = Vec::new();
+    for blob in blobs.iter().filter(|blob| !blob.name.ends_with('/')) {
+        match download::local_path(dir, blob.name.strip_prefix(inbox).unwrap_or(&blob.name)) {
+            Ok(path) => messages.push((blob, path)),
+            Err(e) => eprintln!("Skipped: {}", e)
+        }
+    }
+
+    if dry_run {
+        for (blob, path) in messages.iter() {
+            println!(" {} -> {} (dry run)", blob.name, path.display());
+        }
+        println!("Would receive {} messages from '{}/{}'", messages.len(), container, inbox);
+        return Ok(());
+    }
+
+    progress.start_total(messages.len(), messages.iter().map(|(blob, _)| blob.properties.content_length).sum());
+    let container_client = &storage_client.as_container_client(container);
+    let results: Vec<_> = stream::iter(messages.iter())
+        .map(|(blob, path)| async move {
+            let result = async {
+                let etag = download::get_blob(container_client, blob, path, transforms, chunk_size, verify, progress, false)
+                    .await?;
+                // A message put again while receiving is kept, so that the new content is received next time
+                let res = container_client.as_blob_client(blob.name.as_str())
+                    .delete()
+                    .if_match_condition(IfMatchCondition::Match(etag))
+                    .execute()
+                    .await;
+                match res {
+                    Ok(res) => audit.record(storage_client, "delete", container, &blob.name, &res.request_id).await
+                        .map(|_| true),
+                    Err(e) if transport::error_status(e.as_ref()) == Some(StatusCode::PRECONDITION_FAILED) => Ok(false),
+                    Err(e) => Err(e)
+                }
+            }.await;
+            match &result {
+                Ok(true) => println!(" {} -> {}", blob.name, path.display()),
+                Ok(false) => println!(" {} -> {} (modified while receiving, kept in the inbox)", blob.name, path.display()),
+                Err(e) => eprintln!(" {} failed: {}", blob.name, e)
+            }
+            result
+        })
+        .buffer_unordered(concurrency.max(1))
+        .collect()
+        .await;
+    progress.finish();
+
+    let failed = results.iter().filter(|result| result.is_err()).count();
+    if failed > 0 {
+        return Err(anyhow!("{} of {} messages failed to receive", failed, messages.len()).into());
+    }
+    println!("Received {} messages from '{}/{}'", messages.len(), container, inbox);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn prefix_by_template() {
+        assert_eq!(prefix("", DEFAULT_INBOX, "dev01").unwrap(), "devices/dev01/inbox/");
+        assert_eq!(prefix("", DEFAULT_OUTBOX, "dev01").unwrap(), "devices/dev01/outbox/");
+        assert_eq!(prefix("sites/osaka/{device_id}/in/", DEFAULT_INBOX, "dev01").unwrap(), "sites/osaka/dev01/in/");
+    }
+
+    #[test]
+    fn prefix_rejects_invalid_device_id() {
+        assert!(prefix("", DEFAULT_INBOX, "").is_err());
+        assert!(prefix("", DEFAULT_INBOX, "dev01/../dev02").is_err());
+        assert!(prefix("", DEFAULT_INBOX, "..").is_err());
+        assert!(prefix("", DEFAULT_INBOX, ".").is_err());
+    }
+}
@@ -0,0 +1,500 @@
+use azure_core::prelude::*;
+use azure_storage::blob::prelude::*;
+use azure_storage::core::prelude::*;
+
+use std::io::prelude::*;
+use std::io::SeekFrom;
+use std::fs::{self, File};
+use std::io;
+use std::path::{Component, Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::error::Error;
+use anyhow::anyhow;
+use chrono::{DateTime, Utc};
+use futures::stream::{self, StreamExt, TryStreamExt};
+use azst::Pipeline;
+use serde_json::json;
+use crate::filter::BlobFilter;
+use crate::hash;
+use crate::hash_cache::HashCache;
+use crate::journal::BlockJournal;
+use crate::progress::Progress;
+
+// Size of each request to get allocated pages of a page blob
+const PAGE_CHUNK_SIZE: u64 = 4 * 1024 * 1024;
+
+// Whether to overwrite existing local files on getting blobs
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Overwrite {
+    Always,
+    Never,
+    // If the blob is modified after the local file
+    IfNewer,
+    // If the size or the hash differs. Blobs without any hash are overwritten if newer
+    IfDifferent,
+}
+
+impl FromStr for Overwrite {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "always" => Ok(Overwrite::Always),
+            "never" => Ok(Overwrite::Never),
+            "if-newer" => Ok(Overwrite::IfNewer),
+            "if-different" => Ok(Overwrite::IfDifferent),
+            _ => Err(anyhow!("Invalid overwrite policy '{}'", s))
+        }
+    }
+}
+
+impl Overwrite {
+    // Whether to write the blob to the local path by this policy
+    fn should_write(self, blob: &Blob, path: &Path, transformed: bool, cache: &HashCache) -> io::Result<bool> {
+        let metadata = match fs::metadata(path) {
+            Ok(metadata) => metadata,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(true),
+            Err(e) => return Err(e)
+        };
+        match self {
+            Overwrite::Always => Ok(true),
+            Overwrite::Never => Ok(false),
+            Overwrite::IfNewer => Ok(blob.properties.last_modified > DateTime::<Utc>::from(metadata.modified()?)),
+            Overwrite::IfDifferent => {
+                // The content length of a transformed blob is of the content after transforms
+                let size = Some(blob.properties.content_length).filter(|_| !transformed);
+                is_different(path, size, &hash::blob_digests(blob, transformed), blob.properties.last_modified, cache)
+            }
+        }
+    }
+}
+
+// Whether the local file differs from the blob of the size, if known, and the hashes. The hash by the configured
+// algorithm is compared if the blob has it, so that the hash cache is used, or else any hash the blob has.
+// Without any hash, the blob is different only if modified after the local file, like if-newer.
+fn is_different(path: &Path, size: Option<u64>, digests: &[(hash::HashAlgorithm, String)], last_modified: DateTime<Utc>,
+    cache: &HashCache) -> io::Result<bool>
+{
+    let metadata = fs::metadata(path)?;
+    if size.map_or(false, |size| size != metadata.len()) {
+        return Ok(true);
+    }
+    let digest = digests.iter().find(|(algorithm, _)| *algorithm == cache.algorithm()).or_else(|| digests.first());
+    match digest {
+        Some((algorithm, digest)) => Ok(cache.digest_by(path, *algorithm)? != *digest),
+        None => Ok(last_modified > DateTime::<Utc>::from(metadata.modified()?))
+    }
+}
+
+// Local path of a blob under the directory, keeping the virtual directories in the blob name.
+// Blob names with "..", absolute paths or drive prefixes are rejected,
+// so that a malicious container cannot write outside of the directory.
+pub fn local_path(dir: &Path, blob: &str) -> Result<PathBuf, Box<dyn Error + Send + Sync>> {
+    let mut path = dir.to_path_buf();
+    let mut names = 0;
+    for component in Path::new(blob).components() {
+        match component {
+            Component::Normal(name) => {
+                path.push(name);
+                names += 1;
+            },
+            Component::CurDir => {},
+            _ => return Err(anyhow!("Blob name '{}' escapes the local directory", blob).into())
+        }
+    }
+    if names == 0 {
+        return Err(anyhow!("Blob name '{}' has no file name", blob).into());
+    }
+    Ok(path)
+}
+
+// Write a file, creating its parent directories if not exist
+pub fn write_file(path: &Path, data: &[u8]) -> io::Result<()> {
+    if let Some(parent) = path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(path, data)
+}
+
+// Fail early if the file system of the path has no space to write the size of data, rather than filling up
+// the flash of the device and leaving a partial file. Existing files replaced by the data are counted as free.
+pub fn check_space(path: &Path, size: u64, replaced: u64) -> Result<(), Box<dyn Error + Send + Sync>> {
+    // Missing directories of the path are created on write
+    let dir = path.ancestors().find(|dir| dir.is_dir()).unwrap_or_else(|| Path::new("."));
+    let available = fs2::available_space(dir).map_err(|e| anyhow!("Cannot check free space of {:?}: {}", dir, e))?;
+    if size > available + replaced {
+        return Err(anyhow!("Not enough space to write {:?}: {} bytes needed, {} bytes available",
+            path, size - replaced, available).into());
+    }
+    Ok(())
+}
+
+// Size of an existing local file, or 0 if not exist
+pub fn file_size(path: &Path) -> u64 {
+    fs::metadata(path).map(|metadata| metadata.len()).unwrap_or(0)
+}
+
+// Get a page blob into a sparse file, getting only allocated page ranges.
+// Unallocated pages (such as unused areas of VHDs) are left as holes instead of downloading zeros.
+// Returns the number of bytes downloaded.
+pub async fn get_page_blob(blob_client: &BlobClient, path: &Path, size: u64) -> Result<u64, Box<dyn Error + Send + Sync>> {
+    let ranges = blob_client.get_page_ranges().execute().await?.page_list.ranges;
+
+    if let Some(parent) = path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+        fs::create_dir_all(parent)?;
+    }
+    let mut file = File::create(path)?;
+    file.set_len(size)?;
+
+    let mut downloaded = 0;
+    for range in ranges {
+        // Page ranges are inclusive
+        let (mut start, end) = (range.start(), range.end() + 1);
+        while start < end {
+            let chunk_end = (start + PAGE_CHUNK_SIZE).min(end);
+            let res = blob_client.get().range(Range::new(start, chunk_end)).execute().await?;
+            file.seek(SeekFrom::Start(start))?;
+            file.write_all(&res.data)?;
+            downloaded += res.data.len() as u64;
+            start = chunk_end;
+        }
+    }
+    file.sync_all()?;
+    Ok(downloaded)
+}
+
+// Get a blob into a file pre-allocated to the size, by concurrent range requests of the chunk size
+// written at their offsets. At most as many chunks as the concurrency are in memory.
+// Completed ranges are recorded in the journal ".<file name>.ranges" next to the file, so that an interrupted get
+// can be resumed with the partial file, if the blob has the same ETag.
+pub async fn get_ranges(blob_client: &BlobClient, path: &Path, size: u64, etag: &str, chunk_size: u64,
+    concurrency: usize, resume: bool, progress: &Progress, debug: bool) -> Result<(), Box<dyn Error + Send + Sync>>
+{
+    let chunk_size = chunk_size.max(1);
+    let file_name = path.file_name().ok_or(anyhow!("Invalid local path {:?}", path))?;
+    let journal_path = path.with_file_name(format!(".{}.ranges", file_name.to_string_lossy()));
+    let header = json!({ "etag": etag, "size": size, "chunk_size": chunk_size });
+    let journal = BlockJournal::open(&journal_path, &header, resume && path.exists())?;
+    if debug && journal.resumed() > 0 {
+        println!("{} ranges of {:?} are already got", journal.resumed(), path);
+    }
+
+    // The partial file is kept if resuming
+    if journal.resumed() == 0 {
+        if let Some(parent) = path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+            fs::create_dir_all(parent)?;
+        }
+        File::create(path)?.set_len(size)?;
+    }
+
+    let file_progress = progress.file(path, size);
+    let file_progress = &file_progress;
+    let journal_ref = &journal;
+    stream::iter((0..size).step_by(chunk_size as usize).enumerate())
+        .filter(|(i, start)| {
+            let done = journal_ref.is_done(*i as u64);
+            if done {
+                file_progress.inc((start + chunk_size).min(size) - start);
+            }
+            futures::future::ready(!done)
+        })
+        .map(|(i, start)| async move {
+            let end = (start + chunk_size).min(size);
+            let res = blob_client.get().range(Range::new(start, end)).execute().await?;
+            if res.data.len() as u64 != end - start {
+                return Err(anyhow!("Range {}-{} of {} bytes is got as {} bytes", start, end, size, res.data.len()).into());
+            }
+            let mut file = fs::OpenOptions::new().write(true).open(path)?;
+            file.seek(SeekFrom::Start(start))?;
+            file.write_all(&res.data)?;
+            file.sync_data()?;
+            journal_ref.add(i as u64)?;
+            file_progress.inc(end - start);
+            if debug {
+                println!(" range {}-{} of {:?}", start, end, path);
+            }
+            Ok::<_, Box<dyn Error + Send + Sync>>(())
+        })
+        .buffer_unordered(concurrency.max(1))
+        .try_collect::<Vec<_>>()
+        .await?;
+
+    // Ranges got before and after a change of the blob do not match
+    let current = blob_client.get_properties().execute().await?.blob.properties.etag.to_string();
+    if current != etag {
+        return Err(anyhow!("Blob is modified while getting (ETag {} -> {}). Get it again", etag, current).into());
+    }
+    journal.remove()?;
+    Ok(())
+}
+
+// Write the content of the blob to stdout without touching the file system. Untransformed blobs are got
+// by ranges of the chunk size one by one with bounded memory, and transformed blobs are decoded as a whole.
+// If verifying, the content written is checked by the hashes of the blob at the end, and fails on mismatch.
+// Block blobs without any hash are refused like get.
+pub async fn cat(blob_client: &BlobClient, blob: &Blob, transforms: &Pipeline, chunk_size: u64, verify: bool)
+    -> Result<(), Box<dyn Error + Send + Sync>>
+{
+    let expected = match verify {
+        true => hash::blob_digests(blob, !transforms.is_empty()),
+        false => Vec::new()
+    };
+    // Nothing is written yet, so a block blob which cannot be verified is refused before writing
+    if verify && expected.is_empty() {
+        if matches!(blob.properties.blob_type, BlobType::BlockBlob) {
+            return Err(anyhow!("'{}' has no hash to verify the content. Cat it with --no-verify to trust it", blob.name).into());
+        }
+        eprintln!("Warning: '{}' has no hash, the content is not verified", blob.name);
+    }
+    let mut hashers: Vec<hash::Hasher> = expected.iter().map(|(algorithm, _)| hash::Hasher::new(*algorithm)).collect();
+    let mut write = |data: &[u8]| {
+        for hasher in hashers.iter_mut() {
+            hasher.update(data);
+        }
+        let mut stdout = io::stdout();
+        stdout.write_all(data).and_then(|_| stdout.flush())
+    };
+
+    if transforms.is_empty() {
+        let size = blob.properties.content_length;
+        let etag = blob.properties.etag.to_string();
+        for start in (0..size).step_by(chunk_size.max(1) as usize) {
+            let end = (start + chunk_size).min(size);
+            let res = blob_client.get().range(Range::new(start, end)).execute().await?;
+            if res.blob.properties.etag.to_string() != etag {
+                return Err(anyhow!("Blob '{}' is modified while getting", blob.name).into());
+            }
+            write(&res.data)?;
+        }
+    } else {
+        let res = blob_client.get().execute().await?;
+        write(&transforms.decode(res.data.to_vec())?)?;
+    }
+
+    for ((algorithm, expected), hasher) in expected.iter().zip(hashers.into_iter()) {
+        let actual = hasher.finalize();
+        if actual != *expected {
+            return Err(anyhow!("{} of the content written ({}) does not match '{}' ({})",
+                algorithm.name(), actual, blob.name, expected).into());
+        }
+    }
+    Ok(())
+}
+
+// Get a listed blob into the local path. Page blobs are written as sparse files, and block blobs are decoded by
+// the transform pipeline. Untransformed blobs larger than the chunk size are got by ranges one by one with bounded
+// memory, failing if the blob is modified while getting. If verifying, the file is verified by the hashes of the blob.
+// Returns the ETag of the content got, to modify the blob only if not modified since.
+pub async fn get_blob(container_client: &Arc<ContainerClient>, blob: &Blob, path: &Path, transforms: &Pipeline,
+    chunk_size: u64, verify: bool, progress: &Progress, debug: bool) -> Result<String, Box<dyn Error + Send + Sync>>
+{
+    let blob_client = container_client.as_blob_client(blob.name.as_str());
+    let size = blob.properties.content_length;
+    if matches!(blob.properties.blob_type, BlobType::PageBlob) {
+        let file_progress = progress.file(path, size);
+        get_page_blob(&blob_client, path, size).await?;
+        if verify {
+            verify_file(path, blob, false)?;
+        }
+        file_progress.inc(size);
+        Ok(blob.properties.etag.to_string())
+    } else if transforms.is_empty() && size > chunk_size {
+        // Ranges of each blob are got one by one, since blobs are got concurrently
+        let etag = blob.properties.etag.to_string();
+        get_ranges(&blob_client, path, size, &etag, chunk_size, 1, false, progress, debug).await?;
+        if verify {
+            verify_file(path, blob, false)?;
+        }
+        Ok(etag)
+    } else {
+        // The content got may be newer than listed, so it is verified by the hashes of the blob got
+        let file_progress = progress.file(path, size);
+        let res = blob_client.get().execute().await?;
+        write_file(path, &transforms.decode(res.data.to_vec())?)?;
+        if verify {
+            verify_file(path, &res.blob, !transforms.is_empty())?;
+        }
+        file_progress.inc(size);
+        Ok(res.blob.properties.etag.to_string())
+    }
+}
+
+// Verify the written file by the hashes of the blob, which are the Content-MD5 unless transformed and the hashes
+// in the metadata recorded on put (such as azst_sha256). The file is removed on mismatch, so that a corrupt file
+// is never left in place. Block blobs without any hash cannot be verified, and are also removed unless --no-verify.
+// The service records no hash of the whole content of append and page blobs, so they are only warned.
+pub fn verify_file(path: &Path, blob: &Blob, transformed: bool) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let required = matches!(blob.properties.blob_type, BlobType::BlockBlob);
+    verify_digests(path, &blob.name, &hash::blob_digests(blob, transformed), required)
+}
+
+fn verify_digests(path: &Path, name: &str, digests: &[(hash::HashAlgorithm, String)], required: bool)
+    -> Result<(), Box<dyn Error + Send + Sync>>
+{
+    if digests.is_empty() {
+        if required {
+            fs::remove_file(path)?;
+            return Err(anyhow!("'{}' has no hash to verify {:?}. The file is removed. Get it with --no-verify to trust it",
+                name, path).into());
+        }
+        eprintln!("Warning: '{}' has no hash, {:?} is not verified", name, path);
+    }
+    for (algorithm, expected) in digests {
+        let actual = algorithm.file_digest(path)?;
+        if actual != *expected {
+            fs::remove_file(path)?;
+            return Err(anyhow!("{} of {:?} ({}) does not match '{}' ({}). The file is removed",
+                algorithm.name(), path, actual, name, expected).into());
+        }
+    }
+    Ok(())
+}
+
+// Get all blobs with the prefix which match the filter into the directory, recreating their virtual directories.
+// Block blobs are decoded by the transform pipeline. Existing local files are overwritten by the policy.
+pub async fn get_blobs(storage_client: &StorageClient, container: &str, prefix: &str, filter: &BlobFilter, dir: &Path,
+    overwrite: Overwrite, transforms: &Pipeline, cache: &HashCache, chunk_size: u64, verify: bool, concurrency: usize,
+    progress: &Progress, debug: bool) -> Result<(), Box<dyn Error + Send + Sync>>
+{
+    let container_client = storage_client.as_container_client(container);
+    let blobs = crate::list_all_blobs(storage_client, container, Some(prefix)).await?;
+
+    // Names ending with '/' are directory markers, which have no content.
+    // Blobs whose names are not safe as local paths are skipped.
+    let mut targets = Vec::new();
+    let mut unchanged = 0;
+    let transformed = !transforms.is_empty();
+    for blob in blobs.iter().filter(|blob| !blob.name.ends_with('/') && filter.matches(blob)) {
+        match local_path(dir, &blob.name) {
+            Ok(path) if overwrite.should_write(blob, &path, transformed, cache)? => targets.push((blob, path)),
+            Ok(path) => {
+                if debug {
+                    println!(" {} -> {} (kept)", blob.name, path.display());
+                }
+                unchanged += 1;
+            },
+            Err(e) => eprintln!("Skipped: {}", e)
+        }
+    }
+    if debug {
+        println!("{} blobs with prefix '{}', {} to get", blobs.len(), prefix, targets.len());
+    }
+    // Page blobs are written as sparse files, so their sizes are not counted
+    let blocks = targets.iter().filter(|(blob, _)| !matches!(blob.properties.blob_type, BlobType::PageBlob));
+    let (size, replaced) = blocks.fold((0, 0), |(size, replaced), (blob, path)|
+        (size + blob.properties.content_length, replaced + file_size(path)));
+    check_space(dir, size, replaced)?;
+    progress.start_total(targets.len(), targets.iter().map(|(blob, _)| blob.properties.content_length).sum());
+
+    let container_client = &container_client;
+    stream::iter(targets.iter())
+        .map(|(blob, path)| async move {
+            get_blob(container_client, blob, path, transforms, chunk_size, verify, progress, debug).await?;
+            println!(" {} -> {}", blob.name, path.display());
+            Ok::<_, Box<dyn Error + Send + Sync>>(())
+        })
+        .buffer_unordered(concurrency.max(1))
+        .try_collect::<Vec<_>>()
+        .await?;
+
+    progress.finish();
+    println!("Got {} blobs from '{}/{}', {} local files kept", targets.len(), container, prefix, unchanged);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn local_path_keeps_virtual_directories() {
+        let dir = Path::new("/data");
+        assert_eq!(local_path(dir, "a.txt").unwrap(), Path::new("/data/a.txt"));
+        assert_eq!(local_path(dir, "logs/2021/a.txt").unwrap(), Path::new("/data/logs/2021/a.txt"));
+        assert_eq!(local_path(dir, "./logs/a.txt").unwrap(), Path::new("/data/logs/a.txt"));
+    }
+
+    #[test]
+    fn local_path_rejects_traversal() {
+        let dir = Path::new("/data");
+        assert!(local_path(dir, "../etc/passwd").is_err());
+        assert!(local_path(dir, "logs/../../etc/passwd").is_err());
+        assert!(local_path(dir, "/etc/passwd").is_err());
+        assert!(local_path(dir, "").is_err());
+        assert!(local_path(dir, ".").is_err());
+    }
+
+    fn temp_file(data: &[u8]) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("azst-verify-{}", uuid::Uuid::new_v4()));
+        fs::write(&path, data).unwrap();
+        path
+    }
+
+    #[test]
+    fn verify_digests_keeps_matching_file() {
+        let path = temp_file(b"firmware");
+        let digests = vec![
+            (hash::HashAlgorithm::Md5, hash::HashAlgorithm::Md5.digest(b"firmware")),
+            (hash::HashAlgorithm::Sha256, hash::HashAlgorithm::Sha256.digest(b"firmware")),
+        ];
+        verify_digests(&path, "fw.bin", &digests, true).unwrap();
+        assert!(path.exists());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn verify_digests_removes_mismatched_file() {
+        let path = temp_file(b"corrupt");
+        let digests = vec![
+            (hash::HashAlgorithm::Md5, hash::HashAlgorithm::Md5.digest(b"corrupt")),
+            (hash::HashAlgorithm::Sha256, hash::HashAlgorithm::Sha256.digest(b"firmware")),
+        ];
+        let e = verify_digests(&path, "fw.bin", &digests, true).unwrap_err();
+        assert!(e.to_string().contains("sha256"));
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn verify_digests_without_hash() {
+        // Block blobs without any hash are refused and removed
+        let path = temp_file(b"firmware");
+        let e = verify_digests(&path, "fw.bin", &[], true).unwrap_err();
+        assert!(e.to_string().contains("--no-verify"));
+        assert!(!path.exists());
+
+        // Append and page blobs without any hash are kept
+        let path = temp_file(b"log");
+        verify_digests(&path, "app.log", &[], false).unwrap();
+        assert!(path.exists());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn is_different_by_size_and_hash() {
+        let path = temp_file(b"model");
+        let cache = HashCache::load("", hash::HashAlgorithm::Md5).unwrap();
+        let modified = Utc::now() - chrono::Duration::days(1);
+        let md5 = (hash::HashAlgorithm::Md5, hash::HashAlgorithm::Md5.digest(b"model"));
+        let sha256 = (hash::HashAlgorithm::Sha256, hash::HashAlgorithm::Sha256.digest(b"model"));
+
+        assert!(!is_different(&path, Some(5), &[md5.clone()], modified, &cache).unwrap());
+        assert!(is_different(&path, Some(6), &[md5], modified, &cache).unwrap());
+        assert!(is_different(&path, Some(5), &[(hash::HashAlgorithm::Md5, "00".into())], modified, &cache).unwrap());
+        // Another algorithm than configured is compared if the blob has only it
+        assert!(!is_different(&path, Some(5), &[sha256.clone()], modified, &cache).unwrap());
+        assert!(is_different(&path, Some(5), &[(hash::HashAlgorithm::Sha256, "00".into())], modified, &cache).unwrap());
+        // The size of transformed blobs is not compared
+        assert!(!is_different(&path, None, &[sha256], modified, &cache).unwrap());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn is_different_without_hash_if_newer() {
+        let path = temp_file(b"model");
+        let cache = HashCache::load("", hash::HashAlgorithm::Md5).unwrap();
+        assert!(!is_different(&path, Some(5), &[], Utc::now() - chrono::Duration::days(1), &cache).unwrap());
+        assert!(is_different(&path, Some(5), &[], Utc::now() + chrono::Duration::days(1), &cache).unwrap());
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,46 @@
+use azure_storage::core::prelude::*;
+
+use std::error::Error;
+use chrono::{DateTime, Utc};
+use crate::audit::AuditLog;
+
+// Metadata of the time a blob expires, in RFC 3339
+pub const EXPIRES: &str = "azst_expires";
+
+// Delete blobs with the prefix past the expiry recorded in their metadata on put.
+// Blobs without the expiry or with an invalid one are kept.
+pub async fn expire(storage_client: &StorageClient, container: &str, prefix: &str, dry_run: bool, audit: &AuditLog,
+    debug: bool) -> Result<(), Box<dyn Error + Send + Sync>>
+{
+    let now = Utc::now();
+    let blobs = crate::list_all_blobs(storage_client, container, Some(prefix)).await?;
+    let container_client = storage_client.as_container_client(container);
+
+    let mut expired = 0;
+    for blob in blobs.iter() {
+        let expires = blob.metadata.as_ref()
+            .and_then(|metadata| metadata.get(EXPIRES))
+            .and_then(|expires| DateTime::parse_from_rfc3339(expires).ok());
+        let expires = match expires {
+            Some(expires) if expires <= now => expires,
+            _ => {
+                if debug {
+                    println!(" {}: not expired", blob.name);
+                }
+                continue;
+            }
+        };
+        if dry_run {
+            println!(" delete {} expired at {} (dry run)", blob.name, expires.to_rfc3339());
+        } else {
+            let res = container_client.as_blob_client(blob.name.as_str()).delete().execute().await?;
+            audit.record(storage_client, "expire", container, &blob.name, &res.request_id).await?;
+            println!(" delete {} expired at {}", blob.name, expires.to_rfc3339());
+        }
+        expired += 1;
+    }
+
+    println!("{} {} expired blobs of {} in '{}/{}'", if dry_run { "Would delete" } else { "Deleted" }, expired,
+        blobs.len(), container, prefix);
+    Ok(())
+}
@@ -0,0 +1,85 @@
+use azure_storage::blob::prelude::*;
+use azure_storage::core::prelude::*;
+
+use std::io::prelude::*;
+use std::fs::{self, File};
+use std::path::Path;
+use std::error::Error;
+use sha2::{Digest, Sha256};
+use anyhow::anyhow;
+use crate::signature;
+
+// How to verify the config blob
+pub enum Verification<'a> {
+    // By the SHA-256 hash in the hash blob
+    Hash(&'a str),
+    // By the minisign signature in "<blob>.sig" with the public key
+    Signature(&'a str),
+}
+
+// Download a blob, and replace the target file only if the blob is verified by its hash blob or signature.
+pub async fn fetch_config(storage_client: &StorageClient, container: &str, blob: &str,
+    verification: Verification<'_>, target: &Path, debug: bool) -> Result<(), Box<dyn Error + Send + Sync>>
+{
+    let container_client = storage_client.as_container_client(container);
+    let data = container_client.as_blob_client(blob).get().execute().await?.data;
+
+    match verification {
+        Verification::Hash(hash_blob) => verify_hash(storage_client, container, blob, &data, hash_blob, debug).await?,
+        Verification::Signature(public_key) =>
+            signature::verify_blob(storage_client, container, blob, &data, public_key).await?
+    }
+
+    write_atomic(target, &data)?;
+    println!("Updated {:?} from '{}/{}'", target, container, blob);
+    Ok(())
+}
+
+// Verify the data by the SHA-256 hash in the hash blob.
+// The hash blob is in the format of sha256sum, only the first word is used.
+async fn verify_hash(storage_client: &StorageClient, container: &str, blob: &str, data: &[u8], hash_blob: &str,
+    debug: bool) -> Result<(), Box<dyn Error + Send + Sync>>
+{
+    let hash = storage_client
+        .as_container_client(container)
+        .as_blob_client(hash_blob)
+        .get()
+        .execute()
+        .await?
+        .data;
+
+    let expected = String::from_utf8_lossy(&hash)
+        .split_whitespace()
+        .next()
+        .unwrap_or_default()
+        .to_ascii_lowercase();
+    let actual = format!("{:x}", Sha256::digest(data));
+    if debug {
+        println!("sha256 expected = {}, actual = {}", expected, actual);
+    }
+    if expected != actual {
+        return Err(anyhow!("SHA-256 of '{}' does not match '{}'", blob, hash_blob).into());
+    }
+    Ok(())
+}
+
+// Replace a file by writing to a temporary file in the same directory and renaming it,
+// so that readers never see a partially written file. Permissions of the existing file are kept.
+pub fn write_atomic(path: &Path, data: &[u8]) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let file_name = path.file_name().ok_or(anyhow!("Invalid target path {:?}", path))?;
+    let tmp_path = path.with_file_name(format!(".{}.tmp", file_name.to_string_lossy()));
+
+    let result = (|| {
+        let mut f = File::create(&tmp_path)?;
+        if let Ok(metadata) = fs::metadata(path) {
+            f.set_permissions(metadata.permissions())?;
+        }
+        f.write_all(data)?;
+        f.sync_all()?;
+        fs::rename(&tmp_path, path)
+    })();
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp_path);
+    }
+    Ok(result?)
+}
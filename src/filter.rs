@@ -0,0 +1,47 @@
+use azure_storage::blob::prelude::*;
+
+use std::error::Error;
+use anyhow::anyhow;
+use chrono::{DateTime, Utc};
+use crate::metadata;
+
+// Filter of blobs to get by their metadata and last modified time, so that devices get only blobs targeted at them
+#[derive(Clone, Debug, Default)]
+pub struct BlobFilter {
+    // Metadata all of which the blob has. Names are case-insensitive as in the service
+    pub metadata: Vec<(String, String)>,
+    pub modified_after: Option<DateTime<Utc>>,
+    pub modified_before: Option<DateTime<Utc>>,
+}
+
+impl BlobFilter {
+    // Filter by metadata given as <name>=<value>, and times given as RFC 3339 or the duration ago such as 7d
+    pub fn new(metadata: &[&str], modified_after: Option<&str>, modified_before: Option<&str>)
+        -> Result<BlobFilter, Box<dyn Error + Send + Sync>>
+    {
+        Ok(BlobFilter {
+            metadata: metadata::parse_pairs(metadata)?,
+            modified_after: modified_after.map(parse_time).transpose()?,
+            modified_before: modified_before.map(parse_time).transpose()?,
+        })
+    }
+
+    pub fn matches(&self, blob: &Blob) -> bool {
+        let last_modified = blob.properties.last_modified;
+        let has = |name: &str, value: &str| blob.metadata.as_ref()
+            .map_or(false, |metadata| metadata.iter().any(|(k, v)| k.eq_ignore_ascii_case(name) && v == value));
+        self.metadata.iter().all(|(name, value)| has(name, value))
+            && self.modified_after.map_or(true, |time| last_modified > time)
+            && self.modified_before.map_or(true, |time| last_modified < time)
+    }
+}
+
+// Time given as RFC 3339, or as the duration before now
+fn parse_time(time: &str) -> Result<DateTime<Utc>, Box<dyn Error + Send + Sync>> {
+    if let Ok(time) = DateTime::parse_from_rfc3339(time) {
+        return Ok(time.with_timezone(&Utc));
+    }
+    crate::parse_duration(time)
+        .map(|duration| Utc::now() - duration)
+        .map_err(|_| anyhow!("Invalid time '{}'. Specify as RFC 3339 or the duration ago such as 7d", time).into())
+}
@@ -0,0 +1,179 @@
+use azure_storage::blob::prelude::*;
+
+use std::io::prelude::*;
+use std::fs::File;
+use std::io;
+use std::path::Path;
+use std::str::FromStr;
+use anyhow::anyhow;
+use sha2::{Digest, Sha256};
+
+// Hash algorithm to compare local files with blobs.
+// MD5 is the Content-MD5 of blobs. Others are stored in the blob metadata on put, since MD5 is disallowed
+// by some security policies.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum HashAlgorithm {
+    Md5,
+    Sha256,
+    Blake3,
+}
+
+impl FromStr for HashAlgorithm {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "" | "md5" => Ok(HashAlgorithm::Md5),
+            "sha256" => Ok(HashAlgorithm::Sha256),
+            "blake3" => Ok(HashAlgorithm::Blake3),
+            _ => Err(anyhow!("Invalid hash algorithm '{}'", s))
+        }
+    }
+}
+
+impl HashAlgorithm {
+    pub const ALL: [HashAlgorithm; 3] = [HashAlgorithm::Md5, HashAlgorithm::Sha256, HashAlgorithm::Blake3];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            HashAlgorithm::Md5 => "md5",
+            HashAlgorithm::Sha256 => "sha256",
+            HashAlgorithm::Blake3 => "blake3",
+        }
+    }
+
+    // Metadata name of the hash of the content before transforms, such as azst_sha256
+    pub fn metadata_name(self) -> String {
+        format!("azst_{}", self.name())
+    }
+
+    // Whether the hash is stored in the metadata on put at once. MD5 is the Content-MD5 unless transformed,
+    // when the Content-MD5 is of the content after transforms. Blobs put by blocks have it in the metadata anyway.
+    pub fn in_metadata(self, transformed: bool) -> bool {
+        self != HashAlgorithm::Md5 || transformed
+    }
+
+    // Hex encoded hash of the data
+    pub fn digest(self, data: &[u8]) -> String {
+        let mut hasher = Hasher::new(self);
+        hasher.update(data);
+        hasher.finalize()
+    }
+
+    // Hex encoded hash of a local file, read by chunks
+    pub fn file_digest(self, path: &Path) -> io::Result<String> {
+        let mut file = File::open(path)?;
+        let mut hasher = Hasher::new(self);
+        let mut buffer = vec![0u8; 64 * 1024];
+        loop {
+            match file.read(&mut buffer)? {
+                0 => return Ok(hasher.finalize()),
+                n => hasher.update(&buffer[..n])
+            }
+        }
+    }
+
+    // Hex encoded hash of the content of a blob before transforms recorded on put, if any.
+    // Blobs put by blocks have no Content-MD5, and have the MD5 in the metadata instead.
+    pub fn blob_digest(self, blob: &Blob, transformed: bool) -> Option<String> {
+        let content_md5 = blob.properties.content_md5.as_ref().map(|md5| &md5.bytes()[..]);
+        let in_metadata = blob.metadata.as_ref().and_then(|metadata| metadata.get(&self.metadata_name()));
+        self.recorded_digest(content_md5, in_metadata.map(String::as_str), transformed)
+    }
+
+    // Hex encoded hash recorded on put, from the Content-MD5 and the hash of the algorithm in the metadata
+    fn recorded_digest(self, content_md5: Option<&[u8]>, in_metadata: Option<&str>, transformed: bool)
+        -> Option<String>
+    {
+        match self.in_metadata(transformed) {
+            false => content_md5.map(hex).or_else(|| in_metadata.map(String::from)),
+            true => in_metadata.map(String::from)
+        }
+    }
+}
+
+// Hex encoded hashes of the content of a blob before transforms recorded on put by any algorithm.
+// If transformed, MD5 is the one in the metadata instead of the Content-MD5, which is of the content after transforms.
+pub fn blob_digests(blob: &Blob, transformed: bool) -> Vec<(HashAlgorithm, String)> {
+    HashAlgorithm::ALL.iter()
+        .filter_map(|algorithm| algorithm.blob_digest(blob, transformed).map(|digest| (*algorithm, digest)))
+        .collect()
+}
+
+// Incremental hasher of the algorithm, for data uploaded by blocks
+pub enum Hasher {
+    Md5(md5::Context),
+    Sha256(Sha256),
+    Blake3(Box<blake3::Hasher>),
+}
+
+impl Hasher {
+    pub fn new(algorithm: HashAlgorithm) -> Hasher {
+        match algorithm {
+            HashAlgorithm::Md5 => Hasher::Md5(md5::Context::new()),
+            HashAlgorithm::Sha256 => Hasher::Sha256(Sha256::new()),
+            HashAlgorithm::Blake3 => Hasher::Blake3(Box::new(blake3::Hasher::new())),
+        }
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        match self {
+            Hasher::Md5(context) => context.consume(data),
+            Hasher::Sha256(sha256) => sha256.update(data),
+            Hasher::Blake3(blake3) => { blake3.update(data); },
+        }
+    }
+
+    pub fn finalize(self) -> String {
+        match self {
+            Hasher::Md5(context) => hex(&context.compute().0),
+            Hasher::Sha256(sha256) => hex(&sha256.finalize()),
+            Hasher::Blake3(blake3) => blake3.finalize().to_hex().to_string(),
+        }
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // MD5 of "The quick brown fox jumps over the lazy dog"
+    const MD5: [u8; 16] = [
+        0x9e, 0x10, 0x7d, 0x9d, 0x37, 0x2b, 0xb6, 0x82, 0x6b, 0xd8, 0x1d, 0x35, 0x42, 0xa4, 0x19, 0xd6,
+    ];
+    const MD5_HEX: &str = "9e107d9d372bb6826bd81d3542a419d6";
+
+    #[test]
+    fn recorded_digest_of_md5_is_content_md5_unless_transformed() {
+        let md5 = HashAlgorithm::Md5;
+        assert_eq!(md5.recorded_digest(Some(&MD5[..]), Some("0f34"), false).unwrap(), MD5_HEX);
+        assert_eq!(md5.recorded_digest(Some(&MD5[..]), Some("0f34"), true).unwrap(), "0f34");
+        assert_eq!(md5.recorded_digest(Some(&MD5[..]), None, true), None);
+    }
+
+    #[test]
+    fn recorded_digest_of_blocks_is_in_metadata() {
+        // Blobs put by blocks have no Content-MD5
+        assert_eq!(HashAlgorithm::Md5.recorded_digest(None, Some("0f34"), false).unwrap(), "0f34");
+        assert_eq!(HashAlgorithm::Md5.recorded_digest(None, None, false), None);
+    }
+
+    #[test]
+    fn recorded_digest_of_other_algorithms_is_in_metadata() {
+        for algorithm in [HashAlgorithm::Sha256, HashAlgorithm::Blake3] {
+            assert_eq!(algorithm.recorded_digest(Some(&MD5[..]), Some("ab12"), false).unwrap(), "ab12");
+            assert_eq!(algorithm.recorded_digest(Some(&MD5[..]), None, false), None);
+        }
+    }
+
+    #[test]
+    fn digest_of_data() {
+        assert_eq!(HashAlgorithm::Md5.digest(b"The quick brown fox jumps over the lazy dog"), MD5_HEX);
+        let empty = "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+        assert_eq!(HashAlgorithm::Sha256.digest(b""), empty);
+    }
+}
@@ -0,0 +1,91 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::error::Error;
+use anyhow::anyhow;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use crate::hash::HashAlgorithm;
+
+// Hashes of a local file by algorithms, valid while its size and modification time are the same
+#[derive(Serialize, Deserialize, Default, Debug, Clone)]
+#[serde(default)]
+struct CachedHash {
+    size: u64,
+    modified: String,
+    digests: BTreeMap<String, String>,
+}
+
+// Cache of the hashes of local files by the configured algorithm across runs, so that unchanged files
+// are not hashed again. Files are always hashed if no cache file is configured.
+pub struct HashCache {
+    file: Option<PathBuf>,
+    algorithm: HashAlgorithm,
+    hashes: Mutex<BTreeMap<String, CachedHash>>,
+}
+
+impl HashCache {
+    // Load the cache file. A cache file which does not exist is empty.
+    pub fn load(file: &str, algorithm: HashAlgorithm) -> Result<HashCache, Box<dyn Error + Send + Sync>> {
+        if file.is_empty() {
+            return Ok(HashCache { file: None, algorithm, hashes: Mutex::new(BTreeMap::new()) });
+        }
+        let hashes = match fs::read(file) {
+            Ok(data) => serde_json::from_slice(&data).map_err(|e| anyhow!("Cannot parse hash cache {:?}: {}", file, e))?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(e) => return Err(anyhow!("Cannot read hash cache {:?}: {}", file, e).into())
+        };
+        Ok(HashCache { file: Some(PathBuf::from(file)), algorithm, hashes: Mutex::new(hashes) })
+    }
+
+    pub fn algorithm(&self) -> HashAlgorithm {
+        self.algorithm
+    }
+
+    // Hex encoded hash of a local file, from the cache if its size and modification time are not changed
+    pub fn digest(&self, path: &Path) -> io::Result<String> {
+        self.digest_by(path, self.algorithm)
+    }
+
+    // Hex encoded hash of a local file by another algorithm than configured, such as of the hash a blob has
+    pub fn digest_by(&self, path: &Path, algorithm: HashAlgorithm) -> io::Result<String> {
+        if self.file.is_none() {
+            return algorithm.file_digest(path);
+        }
+        let key = fs::canonicalize(path)?.display().to_string();
+        let metadata = fs::metadata(path)?;
+        let size = metadata.len();
+        let modified = DateTime::<Utc>::from(metadata.modified()?).to_rfc3339();
+
+        if let Some(cached) = self.hashes.lock().unwrap().get(&key) {
+            if cached.size == size && cached.modified == modified {
+                if let Some(digest) = cached.digests.get(algorithm.name()) {
+                    return Ok(digest.clone());
+                }
+            }
+        }
+
+        let digest = algorithm.file_digest(path)?;
+        let mut hashes = self.hashes.lock().unwrap();
+        let cached = hashes.entry(key).or_default();
+        if cached.size != size || cached.modified != modified {
+            *cached = CachedHash { size, modified, ..Default::default() };
+        }
+        cached.digests.insert(algorithm.name().into(), digest.clone());
+        Ok(digest)
+    }
+
+    // Save the cache file, dropping files which no longer exist
+    pub fn save(&self) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let file = match &self.file {
+            Some(file) => file,
+            None => return Ok(())
+        };
+        let mut hashes = self.hashes.lock().unwrap();
+        hashes.retain(|path, _| Path::new(path).is_file());
+        fs::write(file, serde_json::to_vec(&*hashes)?)
+            .map_err(|e| anyhow!("Cannot write hash cache {:?}: {}", file, e).into())
+    }
+}
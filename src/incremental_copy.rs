@@ -0,0 +1,95 @@
+use azure_storage::core::prelude::*;
+
+use std::error::Error;
+use anyhow::anyhow;
+use bytes::Bytes;
+use http::{Method, Request, Response};
+use tokio::time::{self, Duration};
+use url::Url;
+use crate::audit::AuditLog;
+use crate::secret::Secret;
+use crate::transport;
+
+// Incremental Copy Blob requires 2016-05-31 or later
+const API_VERSION: &str = "2019-12-12";
+
+// Interval to check the status of the copy
+const POLL_SECONDS: u64 = 5;
+
+// Copy a new snapshot of the source page blob to the target blob incrementally, for managed-disk style backups.
+// The service transfers only the pages changed since the snapshot copied last time, and keeps each copied
+// snapshot as a snapshot of the target. The source snapshot is given to the service by the SAS token.
+pub async fn incremental_copy(storage_client: &StorageClient, container: &str, blob: &str, target_container: &str,
+    target_blob: &str, sas_token: &Secret, audit: &AuditLog, debug: bool) -> Result<(), Box<dyn Error + Send + Sync>>
+{
+    // Take a new snapshot of the source
+    let source_url = transport::blob_url(storage_client, container, blob)?;
+    let res = send(storage_client, Method::PUT, &source_url, "comp=snapshot", sas_token, None).await?;
+    let snapshot = header(&res, "x-ms-snapshot")?;
+    println!("Snapshot {} of '{}/{}'", snapshot, container, blob);
+
+    // Start copying the snapshot
+    let mut copy_source = source_url;
+    copy_source.query_pairs_mut().append_pair("snapshot", &snapshot);
+    let copy_source = format!("{}&{}", copy_source, sas_token.expose());
+    let target_url = transport::blob_url(storage_client, target_container, target_blob)?;
+    let res = send(storage_client, Method::PUT, &target_url, "comp=incrementalcopy", sas_token,
+        Some(&copy_source)).await?;
+    audit.record(storage_client, "incremental-copy", target_container, target_blob,
+        &header(&res, "x-ms-request-id")?).await?;
+
+    // Wait until the copy completes
+    let mut res = res;
+    loop {
+        match header(&res, "x-ms-copy-status")?.as_str() {
+            "success" => {
+                println!("Copied to '{}/{}' snapshot {}", target_container, target_blob,
+                    header(&res, "x-ms-copy-destination-snapshot").unwrap_or_default());
+                return Ok(());
+            },
+            "pending" => {},
+            status => return Err(anyhow!("Copy {}: {}", status,
+                header(&res, "x-ms-copy-status-description").unwrap_or_default()).into())
+        }
+
+        time::sleep(Duration::from_secs(POLL_SECONDS)).await;
+        res = send(storage_client, Method::HEAD, &target_url, "", sas_token, None).await?;
+        if debug {
+            println!("copy progress = {}", header(&res, "x-ms-copy-progress").unwrap_or_default());
+        }
+    }
+}
+
+// Send a request authorized by the SAS token, and fail if the response is not successful
+async fn send(storage_client: &StorageClient, method: Method, url: &Url, query: &str, sas_token: &Secret,
+    copy_source: Option<&str>) -> Result<Response<Bytes>, Box<dyn Error + Send + Sync>>
+{
+    let query = [query, sas_token.expose()].iter().filter(|v| !v.is_empty()).cloned().collect::<Vec<_>>().join("&");
+    let mut request = Request::builder()
+        .method(method)
+        .uri(format!("{}?{}", url, query))
+        .header("x-ms-version", API_VERSION)
+        .header("Content-Length", "0");
+    if let Some(copy_source) = copy_source {
+        request = request.header("x-ms-copy-source", copy_source);
+    }
+
+    let res = storage_client
+        .storage_account_client()
+        .http_client()
+        .execute_request(request.body(Bytes::new())?)
+        .await?;
+    if !res.status().is_success() {
+        return Err(anyhow!("{} {}: {}", res.status(), url, String::from_utf8_lossy(res.body())).into());
+    }
+    Ok(res)
+}
+
+// Value of a response header
+fn header(res: &Response<Bytes>, name: &str) -> Result<String, Box<dyn Error + Send + Sync>> {
+    res.headers()
+        .get(name)
+        .and_then(|v| v.to_str().ok())
+        .map(String::from)
+        .ok_or(anyhow!("No {} header in the response", name).into())
+}
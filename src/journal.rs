@@ -0,0 +1,92 @@
+use std::io::prelude::*;
+use std::collections::HashSet;
+use std::fs::{self, File, OpenOptions};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::error::Error;
+use anyhow::anyhow;
+use serde_json::Value;
+
+// Journal of blocks transferred by put or get of a large file, to resume an interrupted transfer
+// by skipping transferred blocks. The first line is the header in JSON which identifies the transfer,
+// followed by the index of each transferred block per line.
+// Lines are only appended, so the journal is valid even if the process is killed while writing it.
+pub struct BlockJournal {
+    path: PathBuf,
+    file: Mutex<File>,
+    done: HashSet<u64>,
+}
+
+impl BlockJournal {
+    // Journal file of putting a blob, in the current directory
+    pub fn path(container: &str, blob: &str) -> PathBuf {
+        PathBuf::from(format!(".{}_{}.blocks", container, blob.replace('/', "_")))
+    }
+
+    // Open the journal of the transfer identified by the header.
+    // If resuming and the journal has the same header, blocks transferred by the last run are kept.
+    // Otherwise the journal is started over.
+    pub fn open(path: &Path, header: &Value, resume: bool) -> Result<BlockJournal, Box<dyn Error + Send + Sync>> {
+        let done = match resume {
+            true => read_done(path, header)?,
+            false => None
+        };
+        let file = match done {
+            Some(_) => {
+                // Terminate a line partially written when killed, which is ignored on read
+                let mut file = OpenOptions::new().append(true).open(path)?;
+                writeln!(file)?;
+                file
+            },
+            None => {
+                let mut file = File::create(path)?;
+                writeln!(file, "{}", serde_json::to_string(header)?)?;
+                file
+            }
+        };
+        Ok(BlockJournal { path: path.into(), file: Mutex::new(file), done: done.unwrap_or_default() })
+    }
+
+    // Number of blocks transferred by the last run
+    pub fn resumed(&self) -> usize {
+        self.done.len()
+    }
+
+    pub fn is_done(&self, index: u64) -> bool {
+        self.done.contains(&index)
+    }
+
+    // Record a transferred block
+    pub fn add(&self, index: u64) -> io::Result<()> {
+        let mut file = self.file.lock().unwrap();
+        writeln!(file, "{}", index)?;
+        file.flush()
+    }
+
+    // Remove the journal after the transfer completes
+    pub fn remove(self) -> io::Result<()> {
+        drop(self.file);
+        fs::remove_file(&self.path)
+    }
+}
+
+// Transferred blocks in the journal, or None if it does not exist or is of another transfer
+fn read_done(path: &Path, header: &Value) -> Result<Option<HashSet<u64>>, Box<dyn Error + Send + Sync>> {
+    let data = match fs::read_to_string(path) {
+        Ok(data) => data,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(anyhow!("Cannot read journal {:?}: {}", path, e).into())
+    };
+
+    // The last line without newline is partially written when killed, so it is ignored
+    let mut lines: Vec<&str> = data.split('\n').collect();
+    lines.pop();
+    let mut lines = lines.into_iter();
+    match lines.next().map(serde_json::from_str::<Value>) {
+        Some(Ok(last)) if last == *header => {},
+        _ => return Ok(None)
+    }
+    let done = lines.filter_map(|line| line.trim().parse().ok()).collect();
+    Ok(Some(done))
+}
@@ -0,0 +1,138 @@
+use azure_core::prelude::*;
+use azure_storage::blob::prelude::*;
+use azure_storage::core::prelude::*;
+
+use std::error::Error;
+use anyhow::anyhow;
+use http::StatusCode;
+use crate::audit::AuditLog;
+use crate::transport;
+
+// Key-value store of small JSON values, stored as blobs "<prefix><key>".
+// Updates can be made conditional on the ETag returned by get, for optimistic concurrency.
+pub struct KvStore<'a> {
+    storage_client: &'a StorageClient,
+    container: &'a str,
+    prefix: &'a str,
+    audit: &'a AuditLog,
+}
+
+impl<'a> KvStore<'a> {
+    pub fn new(storage_client: &'a StorageClient, container: &'a str, prefix: &'a str, audit: &'a AuditLog) -> Self {
+        KvStore { storage_client, container, prefix, audit }
+    }
+
+    fn blob_name(&self, key: &str) -> String {
+        format!("{}{}", self.prefix, key)
+    }
+
+    fn blob_client(&self, key: &str) -> std::sync::Arc<BlobClient> {
+        self.storage_client
+            .as_container_client(self.container)
+            .as_blob_client(self.blob_name(key))
+    }
+
+    // Get the value and its ETag
+    pub async fn get(&self, key: &str) -> Result<(serde_json::Value, String), Box<dyn Error + Send + Sync>> {
+        let res = self.blob_client(key).get().execute().await
+            .map_err(|e| not_found_or(e, key))?;
+        let value = serde_json::from_slice(&res.data)
+            .map_err(|e| anyhow!("Value of '{}' is not JSON: {}", key, e))?;
+        Ok((value, res.blob.properties.etag.to_string()))
+    }
+
+    // Set the value and return the new ETag.
+    // If the ETag is given, fails unless the value is not modified since it is got.
+    pub async fn set(&self, key: &str, value: &serde_json::Value, if_match: Option<&str>)
+        -> Result<String, Box<dyn Error + Send + Sync>>
+    {
+        let condition = if_match.map(|etag| IfMatchCondition::Match(etag.to_string()));
+        self.put(key, value, condition).await.map_err(|e| conflict_or(e, key))
+    }
+
+    // Set the value only if the key does not exist yet, and return the new ETag.
+    // Of concurrent sets of a new key, only one succeeds.
+    pub async fn set_if_absent(&self, key: &str, value: &serde_json::Value)
+        -> Result<String, Box<dyn Error + Send + Sync>>
+    {
+        self.put(key, value, Some(IfMatchCondition::NotMatch("*".into()))).await
+            .map_err(|e| match transport::error_status(e.as_ref()) {
+                Some(StatusCode::PRECONDITION_FAILED | StatusCode::CONFLICT) =>
+                    anyhow!("Key '{}' already exists", key).into(),
+                _ => e
+            })
+    }
+
+    async fn put(&self, key: &str, value: &serde_json::Value, condition: Option<IfMatchCondition>)
+        -> Result<String, Box<dyn Error + Send + Sync>>
+    {
+        let body = serde_json::to_vec(value)?;
+        let hash = md5::compute(&body).into();
+        let blob_client = self.blob_client(key);
+        let mut request = blob_client
+            .put_block_blob(body)
+            .content_type("application/json")
+            .hash(&hash);
+        if let Some(condition) = condition {
+            request = request.if_match_condition(condition);
+        }
+
+        let res = request.execute().await?;
+        self.audit.record(self.storage_client, "kv-set", self.container, &self.blob_name(key), &res.request_id).await?;
+        Ok(res.etag.to_string())
+    }
+
+    // Delete the value. If the ETag is given, fails unless the value is not modified since it is got.
+    pub async fn delete(&self, key: &str, if_match: Option<&str>) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let blob_client = self.blob_client(key);
+        let mut request = blob_client.delete();
+        if let Some(etag) = if_match {
+            request = request.if_match_condition(IfMatchCondition::Match(etag.to_string()));
+        }
+
+        let res = request.execute().await.map_err(|e| conflict_or(not_found_or(e, key), key))?;
+        self.audit.record(self.storage_client, "kv-delete", self.container, &self.blob_name(key), &res.request_id).await
+    }
+}
+
+fn not_found_or(e: Box<dyn Error + Send + Sync>, key: &str) -> Box<dyn Error + Send + Sync> {
+    match transport::is_not_found(e.as_ref()) {
+        true => anyhow!("Key '{}' is not found", key).into(),
+        false => e
+    }
+}
+
+fn conflict_or(e: Box<dyn Error + Send + Sync>, key: &str) -> Box<dyn Error + Send + Sync> {
+    match transport::error_status(e.as_ref()) {
+        Some(StatusCode::PRECONDITION_FAILED) => anyhow!("Value of '{}' was modified by another (ETag mismatch)", key).into(),
+        _ => e
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Configs;
+    use crate::mock;
+
+    #[tokio::test]
+    async fn set_if_absent_fails_if_key_exists() {
+        let (storage_client, http_client) = mock::storage_client(|request| {
+            match request.header("if-none-match") {
+                Some("*") => mock::status(StatusCode::CONFLICT),
+                _ => mock::status(StatusCode::CREATED)
+            }
+        });
+        let audit = AuditLog::new(&Configs::default()).unwrap();
+        let kv = KvStore::new(&storage_client, "site01", "kv/", &audit);
+
+        let e = kv.set_if_absent("settings", &serde_json::json!({"threshold": 10})).await.unwrap_err();
+        assert_eq!(e.to_string(), "Key 'settings' already exists");
+        let requests = http_client.requests();
+        assert!(requests[0].uri.path().ends_with("/site01/kv/settings"));
+        assert_eq!(requests[0].header("if-none-match"), Some("*"));
+
+        kv.set("settings", &serde_json::json!({"threshold": 20}), None).await.unwrap();
+        assert_eq!(http_client.requests()[1].header("if-none-match"), None);
+    }
+}
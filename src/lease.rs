@@ -0,0 +1,123 @@
+use azure_core::prelude::*;
+use azure_storage::blob::prelude::*;
+use azure_storage::core::prelude::*;
+
+use std::error::Error;
+use std::process::Stdio;
+use anyhow::anyhow;
+use azst::BlobLock;
+use http::StatusCode;
+use tokio::process::Command;
+use tokio::time::{self, Duration};
+use crate::reload::Reload;
+use crate::transport;
+
+const LEASE_SECONDS: u64 = 60;
+
+// Acquire a lease on the blob and run a command by the shell while holding it.
+// The lease is renewed while the command is running, and the command is killed if the lease is lost.
+// If the lease is held by another, retries at the interval, or fails if the interval is 0.
+// The config is reloaded on SIGHUP, and the lease is renewed by the reloaded one without stopping the command.
+pub async fn elect(storage_client: &StorageClient, container: &str, blob: &str, command: &str,
+    interval: u64, reload: &mut Reload, debug: bool) -> Result<(), Box<dyn Error + Send + Sync>>
+{
+    let mut blob_client = storage_client
+        .as_container_client(container)
+        .as_blob_client(blob);
+
+    // A lease requires the blob to exist. It is created only if not exist (If-None-Match: *), since another
+    // device may create it and hold the lease in between, which a plain put would fail on or overwrite.
+    if let Err(e) = blob_client.get_properties().execute().await {
+        if !transport::is_not_found(e.as_ref()) {
+            return Err(e);
+        }
+        let res = blob_client.put_block_blob(Vec::new())
+            .if_match_condition(IfMatchCondition::NotMatch("*".into()))
+            .execute()
+            .await;
+        match res {
+            Ok(_) => {},
+            Err(e) if matches!(transport::error_status(e.as_ref()),
+                Some(StatusCode::CONFLICT | StatusCode::PRECONDITION_FAILED)) => {},
+            Err(e) => return Err(e)
+        }
+    }
+
+    // Wait until this device becomes the leader
+    let mut lock = loop {
+        match BlobLock::acquire(blob_client.clone(), Duration::from_secs(LEASE_SECONDS)).await {
+            Ok(lock) => break lock,
+            Err(e) if interval > 0 => {
+                if debug {
+                    println!("Cannot acquire lease: {}", e);
+                }
+                time::sleep(Duration::from_secs(interval)).await;
+                if let Some(reloaded) = reload.poll().await {
+                    blob_client = reloaded.as_container_client(container).as_blob_client(blob);
+                }
+            },
+            Err(e) => return Err(anyhow!("Cannot acquire lease on '{}': {}", blob, e).into())
+        }
+    };
+    println!("Acquired lease {} on '{}'", lock.lease_id(), blob);
+
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .stdin(Stdio::null())
+        .kill_on_drop(true)
+        .spawn()?;
+
+    let status = loop {
+        tokio::select! {
+            status = child.wait() => break status?,
+            _ = lock.lost() => {
+                child.kill().await?;
+                return Err(anyhow!("Lease on '{}' is lost", blob).into());
+            },
+            reloaded = reload.reloaded() => {
+                lock.set_blob_client(reloaded.as_container_client(container).as_blob_client(blob));
+            }
+        }
+    };
+
+    lock.release().await?;
+    if !status.success() {
+        return Err(anyhow!("Command exited with {}", status).into());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http::Method;
+    use crate::mock;
+
+    // The lease blob is created by another device between the check and the create
+    fn handler(request: &mock::Recorded) -> http::Response<bytes::Bytes> {
+        match (&request.method, request.header("x-ms-lease-action")) {
+            (&Method::HEAD, _) => mock::status(StatusCode::NOT_FOUND),
+            (&Method::PUT, Some("acquire")) => mock::response(StatusCode::CREATED)
+                .header("x-ms-lease-id", "5c8b7a46-9a3b-4d9e-9a0e-4a2e1f0f4c11")
+                .header("content-length", "0")
+                .body(bytes::Bytes::new())
+                .unwrap(),
+            (&Method::PUT, Some(_)) => mock::status(StatusCode::OK),
+            (&Method::PUT, None) => mock::status(StatusCode::PRECONDITION_FAILED),
+            _ => mock::status(StatusCode::BAD_REQUEST)
+        }
+    }
+
+    #[tokio::test]
+    async fn elect_creates_lease_blob_only_if_not_exist() {
+        let (storage_client, http_client) = mock::storage_client(handler);
+        let mut reload = Reload::new(|| Err(anyhow!("Not reloaded").into()));
+        elect(&storage_client, "locks", "leader", "true", 0, &mut reload, false).await.unwrap();
+
+        let requests = http_client.requests();
+        let create = requests.iter().find(|request| request.header("x-ms-blob-type").is_some()).unwrap();
+        assert_eq!(create.header("if-none-match"), Some("*"));
+        assert!(requests.iter().any(|request| request.header("x-ms-lease-action") == Some("acquire")));
+    }
+}
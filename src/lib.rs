@@ -0,0 +1,10 @@
+// Reusable parts of azure-storage for other services
+pub mod lock;
+pub mod naming;
+pub mod transform;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+
+pub use lock::BlobLock;
+pub use naming::NamingPolicy;
+pub use transform::{Pipeline, Transform};
@@ -0,0 +1,64 @@
+use azure_storage::core::prelude::*;
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::error::Error;
+use anyhow::anyhow;
+use serde::{Deserialize, Serialize};
+
+// Blob in a saved listing
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+struct Entry {
+    size: u64,
+    etag: String,
+    last_modified: String,
+}
+
+// Compare the blobs with the prefix in the container with the listing saved in the file by the last run,
+// and print blobs added (+), removed (-) and changed (~). The file is replaced with the current listing,
+// so that the next run shows the changes since this run. A listing file which does not exist is empty.
+pub async fn list_diff(storage_client: &StorageClient, container: &str, prefix: &str, listing: &Path)
+    -> Result<(), Box<dyn Error + Send + Sync>>
+{
+    let previous: BTreeMap<String, Entry> = match fs::read(listing) {
+        Ok(data) => serde_json::from_slice(&data).map_err(|e| anyhow!("Cannot parse listing {:?}: {}", listing, e))?,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => BTreeMap::new(),
+        Err(e) => return Err(anyhow!("Cannot read listing {:?}: {}", listing, e).into())
+    };
+    let current: BTreeMap<String, Entry> = crate::list_all_blobs(storage_client, container, Some(prefix)).await?
+        .into_iter()
+        .map(|blob| (blob.name, Entry {
+            size: blob.properties.content_length,
+            etag: blob.properties.etag.to_string(),
+            last_modified: blob.properties.last_modified.to_rfc3339(),
+        }))
+        .collect();
+
+    let (mut added, mut removed, mut changed) = (0, 0, 0);
+    for (name, entry) in current.iter() {
+        match previous.get(name) {
+            None => {
+                println!("+ {} {:>10} {}", entry.last_modified, entry.size, name);
+                added += 1;
+            },
+            Some(old) if old != entry => {
+                println!("~ {} {:>10} {} (was {} bytes at {})", entry.last_modified, entry.size, name, old.size,
+                    old.last_modified);
+                changed += 1;
+            },
+            Some(_) => {}
+        }
+    }
+    for (name, old) in previous.iter().filter(|(name, _)| !current.contains_key(*name)) {
+        println!("- {} {:>10} {}", old.last_modified, old.size, name);
+        removed += 1;
+    }
+
+    fs::write(listing, serde_json::to_vec_pretty(&current)?)
+        .map_err(|e| anyhow!("Cannot write listing {:?}: {}", listing, e))?;
+    println!("{} added, {} removed, {} changed of {} blobs in '{}/{}' since the listing {:?}", added, removed, changed,
+        current.len(), container, prefix, listing);
+    Ok(())
+}
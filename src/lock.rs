@@ -0,0 +1,159 @@
+use azure_storage::blob::prelude::*;
+
+use std::sync::Arc;
+use std::error::Error;
+use anyhow::anyhow;
+use tokio::runtime::Handle;
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+use tokio::time::{self, Duration, Instant};
+
+// Delay between retries of a failed renewal
+const RENEW_RETRY_DELAY: Duration = Duration::from_secs(1);
+
+/// Distributed lock by a lease on a blob.
+///
+/// The lease is renewed in background while the lock is held. The lease is released by `release()`,
+/// or on a best effort basis when the lock is dropped. The blob must exist before acquiring the lock.
+pub struct BlobLock {
+    lease_id: LeaseId,
+    lease_client: Arc<BlobLeaseClient>,
+    // Lease client which the renewer uses, replaced by `set_blob_client()`
+    renew_client: watch::Sender<Arc<BlobLeaseClient>>,
+    lost: watch::Receiver<bool>,
+    renewer: JoinHandle<()>,
+    released: bool,
+}
+
+impl BlobLock {
+    /// Acquire a lease on the blob for the duration (15 to 60 seconds).
+    /// Fails if the lease is held by another.
+    pub async fn acquire(blob_client: Arc<BlobClient>, duration: Duration)
+        -> Result<BlobLock, Box<dyn Error + Send + Sync>>
+    {
+        let seconds = lease_seconds(duration)?;
+        let acquired = Instant::now();
+        let res = blob_client
+            .acquire_lease(LeaseDuration::Seconds(seconds))
+            .execute()
+            .await?;
+        let lease_id = res.lease_id;
+        let lease_client = blob_client.as_blob_lease_client(lease_id);
+
+        // Renew at a third of the duration. Failed renewals are retried until the duration passes since the last
+        // successful one, when the lease expires, so that transient failures do not lose the lease.
+        let (lost_tx, lost) = watch::channel(false);
+        let (renew_client, renew_client_rx) = watch::channel(lease_client.clone());
+        let renewer = tokio::spawn(async move {
+            let mut renewed = acquired;
+            let mut delay = duration / 3;
+            loop {
+                time::sleep(delay).await;
+                let start = Instant::now();
+                let lease_client = renew_client_rx.borrow().clone();
+                let succeeded = lease_client.renew().execute().await.is_ok();
+                if succeeded {
+                    renewed = start;
+                }
+                match renew_delay(succeeded, renewed.elapsed(), duration) {
+                    Some(next) => delay = next,
+                    None => {
+                        let _ = lost_tx.send(true);
+                        return;
+                    }
+                }
+            }
+        });
+
+        Ok(BlobLock { lease_id, lease_client, renew_client, lost, renewer, released: false })
+    }
+
+    pub fn lease_id(&self) -> &LeaseId {
+        &self.lease_id
+    }
+
+    /// Renew and release the lease by the blob client from now on, such as one with rotated credentials.
+    /// The blob client must be of the same blob.
+    pub fn set_blob_client(&mut self, blob_client: Arc<BlobClient>) {
+        self.lease_client = blob_client.as_blob_lease_client(self.lease_id);
+        let _ = self.renew_client.send(self.lease_client.clone());
+    }
+
+    /// Whether the lease could not be renewed before it expired
+    pub fn is_lost(&self) -> bool {
+        *self.lost.borrow()
+    }
+
+    /// Wait until the lease is lost
+    pub async fn lost(&mut self) {
+        while !*self.lost.borrow() {
+            if self.lost.changed().await.is_err() {
+                return;
+            }
+        }
+    }
+
+    /// Release the lease
+    pub async fn release(mut self) -> Result<(), Box<dyn Error + Send + Sync>> {
+        self.released = true;
+        self.renewer.abort();
+        self.lease_client.release().execute().await?;
+        Ok(())
+    }
+}
+
+// Duration of a lease in whole seconds, which the service accepts from 15 to 60
+fn lease_seconds(duration: Duration) -> Result<u8, Box<dyn Error + Send + Sync>> {
+    if !(15..=60).contains(&duration.as_secs()) || duration.subsec_nanos() != 0 {
+        return Err(anyhow!("Invalid lease duration {:?}. Specify 15 to 60 seconds", duration).into());
+    }
+    Ok(duration.as_secs() as u8)
+}
+
+// Delay before the next renewal, after a renewal succeeded or failed with the time since the last successful one.
+// None if the lease expires before a retry.
+fn renew_delay(succeeded: bool, since_renewed: Duration, duration: Duration) -> Option<Duration> {
+    match succeeded {
+        true => Some(duration / 3),
+        false if since_renewed + RENEW_RETRY_DELAY < duration => Some(RENEW_RETRY_DELAY),
+        false => None
+    }
+}
+
+impl Drop for BlobLock {
+    fn drop(&mut self) {
+        if self.released {
+            return;
+        }
+        self.renewer.abort();
+        let lease_client = self.lease_client.clone();
+        if let Ok(handle) = Handle::try_current() {
+            handle.spawn(async move {
+                let _ = lease_client.release().execute().await;
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lease_seconds_of_valid_durations() {
+        assert_eq!(lease_seconds(Duration::from_secs(15)).unwrap(), 15);
+        assert_eq!(lease_seconds(Duration::from_secs(60)).unwrap(), 60);
+        assert!(lease_seconds(Duration::from_secs(14)).is_err());
+        assert!(lease_seconds(Duration::from_secs(300)).is_err());
+        assert!(lease_seconds(Duration::from_millis(20500)).is_err());
+    }
+
+    #[test]
+    fn renewal_is_retried_until_lease_expires() {
+        let duration = Duration::from_secs(15);
+        assert_eq!(renew_delay(true, Duration::ZERO, duration), Some(Duration::from_secs(5)));
+        assert_eq!(renew_delay(false, Duration::from_secs(5), duration), Some(RENEW_RETRY_DELAY));
+        assert_eq!(renew_delay(false, Duration::from_secs(13), duration), Some(RENEW_RETRY_DELAY));
+        assert_eq!(renew_delay(false, Duration::from_secs(14), duration), None);
+    }
+}
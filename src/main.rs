@@ -1,27 +1,85 @@
+// Modes take the transfer settings of the command line one by one, as the other modes do
+#![allow(clippy::too_many_arguments)]
+
 use azure_core::prelude::*;
 use azure_storage::blob::prelude::*;
 use azure_storage::core::prelude::*;
 
 use std::io::prelude::*;
-use std::io::BufReader;
 use std::fs::File;
 use std::env;
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use std::collections::BTreeMap;
 use std::error::Error;
 use std::fmt;
-use clap::{App, Arg, ArgGroup};
+use clap::{App, Arg, ArgGroup, ArgMatches};
 use anyhow::{Result, anyhow};
-use serde::Deserialize;
+use futures::stream::{self, StreamExt, TryStreamExt};
+use regex::Regex;
+use chrono::{Duration, Utc};
+use rand::Rng;
+
+mod aad;
+mod after_upload;
+mod account;
+mod audit;
+mod bench;
+mod block_hashes;
+mod chain;
+mod command_channel;
+mod config;
+mod device;
+mod download;
+mod expire;
+mod fetch_config;
+mod filter;
+mod hash;
+mod hash_cache;
+mod incremental_copy;
+mod journal;
+mod kv;
+mod lease;
+mod list_diff;
+mod manifest;
+mod metadata;
+#[cfg(test)]
+mod mock;
+mod output;
+mod preflight;
+mod progress;
+mod queue;
+mod quota;
+mod receipt;
+mod reload;
+mod replicate;
+mod rest;
+mod restore;
+mod rollover;
+mod secret;
+mod signature;
+mod signer;
+mod stats;
+mod sync;
+mod systemd;
+mod tail;
+mod transport;
+mod upload;
+mod verify;
+mod watch;
 
-#[derive(Deserialize, Default, Debug)]
-struct Configs {
-    storage_account: String,
-    storage_master_key: String,
-    local: String,
+// Lifetime of SAS tokens signed by the signing command
+const SAS_LIFETIME_HOURS: i64 = 24;
+
+fn main() {
+    // Errors of requests may show their URLs with the signature of the SAS token
+    if let Err(e) = run() {
+        eprintln!("Error: {}", secret::redact_signatures(&e.to_string()));
+        std::process::exit(1);
+    }
 }
 
-fn main() -> Result<(), Box<dyn Error + Send + Sync>> {
+fn run() -> Result<(), Box<dyn Error + Send + Sync>> {
     // Parse command line arguments
     let args = App::new("azure-storage")
         // headers
@@ -36,8 +94,45 @@ fn main() -> Result<(), Box<dyn Error + Send + Sync>> {
         .arg(Arg::with_name("append").help("Append a file to existing append blob"))
         .arg(Arg::with_name("put-append").help("Create a new append blob to remote"))
         .arg(Arg::with_name("delete").help("Delete a blob from remote"))
+        .arg(Arg::with_name("find").help("Find blobs by name pattern"))
+        .arg(Arg::with_name("compare").help("Compare blobs in two containers"))
+        .arg(Arg::with_name("config-show").help("Show config files and parameters"))
+        .arg(Arg::with_name("config-init").help("Create a new config file"))
+        .arg(Arg::with_name("config-validate").help("Check config files and the access to the storage"))
+        .arg(Arg::with_name("poll-commands").help("Poll a command blob and run it when updated"))
+        .arg(Arg::with_name("elect").help("Run a command while holding a lease on a blob"))
+        .arg(Arg::with_name("publish").help("Append a message to an append blob queue"))
+        .arg(Arg::with_name("consume").help("Print new messages in an append blob queue"))
+        .arg(Arg::with_name("kv-get").help("Get a JSON value by key"))
+        .arg(Arg::with_name("kv-set").help("Set a JSON value by key"))
+        .arg(Arg::with_name("kv-delete").help("Delete a JSON value by key"))
+        .arg(Arg::with_name("bench").help("Measure upload throughput by block sizes and concurrency"))
+        .arg(Arg::with_name("fetch-config").help("Get a config blob and replace a local file if verified"))
+        .arg(Arg::with_name("incremental-copy").help("Copy a new snapshot of a page blob incrementally"))
+        .arg(Arg::with_name("account-info").help("Show the SKU and kind of the storage account"))
+        .arg(Arg::with_name("restore").help("Get the blobs recorded in a manifest and verify them"))
+        .arg(Arg::with_name("sync").help("Put only new or changed files in a local directory, or get only changed blobs"))
+        .arg(Arg::with_name("rest").help("Send a signed REST request and print the response"))
+        .arg(Arg::with_name("expire").help("Delete blobs past the expiry recorded by put --expires-in"))
+        .arg(Arg::with_name("stats-record").help("Append the number and the total size of blobs to a stats blob"))
+        .arg(Arg::with_name("stats-show").help("Show the growth of blobs recorded in a stats blob"))
+        .arg(Arg::with_name("verify").help("Check the content of blobs by the hashes recorded on put"))
+        .arg(Arg::with_name("cat").help("Write a blob to stdout"))
+        .arg(Arg::with_name("head").help("Print a byte range of a blob, the beginning by default"))
+        .arg(Arg::with_name("tail").help("Print the last lines or bytes of a blob, and content appended if following"))
+        .arg(Arg::with_name("preflight").help("Check whether the credentials allow an operation by probe requests"))
+        .arg(Arg::with_name("list-diff").help("Show blobs added, removed and changed since the listing saved by the last run"))
+        .arg(Arg::with_name("set-metadata").help("Set metadata on all blobs with a prefix, keeping other metadata"))
+        .arg(Arg::with_name("watch").help("Put files as they are closed or renamed into a local directory"))
+        .arg(Arg::with_name("push-outbox").help("Put new or changed files in a local directory to the outbox of this device"))
+        .arg(Arg::with_name("pull-inbox").help("Get and remove the messages in the inbox of this device"))
         .group(ArgGroup::with_name("mode")
-            .args(&["list", "get", "put", "append", "put-append", "delete"])
+            .args(&["list", "get", "put", "append", "put-append", "delete", "find", "compare", "config-show",
+                "config-init", "config-validate", "poll-commands", "elect", "publish", "consume",
+                "kv-get", "kv-set", "kv-delete", "bench", "fetch-config",
+                "incremental-copy", "account-info", "restore", "sync", "rest", "expire",
+                "stats-record", "stats-show", "verify", "cat", "head", "tail", "preflight", "list-diff",
+                "set-metadata", "watch", "push-outbox", "pull-inbox"])
             .required(true)
         )
 
@@ -62,97 +157,912 @@ fn main() -> Result<(), Box<dyn Error + Send + Sync>> {
             .help("STORAGE_ACCOUNT")
             .takes_value(true)
         )
+        .arg(Arg::with_name("endpoint")
+            .long("endpoint")
+            .help("Blob endpoint URL of the storage emulator (Azurite) with the account in the path, such as http://127.0.0.1:10000/devstoreaccount1")
+            .takes_value(true)
+        )
+        .arg(Arg::with_name("cloud")
+            .long("cloud")
+            .help("Azure cloud of the account, for the blob endpoint such as <account>.blob.core.usgovcloudapi.net [default: public]")
+            .takes_value(true)
+            .possible_values(&["public", "usgov", "china", "germany"])
+        )
+        .arg(Arg::with_name("endpoint suffix")
+            .long("endpoint-suffix")
+            .help("Endpoint suffix of the account instead of the one of the cloud, such as core.usgovcloudapi.net")
+            .takes_value(true)
+        )
+        .arg(Arg::with_name("blob endpoint")
+            .long("blob-endpoint")
+            .help("Blob endpoint URL instead of the one of the account, such as https://storage.example.com for a custom domain or a private link")
+            .takes_value(true)
+        )
+        .arg(Arg::with_name("connection string")
+            .long("connection-string")
+            .help("Connection string, such as UseDevelopmentStorage=true for Azurite, AZURE_STORAGE_CONNECTION_STRING")
+            .takes_value(true)
+        )
         .arg(Arg::with_name("storage master key")
             .short("k").long("storage_master_key")
             .help("STORAGE_MASTER_KEY")
             .takes_value(true)
         )
+        .arg(Arg::with_name("sas")
+            .long("sas")
+            .help("SAS token used instead of the master key, AZURE_STORAGE_SAS_TOKEN")
+            .takes_value(true)
+        )
+        .arg(Arg::with_name("auth")
+            .long("auth")
+            .help("Authentication by the master key, a SAS token or the signing command (key), an Azure AD service principal (aad), or the user logged in by Azure CLI (azure-cli) [default: key]")
+            .takes_value(true)
+            .possible_values(&["key", "aad", "azure-cli"])
+        )
+        .arg(Arg::with_name("prefix")
+            .long("prefix")
+            .help("Blob name prefix to get, put, sync or compare, of key-value blobs, or of append blobs rolled over [default for kv: kv/]")
+            .takes_value(true)
+        )
+        .arg(Arg::with_name("target container")
+            .long("target_container")
+            .help("Container name to compare with, or to copy to")
+            .takes_value(true)
+        )
+        .arg(Arg::with_name("target blob")
+            .long("target_blob")
+            .help("Blob name to copy to [default: same as --blob]")
+            .takes_value(true)
+        )
+        .arg(Arg::with_name("target prefix")
+            .long("target_prefix")
+            .help("Blob name prefix to compare with [default: same as --prefix]")
+            .takes_value(true)
+        )
+        .arg(Arg::with_name("target account")
+            .long("target_storage_account")
+            .help("Storage account to compare with [default: same account]")
+            .takes_value(true)
+            .requires("target master key")
+        )
+        .arg(Arg::with_name("target master key")
+            .long("target_storage_master_key")
+            .help("Storage master key of the account to compare with")
+            .takes_value(true)
+            .requires("target account")
+        )
+        .arg(Arg::with_name("response blob")
+            .long("response_blob")
+            .help("Blob name to write command results to [default: <blob>.response]")
+            .takes_value(true)
+        )
+        .arg(Arg::with_name("overwrite")
+            .long("overwrite")
+            .help("Whether to overwrite existing local files (get with --prefix only)")
+            .takes_value(true)
+            .possible_values(&["always", "never", "if-newer", "if-different"])
+            .default_value("always")
+        )
+        .arg(Arg::with_name("block hashes")
+            .long("block-hashes")
+            .help("Record the SHA-256 of each block of files put by blocks in <blob>.blocks, for verify --spot-check (put mode only)")
+        )
+        .arg(Arg::with_name("spot check")
+            .long("spot-check")
+            .help("Check only the number of random blocks of blobs with hashes recorded by put --block-hashes (verify mode only)")
+            .takes_value(true)
+        )
+        .arg(Arg::with_name("after upload")
+            .long("after-upload")
+            .help("What to do with local files after they are put and verified by read-back: keep, delete, move:<dir> or truncate (put, sync, watch and push-outbox modes only) [default: keep]")
+            .takes_value(true)
+        )
+        .arg(Arg::with_name("verify after upload")
+            .long("verify-after-upload")
+            .help("Read back put blobs and check their size and SHA-256 hash against the local files (put mode only)")
+        )
+        .arg(Arg::with_name("version id")
+            .long("version-id")
+            .help("Version ID of the blob to get (get mode only)")
+            .takes_value(true)
+            .conflicts_with("snapshot")
+        )
+        .arg(Arg::with_name("snapshot")
+            .long("snapshot")
+            .help("Snapshot timestamp of the blob to get, in RFC 3339 (get mode only)")
+            .takes_value(true)
+        )
+        .arg(Arg::with_name("verify-sig")
+            .long("verify-sig")
+            .help("Verify the minisign signature in <blob>.sig by verify_key before writing (get, fetch-config and restore modes only)")
+        )
+        .arg(Arg::with_name("sign")
+            .long("sign")
+            .help("Upload the minisign signature as <blob>.sig by sign_key or sign_command (put mode only)")
+        )
+        .arg(Arg::with_name("recursive")
+            .long("recursive")
+            .short("r")
+            .help("Put all files under the local directory, named by --prefix and the relative path (put mode only)")
+        )
+        .arg(Arg::with_name("chain")
+            .long("chain")
+            .help("Maintain (append mode) or verify (get mode) the hash chain of appends in <blob>.chain")
+        )
+        .arg(Arg::with_name("create container")
+            .long("create-container-if-missing")
+            .help("Create the container with the public access level if it does not exist (put, sync and watch modes only)")
+        )
+        .arg(Arg::with_name("public access")
+            .long("public-access")
+            .help("Public access level of the container created by --create-container-if-missing [default: none]")
+            .takes_value(true)
+            .possible_values(&["none", "blob", "container"])
+        )
+        .arg(Arg::with_name("expires in")
+            .long("expires-in")
+            .help("Record the expiry after the duration, such as 30d, in the metadata of put blobs to delete them by expire mode (put mode only)")
+            .takes_value(true)
+        )
+        .arg(Arg::with_name("replicate")
+            .long("replicate")
+            .help("Also put to the replicas in the config one by one or all at once, with the status of each (put mode only)")
+            .takes_value(true)
+            .possible_values(&["sequential", "parallel"])
+        )
+        .arg(Arg::with_name("rollover")
+            .long("rollover")
+            .help("Append to the blob of the current UTC window, named by --prefix, the window and the extension of the local file (append mode only)")
+            .takes_value(true)
+            .possible_values(&["hourly", "daily"])
+        )
+        .arg(Arg::with_name("receipt")
+            .long("receipt")
+            .help("Write the receipt of the got blob to <local>.receipt.json, signed if sign_key or sign_command is configured (get mode only)")
+        )
+        .arg(Arg::with_name("resume")
+            .long("resume")
+            .help("Resume an interrupted put or get of large files, skipping blocks already transferred")
+        )
+        .arg(Arg::with_name("no verify")
+            .long("no-verify")
+            .help("Do not verify got files by the hashes of blobs (get, sync, cat and pull-inbox modes only)")
+        )
+        .arg(Arg::with_name("no progress")
+            .long("no-progress")
+            .help("Do not show the progress of transfers, which is shown only if stderr is a terminal")
+        )
+        .arg(Arg::with_name("manifest")
+            .long("manifest")
+            .help("Local file to write the manifest of uploaded files to (put and sync modes), or to restore by (restore mode)")
+            .takes_value(true)
+        )
+        .arg(Arg::with_name("manifest blob")
+            .long("manifest_blob")
+            .help("Blob name in the container to upload the manifest of uploaded files to (put and sync modes only)")
+            .takes_value(true)
+        )
+        .arg(Arg::with_name("sign key")
+            .long("sign_key")
+            .help("Minisign secret key file to sign uploaded blobs")
+            .takes_value(true)
+        )
+        .arg(Arg::with_name("verify key")
+            .long("verify_key")
+            .help("Minisign public key or its file path to verify signatures")
+            .takes_value(true)
+        )
+        .arg(Arg::with_name("hash blob")
+            .long("hash_blob")
+            .help("Blob name of the SHA-256 hash to verify in fetch-config mode [default: <blob>.sha256]")
+            .takes_value(true)
+        )
+        .arg(Arg::with_name("command")
+            .long("command")
+            .help("Command to run while holding the lease (elect mode only)")
+            .takes_value(true)
+        )
+        .arg(Arg::with_name("message")
+            .short("m").long("message")
+            .help("Message to publish. The content of the local file is published if not specified")
+            .takes_value(true)
+        )
+        .arg(Arg::with_name("offset file")
+            .long("offset_file")
+            .help("File to save the offset of consumed messages [default: .<container>_<blob>.offset]")
+            .takes_value(true)
+        )
+        .arg(Arg::with_name("direction")
+            .long("direction")
+            .help("Direction to sync. up puts local files, down gets blobs (sync mode only)")
+            .takes_value(true)
+            .possible_values(&["up", "down"])
+            .default_value("up")
+        )
+        .arg(Arg::with_name("delete extras")
+            .long("delete")
+            .help("Delete blobs or local files which do not exist in the source (sync mode only)")
+        )
+        .arg(Arg::with_name("delete uploaded")
+            .long("delete-uploaded")
+            .help("Delete local files after they are put and verified, same as --after-upload=delete (watch mode only)")
+            .conflicts_with_all(&["move uploaded", "after upload"])
+        )
+        .arg(Arg::with_name("move uploaded")
+            .long("move-uploaded")
+            .help("Move local files to \"uploaded/\" under the watched directory after they are put and verified (watch mode only)")
+            .conflicts_with("after upload")
+        )
+        .arg(Arg::with_name("dry run")
+            .long("dry-run")
+            .help("Only print what would be put, got, deleted and changed (sync, expire, set-metadata, push-outbox and pull-inbox modes only)")
+        )
+        .arg(Arg::with_name("metadata")
+            .long("metadata")
+            .help("Metadata to set, as <name>=<value> (set-metadata mode only)")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1)
+        )
+        .arg(Arg::with_name("filter meta")
+            .long("filter-meta")
+            .help("Get only blobs with the metadata, as <name>=<value>. Repeat for multiple names (get with --prefix and sync down only)")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1)
+        )
+        .arg(Arg::with_name("modified after")
+            .long("modified-after")
+            .help("Get only blobs last modified after the time, as RFC 3339 or the duration ago such as 7d (get with --prefix and sync down only)")
+            .takes_value(true)
+        )
+        .arg(Arg::with_name("modified before")
+            .long("modified-before")
+            .help("Get only blobs last modified before the time, as RFC 3339 or the duration ago such as 7d (get with --prefix and sync down only)")
+            .takes_value(true)
+        )
+        .arg(Arg::with_name("method")
+            .long("method")
+            .help("HTTP method of the request (rest mode only)")
+            .takes_value(true)
+            .default_value("GET")
+        )
+        .arg(Arg::with_name("path")
+            .long("path")
+            .help("Path and query of the request relative to the blob endpoint, such as /<container>/<blob>?comp=metadata (rest mode only)")
+            .takes_value(true)
+        )
+        .arg(Arg::with_name("state file")
+            .long("state_file")
+            .help("File to save the state of blobs got by sync down, the continuation of list, or the listing of list-diff [default: .<container>_<prefix>.sync, .<container>.list, .<container>_<prefix>.listing]")
+            .takes_value(true)
+        )
+        .arg(Arg::with_name("time budget")
+            .long("time-budget")
+            .help("Stop listing blobs within the duration, such as 5m, and resume from there by the next run (list mode only)")
+            .takes_value(true)
+        )
+        .arg(Arg::with_name("output blob")
+            .long("output-blob")
+            .help("Write the listed blobs or containers to the blob in the same account instead of stdout, as <container>/<blob> (list mode only)")
+            .takes_value(true)
+        )
+        .arg(Arg::with_name("continuation token")
+            .long("continuation-token")
+            .help("Continuation token printed by the last list to list the next page from (list mode only)")
+            .takes_value(true)
+        )
+        .arg(Arg::with_name("operation")
+            .long("operation")
+            .help("Operation to check the permissions for in the container (preflight mode only)")
+            .takes_value(true)
+            .possible_values(&["list", "find", "get", "cat", "head", "tail", "verify", "put", "append", "delete", "sync",
+                "expire", "set-metadata", "watch", "push-outbox", "pull-inbox"])
+        )
+        .arg(Arg::with_name("key")
+            .long("key")
+            .help("Key of the value (kv modes only)")
+            .takes_value(true)
+        )
+        .arg(Arg::with_name("value")
+            .long("value")
+            .help("JSON value to set (kv-set mode only)")
+            .takes_value(true)
+        )
+        .arg(Arg::with_name("if-match")
+            .long("if-match")
+            .help("Update only if the ETag matches (kv-set and kv-delete modes only)")
+            .takes_value(true)
+        )
+        .arg(Arg::with_name("if-absent")
+            .long("if-absent")
+            .help("Set only if the key does not exist yet (kv-set mode only)")
+            .conflicts_with("if-match")
+        )
+        .arg(Arg::with_name("bench size")
+            .long("bench_size")
+            .help("Size of data to upload in bench mode")
+            .takes_value(true)
+            .default_value("64M")
+        )
+        .arg(Arg::with_name("bench block sizes")
+            .long("bench_block_sizes")
+            .help("Comma separated block sizes to measure in bench mode")
+            .takes_value(true)
+            .default_value("1M,4M,8M,16M")
+        )
+        .arg(Arg::with_name("bench concurrency")
+            .long("bench_concurrency")
+            .help("Comma separated concurrency levels to measure in bench mode")
+            .takes_value(true)
+            .default_value("1,2,4,8")
+        )
+        .arg(Arg::with_name("interval")
+            .long("interval")
+            .help("Polling or retry interval in seconds. Tries only once if 0")
+            .takes_value(true)
+            .default_value("60")
+        )
+        .arg(Arg::with_name("lines")
+            .long("lines")
+            .help("Number of the last lines to print (tail mode only)")
+            .takes_value(true)
+            .default_value("10")
+        )
+        .arg(Arg::with_name("bytes")
+            .long("bytes")
+            .help("Size of the beginning (head mode) or the end instead of lines (tail mode) to print, such as 1K [default for head: 1K]")
+            .takes_value(true)
+        )
+        .arg(Arg::with_name("range")
+            .long("range")
+            .help("Byte range to print as <start>-<end> inclusive, or <start>- to the end, such as 1M-2M (head mode only)")
+            .takes_value(true)
+            .conflicts_with("bytes")
+        )
+        .arg(Arg::with_name("follow")
+            .long("follow")
+            .help("Poll the length of the blob by --interval and print content appended (tail mode only) [default interval: 2]")
+        )
+        .arg(Arg::with_name("pattern")
+            .short("p").long("pattern")
+            .help("Blob name pattern to find (regular expression)")
+            .takes_value(true)
+        )
+        .arg(Arg::with_name("glob")
+            .long("glob")
+            .help("Treat the find pattern as a glob instead of a regular expression")
+        )
+        .arg(Arg::with_name("all-containers")
+            .long("all-containers")
+            .help("List blobs in all containers (list mode only)")
+            .conflicts_with("container")
+        )
+        .arg(Arg::with_name("concurrency")
+            .long("concurrency")
+            .help("Maximum number of concurrent requests [default: 4, 16 for premium block blob accounts]")
+            .takes_value(true)
+        )
+        .arg(Arg::with_name("block size")
+            .long("block-size")
+            .help("Size of blocks to put, or ranges to get, files larger than it by, such as 16M [default: 4M, 16M for premium block blob accounts]")
+            .takes_value(true)
+        )
+        .arg(Arg::with_name("client request id")
+            .long("client-request-id")
+            .help("ID sent with all requests to correlate them in server side logs [default: random UUID]")
+            .takes_value(true)
+        )
+        .arg(Arg::with_name("max requests per second")
+            .long("max-requests-per-second")
+            .help("Maximum number of requests per second")
+            .takes_value(true)
+        )
+        .arg(Arg::with_name("limit rate")
+            .long("limit-rate")
+            .help("Maximum throughput of uploads and downloads, such as 10MB/s")
+            .takes_value(true)
+        )
+        .arg(Arg::with_name("retries")
+            .long("retries")
+            .help("Maximum number of retries of a request failed by a connection error, a timeout or a transient status [default: 3]")
+            .takes_value(true)
+        )
+        .arg(Arg::with_name("start jitter")
+            .long("start-jitter")
+            .help("Wait for a random time up to the seconds before starting")
+            .takes_value(true)
+        )
+        .arg(Arg::with_name("sd-notify")
+            .long("sd-notify")
+            .help("Notify readiness and watchdog to systemd")
+        )
+        .arg(Arg::with_name("warmup")
+            .long("warmup")
+            .help("Resolve DNS once and connect to the storage before starting operations")
+        )
+        .arg(Arg::with_name("low memory")
+            .long("low-memory")
+            .help("Transfer by small blocks one by one regardless of other settings, for devices with little memory")
+        )
+        .arg(Arg::with_name("tls min version")
+            .long("tls-min-version")
+            .help("Minimum TLS version to connect by. TLS below 1.2 is never used [default: 1.2]")
+            .takes_value(true)
+            .possible_values(&["1.2", "1.3"])
+        )
+        .arg(Arg::with_name("ca cert")
+            .long("ca-cert")
+            .help("PEM file of root CA certificates trusted in addition to the built-in ones, such as of a TLS-intercepting proxy")
+            .takes_value(true)
+        )
+        .arg(Arg::with_name("insecure")
+            .long("insecure")
+            .help("Skip verification of server certificates. Only for troubleshooting")
+        )
+        .arg(Arg::with_name("tls client cert")
+            .long("tls-client-cert")
+            .help("PEM file of the client certificate presented to gateways which require mutual TLS, with the private key unless --tls-client-key")
+            .takes_value(true)
+        )
+        .arg(Arg::with_name("tls client key")
+            .long("tls-client-key")
+            .help("PEM file of the private key of the client certificate")
+            .takes_value(true)
+        )
+        .arg(Arg::with_name("ip version")
+            .long("ip-version")
+            .help("IP version to connect by. auto races IPv6 and IPv4")
+            .takes_value(true)
+            .possible_values(&["4", "6", "auto"])
+        )
+        .arg(Arg::with_name("resolve")
+            .long("resolve")
+            .help("Connect to the host by the IP address instead of DNS, as <host>:<ip>. IPv6 may be in brackets")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1)
+            .validator(|v| transport::parse_resolve(&v).map(|_| ()).map_err(|e| e.to_string()))
+        )
+        .arg(Arg::with_name("inject faults")
+            .long("inject-faults")
+            .help("Inject failures into requests for resilience testing, as drop-after=<size>,status-every=<n>")
+            .takes_value(true)
+            .hidden(true)
+        )
+        .arg(Arg::with_name("hash algorithm")
+            .long("hash-algorithm")
+            .help("Hash algorithm to compare local files with blobs, stored in the metadata if not md5 or transformed")
+            .takes_value(true)
+            .possible_values(&["md5", "sha256", "blake3"])
+        )
+        .arg(Arg::with_name("hash cache")
+            .long("hash_cache")
+            .help("File to cache hashes of local files across runs, to skip hashing unchanged files")
+            .takes_value(true)
+        )
+        .arg(Arg::with_name("header")
+            .long("header")
+            .help("Header added to all requests, as <name>: <value>")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1)
+        )
+        .arg(Arg::with_name("api version")
+            .long("api-version")
+            .help("Service version of requests (x-ms-version), as YYYY-MM-DD [default: version of the SDK]")
+            .takes_value(true)
+        )
         .arg(Arg::with_name("config")
             .long("config")
             .help("Config file path")
             .takes_value(true)
             .default_value("azure-storage.json")
         )
+        .arg(Arg::with_name("profile")
+            .long("profile")
+            .help("Profile in config files to use, such as dev or prod, with its own account, credentials and container")
+            .takes_value(true)
+        )
+        .arg(Arg::with_name("device id")
+            .long("device-id")
+            .help("ID of this device in the inbox and outbox prefixes (push-outbox and pull-inbox modes only)")
+            .takes_value(true)
+        )
+        .arg(Arg::with_name("resolved")
+            .long("resolved")
+            .help("Show the resolved config parameters (config-show mode only)")
+        )
+        .arg(Arg::with_name("interactive")
+            .long("interactive")
+            .help("Ask config parameters and check access to the storage (config-init mode only)")
+        )
         .arg(Arg::with_name("debug")
             .long("debug")
             .help("Enable debug print")
         )
         .get_matches();
 
-    // Read config parameters if exist
-    let mut cfg: Configs = match File::open(args.value_of("config").unwrap()) {
-        Ok(file) => serde_json::from_reader(BufReader::new(file))?,
-        Err(_) => Default::default()
-    };
+    let config_path = args.value_of("config").unwrap();
 
-    // Overwrite config parameters by command line options
-    args.value_of("storage account").map(|v| cfg.storage_account = v.into());
-    args.value_of("storage master key").map(|v| cfg.storage_master_key = v.into());
-    args.value_of("local").map(|v| cfg.local = v.into());
+    // Create a new config file. Written to the per-user config file unless --config is specified.
+    if args.value_of("mode") == Some("config-init") {
+        let path = match args.occurrences_of("config") {
+            0 => config::user_config_file().ok_or(anyhow!("Cannot find home directory"))?,
+            _ => PathBuf::from(config_path)
+        };
+
+        let mut cfg = config::Configs::default();
+        if args.is_present("interactive") {
+            cfg = config::prompt()?;
+            println!("Checking access to storage account '{}'", cfg.storage_account);
+            check_access(create_storage_client(&cfg)?)?;
+        }
+
+        config::write_template(&path, &cfg)?;
+        println!("Config file written to {}", path.display());
+        return Ok(());
+    }
+
+    // Check all config files before loading them, to report all problems at once
+    let validate = args.value_of("mode") == Some("config-validate");
+    if validate {
+        let problems: Vec<String> = config::config_files(config_path).iter()
+            .flat_map(|path| config::validate_file(path))
+            .collect();
+        if !problems.is_empty() {
+            return config::report(&problems);
+        }
+    }
+
+    // Read config parameters from environment variables, config files and command line options
+    let mut cfg = configs(config_path, &args)?;
+    cfg.client_request_id = args.value_of("client request id")
+        .map(String::from)
+        .unwrap_or_else(|| uuid::Uuid::new_v4().to_string());
 
     // debug print
     if args.is_present("debug") {
-        println!("{:#?}", cfg);
+        println!("{}", secret::redact_signatures(&format!("{:#?}", cfg)));
     }
 
-    // Get storage keys from environment variable if no config parameter
-    let account = match cfg.storage_account.as_str() {
-        "" => std::env::var("STORAGE_ACCOUNT").expect("STORAGE_ACCOUNT is not defined"),
-        _ => cfg.storage_account
-    };
+    // Show config files and the resolved config parameters. No storage access required.
+    if args.value_of("mode") == Some("config-show") {
+        println!("Config files (lowest precedence first)");
+        for path in config::config_files(config_path) {
+            println!(" {:<9} {}", if path.exists() { "found" } else { "not found" }, path.display());
+        }
+        if args.is_present("resolved") {
+            println!("\n{}", serde_json::to_string_pretty(&cfg)?);
+        }
+        return Ok(());
+    }
 
-    let master_key = match cfg.storage_master_key.as_str() {
-        "" => std::env::var("STORAGE_MASTER_KEY").expect("STORAGE_MASTER_KEY is not defined"),
-        _ => cfg.storage_master_key
-    };
+    // Check required parameters and the access to the storage with the resolved config
+    if validate {
+        let mut problems = Vec::new();
+        if let Err(e) = cfg.resolve_credentials() {
+            problems.push(e.to_string());
+        }
+        for entry in cfg.resolve.iter() {
+            if let Err(e) = transport::parse_resolve(entry) {
+                problems.push(e.to_string());
+            }
+        }
+        if cfg.storage_account.is_empty() {
+            problems.push("storage_account is not set. Set it in a config file, --storage_account or STORAGE_ACCOUNT".to_string());
+        }
+        if cfg.auth == "aad" {
+            if cfg.tenant_id.is_empty() || cfg.client_id.is_empty() || cfg.client_secret.is_empty() {
+                problems.push("tenant_id, client_id or client_secret is not set. Set them in a config file, or AZURE_TENANT_ID, AZURE_CLIENT_ID and AZURE_CLIENT_SECRET".to_string());
+            }
+        } else if cfg.auth != "azure-cli" && cfg.storage_master_key.is_empty() && cfg.sas_token.is_empty()
+            && cfg.signing_command.is_empty()
+        {
+            problems.push("storage_master_key is not set. Set it, sas_token, credential_command or signing_command in a config file, --storage_master_key, --sas, STORAGE_MASTER_KEY or AZURE_STORAGE_SAS_TOKEN".to_string());
+        }
+        if problems.is_empty() {
+            if let Err(e) = create_storage_client(&cfg).and_then(check_access) {
+                problems.push(format!("Cannot access storage account '{}': {}", cfg.storage_account, e));
+            }
+        }
+        return config::report(&problems);
+    }
+
+    // Get the master key by the credential command if not set
+    cfg.resolve_credentials()?;
+
+    // Spread start times of scheduled runs, so that devices with identical schedules
+    // do not access the storage account at the same moment
+    if let Some(jitter) = cfg.start_jitter.filter(|&jitter| jitter > 0) {
+        let delay = rand::thread_rng().gen_range(0..jitter * 1000);
+        if args.is_present("debug") {
+            println!("start jitter = {} ms", delay);
+        }
+        std::thread::sleep(std::time::Duration::from_millis(delay));
+    }
 
     // Create a storage client object
-    let http_client = new_http_client();
-    let storage_client =
-        StorageAccountClient::new_access_key(http_client, &account, &master_key).as_storage_client();
+    let storage_client = create_storage_client(&cfg)?;
 
-    // Perform Azure Storage access
-    let local = if cfg.local != "" { Some(cfg.local.as_str()) } else { None };
-    azure_storage(storage_client, 
-        args.value_of("mode"), 
-        args.value_of("container"), 
-        args.value_of("blob"), 
-        local, 
-        args.is_present("debug"))?;
+    // Create a storage client object again by the config reloaded on SIGHUP, with the same client request ID
+    let reload = {
+        let (config_path, args, client_request_id) = (config_path.to_string(), args.clone(), cfg.client_request_id.clone());
+        reload::Reload::new(move || {
+            let mut cfg = configs(&config_path, &args)?;
+            cfg.client_request_id = client_request_id.clone();
+            cfg.resolve_credentials()?;
+            create_storage_client(&cfg)
+        })
+    };
+
+    // Perform Azure Storage access. The client request ID is shown on failure to find the requests in server side logs.
+    azure_storage(storage_client, &cfg, &args, reload)
+        .map_err(|e| anyhow!("{} (client request ID: {})", e, cfg.client_request_id))?;
 
     Ok(())
 }
 
+// Load config parameters from environment variables and config files, and overwrite them by command line options.
+// Called again when the config is reloaded.
+fn configs(config_path: &str, args: &ArgMatches<'_>) -> Result<config::Configs, Box<dyn Error + Send + Sync>> {
+    let mut cfg = config::load(config_path, args.value_of("profile"), args.is_present("debug"))?;
+
+    // Overwrite config parameters by command line options
+    args.value_of("storage account").map(|v| cfg.storage_account = v.into());
+    args.value_of("storage master key").map(|v| cfg.storage_master_key = v.into());
+    args.value_of("sas").map(|v| cfg.sas_token = v.into());
+    args.value_of("endpoint").map(|v| cfg.endpoint = v.into());
+    args.value_of("cloud").map(|v| cfg.cloud = v.into());
+    args.value_of("endpoint suffix").map(|v| cfg.endpoint_suffix = v.into());
+    args.value_of("blob endpoint").map(|v| cfg.blob_endpoint = v.into());
+    args.value_of("connection string").map(|v| cfg.connection_string = v.into());
+    args.value_of("auth").map(|v| cfg.auth = v.into());
+    args.value_of("local").map(|v| cfg.local = v.into());
+    args.value_of("device id").map(|v| cfg.device_id = v.into());
+    if let Some(v) = args.value_of("max requests per second") {
+        cfg.max_requests_per_second = Some(v.parse().map_err(|_| anyhow!("Invalid max requests per second"))?);
+    }
+    if args.is_present("sd-notify") {
+        cfg.sd_notify = Some(true);
+    }
+    if args.is_present("warmup") {
+        cfg.warmup = Some(true);
+    }
+    if args.is_present("low memory") {
+        cfg.low_memory = Some(true);
+    }
+    args.value_of("ip version").map(|v| cfg.ip_version = v.into());
+    args.value_of("tls min version").map(|v| cfg.tls_min_version = v.into());
+    args.value_of("ca cert").map(|v| cfg.ca_cert = v.into());
+    if args.is_present("insecure") {
+        cfg.insecure = Some(true);
+    }
+    args.value_of("tls client cert").map(|v| cfg.tls_client_cert = v.into());
+    args.value_of("tls client key").map(|v| cfg.tls_client_key = v.into());
+    args.values_of("resolve").map(|v| cfg.resolve = v.map(String::from).collect());
+    args.values_of("header").map(|v| cfg.headers = v.map(String::from).collect());
+    args.value_of("api version").map(|v| cfg.api_version = v.into());
+    args.value_of("hash cache").map(|v| cfg.hash_cache = v.into());
+    args.value_of("hash algorithm").map(|v| cfg.hash_algorithm = v.into());
+    args.value_of("verify key").map(|v| cfg.verify_key = v.into());
+    args.value_of("sign key").map(|v| cfg.sign_key = v.into());
+    args.value_of("public access").map(|v| cfg.public_access = v.into());
+    args.value_of("inject faults").map(|v| cfg.inject_faults = v.into());
+    if let Some(v) = args.value_of("start jitter") {
+        cfg.start_jitter = Some(v.parse().map_err(|_| anyhow!("Invalid start jitter"))?);
+    }
+    if let Some(v) = args.value_of("block size") {
+        cfg.block_size = Some(parse_size(v)?);
+    }
+    if let Some(v) = args.value_of("concurrency") {
+        cfg.concurrency = Some(v.parse().map_err(|_| anyhow!("Invalid concurrency"))?);
+    }
+    if let Some(v) = args.value_of("retries") {
+        cfg.retries = Some(v.parse().map_err(|_| anyhow!("Invalid retries"))?);
+    }
+    if let Some(v) = args.value_of("limit rate") {
+        cfg.limit_rate = Some(parse_size(v.trim_end_matches("/s"))?);
+    }
+
+    Ok(cfg)
+}
+
+// Create a storage client object with the credentials in the config.
+// With the endpoint of the local emulator, its well-known account and key are used.
+// With AAD or Azure CLI authentication, an OAuth token of the service principal or the user logged in is used,
+// so that no account key is needed.
+// Otherwise a SAS token is used if configured, for scoped and time-limited access. Otherwise the master key is used
+// if available, or a SAS token signed by the signing command, so that the master key never exists in this process.
+// The blob endpoint of a custom domain or a national cloud is given to the SDK by a connection string.
+fn create_storage_client(cfg: &config::Configs) -> Result<Arc<StorageClient>, Box<dyn Error + Send + Sync>> {
+    if cfg.storage_account.is_empty() {
+        return Err(anyhow!("STORAGE_ACCOUNT is not defined").into());
+    }
+
+    let http_client = transport::new_http_client(cfg, &cfg.storage_account, &cfg.storage_master_key)?;
+
+    // Path-style endpoint of the emulator, with its well-known account
+    if !cfg.endpoint.is_empty() {
+        let url = url::Url::parse(&cfg.endpoint)?;
+        let client = StorageAccountClient::new_emulator(http_client, &url, &url, &url);
+        return Ok(client.as_storage_client());
+    }
+    let blob_endpoint = cfg.blob_endpoint_url()?;
+    match cfg.auth.as_str() {
+        "" | "key" => {},
+        "aad" | "azure-cli" if blob_endpoint.is_some() =>
+            return Err(anyhow!("{} authentication is supported only with the blob endpoint of the public cloud", cfg.auth).into()),
+        "aad" => {
+            let token = aad::service_principal_token(cfg)?;
+            let client = StorageAccountClient::new_bearer_token(http_client, &cfg.storage_account, token.expose());
+            return Ok(client.as_storage_client());
+        },
+        "azure-cli" => {
+            let token = aad::azure_cli_token()?;
+            let client = StorageAccountClient::new_bearer_token(http_client, &cfg.storage_account, token.expose());
+            return Ok(client.as_storage_client());
+        },
+        v => return Err(anyhow!("Invalid auth '{}'. Specify key, aad or azure-cli", v).into())
+    }
+    if !cfg.sas_token.is_empty() {
+        let sas_token = cfg.sas_token.expose().trim_start_matches('?');
+        return sas_storage_client(http_client, &cfg.storage_account, sas_token, blob_endpoint.as_deref());
+    }
+    if !cfg.storage_master_key.is_empty() {
+        let client = match &blob_endpoint {
+            Some(endpoint) => StorageAccountClient::new_connection_string(http_client, &format!(
+                "AccountName={};AccountKey={};BlobEndpoint={}",
+                cfg.storage_account, cfg.storage_master_key.expose(), endpoint))?,
+            None => StorageAccountClient::new_access_key(
+                http_client, &cfg.storage_account, cfg.storage_master_key.expose())
+        };
+        return Ok(client.as_storage_client());
+    }
+    if !cfg.signing_command.is_empty() {
+        let signer = signer::CommandSigner::new(&cfg.signing_command);
+        let expiry = Utc::now() + Duration::hours(SAS_LIFETIME_HOURS);
+        let sas_token = signer::account_sas(&cfg.storage_account, &signer, expiry)
+            .map_err(|e| anyhow!("signing_command: {}", e))?;
+        return sas_storage_client(http_client, &cfg.storage_account, sas_token.expose(), blob_endpoint.as_deref());
+    }
+    Err(anyhow!("STORAGE_MASTER_KEY is not defined").into())
+}
+
+// Create a storage client object with a SAS token, at the blob endpoint if not the default one
+fn sas_storage_client(http_client: Arc<dyn azure_core::HttpClient>, account: &str, sas_token: &str, blob_endpoint: Option<&str>)
+    -> Result<Arc<StorageClient>, Box<dyn Error + Send + Sync>>
+{
+    let client = match blob_endpoint {
+        Some(endpoint) => StorageAccountClient::new_connection_string(http_client, &format!(
+            "AccountName={};SharedAccessSignature={};BlobEndpoint={}", account, sas_token, endpoint))?,
+        None => StorageAccountClient::new_sas_token(http_client, account, sas_token)?
+    };
+    Ok(client.as_storage_client())
+}
+
 #[tokio::main]
-async fn azure_storage(storage_client: Arc<StorageClient>, mode: Option<&str>, container: Option<&str>, blob: Option<&str>, local: Option<&str>, debug: bool)
-    -> Result<(), Box<dyn Error + Send + Sync>>
+async fn azure_storage(storage_client: Arc<StorageClient>, cfg: &config::Configs, args: &ArgMatches<'_>,
+    mut reload: reload::Reload) -> Result<(), Box<dyn Error + Send + Sync>>
 {
+    let account = &cfg.storage_account;
+    let local = if cfg.local != "" { Some(cfg.local.as_str()) } else { None };
+    let audit = audit::AuditLog::new(cfg)?;
+    let progress = progress::Progress::new(!args.is_present("no progress"));
+    if cfg.sd_notify.unwrap_or(false) {
+        systemd::spawn_watchdog();
+    }
+    let mode = args.value_of("mode");
+    let container = args.value_of("container").or(Some(cfg.container.as_str()).filter(|v| !v.is_empty()));
+    let blob = args.value_of("blob");
+    let debug = args.is_present("debug");
+    let transforms = cfg.transform_pipeline()?;
+    let naming = cfg.naming_policy()?;
+    let hash: hash::HashAlgorithm = cfg.hash_algorithm.parse()?;
+
     if debug {
         println!("mode = {:?}", mode);
         println!("container name = {:?}", container);
         println!("blob name = {:?}", blob);
         println!("local path = {:?}", local);
-        println!("\n{:#?}", storage_client);
+        // StorageClient is not printed since its Debug output contains the master key
+        println!("storage account = {:?}", account);
     }
 
-    match mode {
-        // List remote objects
-        Some("list") | None => {
-            // blobs (if specified container name)
-            if let Some(container) = container {
-                let res = storage_client
-                    .as_container_client(container)
-                    .list_blobs()
-                    .execute()
-                    .await?;
+    // Connect to the storage before starting operations
+    if cfg.warmup.unwrap_or(false) {
+        let start = std::time::Instant::now();
+        transport::warmup(&storage_client).await?;
+        if debug {
+            println!("warmup = {:?}", start.elapsed());
+        }
+    }
+
+    // Transfer defaults depend on the account type, which is detected only for bulk operations
+    let bulk = args.is_present("all-containers") || args.is_present("recursive") || mode == Some("restore") || mode == Some("sync")
+        || (mode == Some("get") && blob.is_none() && args.is_present("prefix"))
+        || (mode == Some("find") && container.is_none());
+    // The low memory profile overrides the concurrency and the block size
+    let low_memory = cfg.low_memory.unwrap_or(false);
+    let defaults = if low_memory {
+        account::LOW_MEMORY
+    } else if bulk && (!args.is_present("concurrency") || cfg.block_size.is_none()) {
+        account::transfer_defaults(&storage_client, debug).await
+    } else {
+        account::STANDARD
+    };
+    let concurrency = cfg.concurrency.filter(|&concurrency| concurrency > 0 && !low_memory)
+        .unwrap_or(defaults.concurrency);
+    let block_size = cfg.block_size.filter(|&size| size > 0 && !low_memory).unwrap_or(defaults.block_size);
+    if low_memory && !transforms.is_empty() {
+        eprintln!("Warning: Files are read into memory as a whole by transforms, even in the low memory profile");
+    }
+    if debug {
+        println!("transfer defaults = {:?}, concurrency = {}, block size = {}", defaults, concurrency, block_size);
+    }
+
+    match mode {
+        // List remote objects
+        Some("list") | None => {
+            let mut out = output::Output::new(&storage_client, args.value_of("output blob"), block_size)?;
+
+            // blobs in all containers (if --all-containers specified)
+            if args.is_present("all-containers") {
+                let containers = list_all_containers(&storage_client).await?;
+
+                // List each container concurrently, but keep the container order on output
+                let results: Vec<_> = stream::iter(containers.iter())
+                    .map(|container| list_all_blobs(&storage_client, &container.name, None))
+                    .buffered(concurrency.max(1))
+                    .collect()
+                    .await;
+
+                let results = results.into_iter().collect::<Result<Vec<_>, _>>()?;
+
+                let total: usize = results.iter().map(|blobs| blobs.len()).sum();
+                println!("List of {} blobs in {} containers", total, containers.len());
+                for (container, blobs) in containers.iter().zip(results.iter()) {
+                    for blob in blobs.iter() {
+                        out.line(format!(" {} {:>8} {:>10} {}/{}",
+                            blob.properties.last_modified,
+                            blob.properties.content_length,
+                            blob.properties.blob_type.to_string(),
+                            container.name,
+                            blob.name)).await?;
+                    }
+                }
+            }
+
+            // blobs page by page within the time budget, continued by the next run
+            else if let (Some(container), Some(budget)) = (container, args.value_of("time budget")) {
+                let budget = parse_duration(budget)?.to_std()?;
+                let state_file = args.value_of("state file")
+                    .map(PathBuf::from)
+                    .unwrap_or_else(|| PathBuf::from(format!(".{}.list", container)));
+                list_blobs_within(&storage_client, container, budget, &state_file, args.value_of("continuation token"),
+                    &mut out).await?;
+            }
+
+            // blobs (if specified container name), from the page of the continuation token if specified
+            else if let Some(container) = container {
+                let mut request = storage_client
+                    .as_container_client(container)
+                    .list_blobs();
+                if let Some(token) = args.value_of("continuation token") {
+                    request = request.next_marker(NextMarker::new(token.to_string()));
+                }
+                let res = request.execute().await?;
 
                 println!("List of {} blobs in container '{}'", res.blobs.blobs.len(), container);
                 for blob in res.blobs.blobs.iter() {
-                    println!(" {} {:>8} {:>10} {}",
+                    out.line(format!(" {} {:>8} {:>10} {}",
                         blob.properties.last_modified,
                         blob.properties.content_length,
                         blob.properties.blob_type.to_string(),
-                        blob.name);
+                        blob.name)).await?;
+                }
+                if let Some(marker) = &res.next_marker {
+                    println!("Continuation token: {}", marker.as_str());
                 }
                 debug_print(res, debug);
             }
@@ -166,10 +1076,11 @@ async fn azure_storage(storage_client: Arc<StorageClient>, mode: Option<&str>, c
 
                 println!("List of {} containers", res.incomplete_vector.len());
                 for container in res.incomplete_vector.iter() {
-                    println!(" {} {}", container.last_modified, container.name);
+                    out.line(format!(" {} {}", container.last_modified, container.name)).await?;
                 }
                 debug_print(res, debug);
             }
+            out.finish(&audit, debug).await?;
         },
 
         // Create a just new append blob to remote. no local file required
@@ -187,6 +1098,7 @@ async fn azure_storage(storage_client: Arc<StorageClient>, mode: Option<&str>, c
                 .execute()
                 .await?;
 
+            audit.record(&storage_client, "put-append", container, blob, &res.request_id).await?;
             debug_print(res, debug);
         },
 
@@ -195,47 +1107,245 @@ async fn azure_storage(storage_client: Arc<StorageClient>, mode: Option<&str>, c
             // Check path
             let local_path = local.ok_or(anyhow!("No local path specified"))?;
             let container = container.ok_or(anyhow!("No container name specified"))?;
+            let mut quota = quota::Quota::from_config(cfg)?;
 
-            // Check local_path. Use the local filename as blob name if no blob name is specified.
-            let blob = match blob {
-                Some(v) => v,
-                None => Path::new(local_path).file_name()
-                    .ok_or(anyhow!("Cannot extract filename from local path"))?.to_str().unwrap()
-            };
-            
-            // Create a blob instance
-            let blob_client = storage_client
-                .as_container_client(container)
-                .as_blob_client(blob);
-    
-            // Read data from file
-            let mut buffer = Vec::new();
-            File::open(local_path).and_then(|mut f| f.read_to_end(&mut buffer))?;
+            // [put] Put files to remote, with the manifest of this run if requested
+            if mode.unwrap() == "put" {
+                let sign = if args.is_present("sign") { Some(cfg) } else { None };
+                let blocks = upload::Blocks { size: block_size, concurrency, resume: args.is_present("resume"),
+                    hashes: args.is_present("block hashes") };
+                let expires = args.value_of("expires in").map(parse_duration).transpose()?.map(|v| Utc::now() + v);
+                // Local files are deleted, moved or truncated only after they are verified
+                let after_upload: after_upload::AfterUpload = args.value_of("after upload").unwrap_or("keep").parse()?;
+                let verify_after_upload = args.is_present("verify after upload")
+                    || after_upload != after_upload::AfterUpload::Keep;
+                let create_container = args.is_present("create container");
+                let manifest_file = args.value_of("manifest").map(Path::new);
+                let manifest_blob = args.value_of("manifest blob").map(|v| (container, v));
+                let mut manifest = manifest::Manifest::new(cfg);
 
-            // this is not mandatory but it helps preventing spurious data to be uploaded
-            let hash = md5::compute(&buffer).into();
+                // Put data from stdin or a FIFO by blocks as read until EOF. Transforms and signing need the whole content.
+                // Use the name of the FIFO as blob name if no blob name is specified.
+                if upload::is_stream(Path::new(local_path)) && !args.is_present("recursive") {
+                    let blob = match blob {
+                        Some(v) => v,
+                        None if local_path == "-" => return Err(anyhow!("No blob name specified").into()),
+                        None => Path::new(local_path).file_name()
+                            .ok_or(anyhow!("Cannot extract filename from local path"))?.to_str().unwrap()
+                    };
+                    if !transforms.is_empty() || sign.is_some() {
+                        return Err(anyhow!("Transforms and --sign are not supported for stdin and FIFOs").into());
+                    }
+                    if after_upload != after_upload::AfterUpload::Keep {
+                        return Err(anyhow!("--after-upload is not supported for stdin and FIFOs").into());
+                    }
+                    if args.is_present("replicate") {
+                        return Err(anyhow!("--replicate is not supported for stdin and FIFOs").into());
+                    }
+                    if create_container {
+                        upload::create_container(&storage_client, container, &cfg.public_access, &audit).await?;
+                    }
+                    quota.check(&storage_client, container, 0).await?;
+                    let entries = vec![upload::put_stream(&storage_client, Path::new(local_path), container, blob, blocks,
+                        hash, expires, &audit, debug).await?];
+                    quota.add(container, entries[0].size)?;
+                    if verify_after_upload {
+                        verify::read_back(&storage_client, &entries, &transforms, block_size, debug).await?;
+                    }
+                    manifest.add(entries);
+                }
 
-            // [put] Put to remote
-            if mode.unwrap() == "put" {
-                let res = blob_client
-                    .put_block_blob(buffer)
-                    .hash(&hash)
-                    .execute()
-                    .await?;
-                debug_print(res, debug);
+                else {
+                    // Put all files under the local directory, or a file.
+                    // Use the local filename as blob name if no blob name is specified.
+                    let recursive = args.is_present("recursive");
+                    let prefix = args.value_of("prefix").unwrap_or("");
+                    let blob = match blob {
+                        Some(v) => v,
+                        None if recursive => "",
+                        None => Path::new(local_path).file_name()
+                            .ok_or(anyhow!("Cannot extract filename from local path"))?.to_str().unwrap()
+                    };
+                    let size = upload::local_size(Path::new(local_path))?;
+                    quota.check(&storage_client, container, size).await?;
+                    let naming = naming.as_ref();
+                    let (transforms, progress, audit) = (&transforms, &progress, &audit);
+                    let put = |storage_client: Arc<StorageClient>, container: String| async move {
+                        if create_container {
+                            upload::create_container(&storage_client, &container, &cfg.public_access, audit).await?;
+                        }
+                        let entries = if recursive {
+                            upload::put_dir(&storage_client, Path::new(local_path), &container, prefix, naming,
+                                transforms, sign, blocks, hash, expires, concurrency, progress, audit, debug).await?
+                        } else {
+                            vec![upload::put_file(&storage_client, Path::new(local_path), &container, blob, transforms,
+                                sign, blocks, hash, expires, progress, audit, debug).await?]
+                        };
+                        if verify_after_upload {
+                            verify::read_back(&storage_client, &entries, transforms, block_size, debug).await?;
+                        }
+                        Ok::<_, Box<dyn Error + Send + Sync>>(entries)
+                    };
+
+                    // Put to the replicas too, for client-side replication across regions
+                    let entries = match args.value_of("replicate") {
+                        Some(mode) => {
+                            // Journals of blocks are named by the container and the blob, same across accounts
+                            if mode == "parallel" && blocks.resume {
+                                return Err(anyhow!("--resume is not supported with --replicate parallel").into());
+                            }
+                            let destinations = replicate::destinations(&storage_client, cfg, container)?;
+                            replicate::put(destinations, mode == "parallel", put).await?
+                        },
+                        None => put(storage_client.clone(), container.to_string()).await?
+                    };
+                    quota.add(container, size)?;
+                    let root = match recursive {
+                        true => Path::new(local_path),
+                        false => Path::new(local_path).parent().unwrap_or(Path::new(""))
+                    };
+                    after_upload.apply(&entries, root, debug)?;
+                    manifest.add(entries);
+                }
+
+                if manifest_file.is_some() || manifest_blob.is_some() {
+                    manifest.write(&storage_client, manifest_file, manifest_blob, cfg, &audit).await?;
+                }
             }
 
             // [append] Append to remote blob
             else {
-                let res = blob_client
-                    .append_block(buffer)
-                    .hash(&hash)
-                    .execute()
-                    .await?;
-                debug_print(res, debug);
+                // Roll over to the blob of the current UTC window, created on the first write of each window.
+                // Check local_path. Use the local filename as blob name if no blob name is specified.
+                let rollover: Option<rollover::Rollover> = args.value_of("rollover").map(str::parse).transpose()?;
+                let rolled;
+                let blob = match (rollover, blob) {
+                    (Some(rollover), _) => {
+                        let extension = Path::new(local_path).extension().and_then(|v| v.to_str());
+                        rolled = rollover.blob_name(args.value_of("prefix").unwrap_or(""), extension, Utc::now());
+                        if debug {
+                            println!("blob (rolled over) = {}", rolled);
+                        }
+                        rolled.as_str()
+                    },
+                    (None, Some(v)) => v,
+                    (None, None) if local_path == "-" => return Err(anyhow!("No blob name specified").into()),
+                    (None, None) => Path::new(local_path).file_name()
+                        .ok_or(anyhow!("Cannot extract filename from local path"))?.to_str().unwrap()
+                };
+                let chain = args.is_present("chain");
+
+                // Append stdin or a FIFO by blocks as read until EOF, since the size of an append block is limited
+                if upload::is_stream(Path::new(local_path)) {
+                    quota.check(&storage_client, container, 0).await?;
+                    let input = upload::open_stream(Path::new(local_path))?;
+                    let mut blocks = Box::pin(upload::read_blocks(input, upload::APPEND_BLOCK_SIZE, 1));
+                    while let Some(buffer) = blocks.next().await {
+                        let buffer = buffer?;
+                        let size = buffer.len() as u64;
+                        upload::append_block(&storage_client, container, blob, buffer, chain, rollover.is_some(),
+                            &audit, debug).await?;
+                        quota.add(container, size)?;
+                    }
+                }
+
+                // Read data from file
+                else {
+                    let mut buffer = Vec::new();
+                    File::open(local_path).and_then(|mut f| f.read_to_end(&mut buffer))?;
+                    let size = buffer.len() as u64;
+                    quota.check(&storage_client, container, size).await?;
+                    upload::append_block(&storage_client, container, blob, buffer, chain, rollover.is_some(), &audit,
+                        debug).await?;
+                    quota.add(container, size)?;
+                }
             }
         },
 
+        // Write a blob to stdout
+        Some("cat" | "get") if mode == Some("cat") || local == Some("-") => {
+            let container = container.ok_or(anyhow!("No container name specified"))?;
+            let blob = blob.ok_or(anyhow!("No blob name specified"))?;
+            let blob_client = storage_client
+                .as_container_client(container)
+                .as_blob_client(blob);
+            let remote = blob_client.get_properties().execute().await?.blob;
+            download::cat(&blob_client, &remote, &transforms, block_size, !args.is_present("no verify")).await?;
+        },
+
+        // Print a byte range of a blob, to inspect headers of huge blobs without getting them entirely
+        Some("head") => {
+            let container = container.ok_or(anyhow!("No container name specified"))?;
+            let blob = blob.ok_or(anyhow!("No blob name specified"))?;
+            let blob_client = storage_client
+                .as_container_client(container)
+                .as_blob_client(blob);
+            let (start, end) = match (args.value_of("range"), args.value_of("bytes")) {
+                (Some(range), _) => parse_range(range)?,
+                (None, Some(bytes)) => (0, Some(parse_size(bytes)?)),
+                (None, None) => (0, Some(tail::HEAD_BYTES))
+            };
+            let size = blob_client.get_properties().execute().await?.blob.properties.content_length;
+            if start >= size && size > 0 {
+                return Err(anyhow!("Range start {} is beyond the size {} of '{}/{}'", start, size, container, blob).into());
+            }
+            tail::write_range(&blob_client, start, end.map_or(size, |end| end.min(size))).await?;
+        },
+
+        // Check the permissions of the credentials for an operation before running it
+        Some("preflight") => {
+            let container = container.ok_or(anyhow!("No container name specified"))?;
+            let operation = args.value_of("operation").ok_or(anyhow!("No operation to check specified"))?;
+            preflight::preflight(&storage_client, container, operation).await?;
+        },
+
+        // Compare blobs with the listing saved by the last run, such as to review what a nightly job changed
+        Some("list-diff") => {
+            let container = container.ok_or(anyhow!("No container name specified"))?;
+            let prefix = args.value_of("prefix").unwrap_or("");
+            let state_file = args.value_of("state file")
+                .map(PathBuf::from)
+                .unwrap_or_else(|| PathBuf::from(format!(".{}_{}.listing", container, prefix.replace('/', "_"))));
+            list_diff::list_diff(&storage_client, container, prefix, &state_file).await?;
+        },
+
+        // Print the end of a blob, such as device logs put by append mode
+        Some("tail") => {
+            let container = container.ok_or(anyhow!("No container name specified"))?;
+            let blob = blob.ok_or(anyhow!("No blob name specified"))?;
+            let blob_client = storage_client
+                .as_container_client(container)
+                .as_blob_client(blob);
+            let last = match args.value_of("bytes") {
+                Some(bytes) => tail::Last::Bytes(parse_size(bytes)?),
+                None => tail::Last::Lines(args.value_of("lines").unwrap().parse()
+                    .map_err(|_| anyhow!("Invalid number of lines"))?)
+            };
+            let follow = match (args.is_present("follow"), args.occurrences_of("interval")) {
+                (false, _) => None,
+                (true, 0) => Some(tail::FOLLOW_INTERVAL),
+                (true, _) => Some(args.value_of("interval").unwrap().parse().map_err(|_| anyhow!("Invalid interval"))?)
+            };
+            tail::tail(&blob_client, last, follow).await?;
+        },
+
+        // Get all blobs with a prefix into a local directory
+        Some("get") if blob.is_none() && args.is_present("prefix") => {
+            let local_dir = local.ok_or(anyhow!("No local path specified"))?;
+            let container = container.ok_or(anyhow!("No container name specified"))?;
+            let prefix = args.value_of("prefix").unwrap();
+            let overwrite = args.value_of("overwrite").unwrap().parse()?;
+            let cache = hash_cache::HashCache::load(&cfg.hash_cache, hash)?;
+            let filter = blob_filter(args)?;
+
+            // Hashes computed before a failure are still cached
+            let result = download::get_blobs(&storage_client, container, prefix, &filter, Path::new(local_dir),
+                overwrite, &transforms, &cache, block_size, !args.is_present("no verify"), concurrency, &progress, debug)
+                .await;
+            cache.save()?;
+            result?;
+        },
+
         // Get a file from remote
         Some("get") => {
             // Check remote path
@@ -243,33 +1353,119 @@ async fn azure_storage(storage_client: Arc<StorageClient>, mode: Option<&str>, c
             let blob = blob.ok_or(anyhow!("No blob name specified"))?;
 
             // Check local_path. Add the blob name as local filename if local path is directory.
-            let local_path = local.ok_or(anyhow!("No local path specified"))
-                .map(|v| {
-                    let mut path = PathBuf::from(v);
-                    if path.exists() && path.is_dir() {
-                        path = path.join(blob);
-                        if debug {
-                            println!("local path (complemented) = {:?}", path);
-                        }
+            let local_path = {
+                let mut path = PathBuf::from(local.ok_or(anyhow!("No local path specified"))?);
+                if path.exists() && path.is_dir() {
+                    path = download::local_path(&path, blob)?;
+                    if debug {
+                        println!("local path (complemented) = {:?}", path);
                     }
-                    path
-                })?;
+                }
+                path
+            };
             
             // Create a blob instance
             let blob_client = storage_client
                 .as_container_client(container)
                 .as_blob_client(blob);
     
-            // Get the remote file
-            let res = blob_client
-                .get()
-                .execute()
-                .await?;
+            // Pin a historical version or snapshot if specified
+            let versioning = match (args.value_of("version id"), args.value_of("snapshot")) {
+                (Some(version_id), _) => Some(BlobVersioning::VersionId(VersionId::new(version_id.to_string()))),
+                (None, Some(snapshot)) => Some(BlobVersioning::Snapshot(
+                    chrono::DateTime::parse_from_rfc3339(snapshot)
+                        .map_err(|_| anyhow!("Invalid snapshot timestamp '{}'", snapshot))?
+                        .with_timezone(&Utc))),
+                (None, None) => None
+            };
 
-            // Write to a file
-            File::create(local_path).and_then(|mut f| f.write_all(&res.data))?;
+            // Type and size of the blob. Page blobs are written as sparse files, and large blobs are got by ranges.
+            // A pinned version, a signature or a chain to verify needs the whole content, so it is got as is.
+            let remote = if versioning.is_none() && !args.is_present("verify-sig") && !args.is_present("chain") {
+                Some(blob_client.get_properties().execute().await?.blob)
+            } else {
+                None
+            };
+            let properties = remote.as_ref().map(|remote| &remote.properties);
 
-            debug_print(res, debug);
+            // The written file is verified by the hashes of the blob
+            let verify = !args.is_present("no verify");
+
+            // Get only allocated pages of the page blob. The blob got is returned for the receipt.
+            let got = if let Some(properties) = properties.filter(|p| matches!(p.blob_type, BlobType::PageBlob)) {
+                let size = properties.content_length;
+                let downloaded = download::get_page_blob(&blob_client, &local_path, size).await?;
+                if debug {
+                    println!("page blob size = {}, allocated = {}", size, downloaded);
+                }
+                if let Some(remote) = remote.as_ref().filter(|_| verify) {
+                    download::verify_file(&local_path, remote, false)?;
+                }
+                remote.clone()
+            }
+
+            // Get a large blob by concurrent ranges. Transforms need the whole content.
+            else if let Some(properties) = properties
+                .filter(|p| transforms.is_empty() && p.content_length > block_size)
+            {
+                download::check_space(&local_path, properties.content_length, download::file_size(&local_path))?;
+                download::get_ranges(&blob_client, &local_path, properties.content_length,
+                    &properties.etag.to_string(), block_size, concurrency, args.is_present("resume"), &progress, debug)
+                    .await?;
+                if let Some(remote) = remote.as_ref().filter(|_| verify) {
+                    download::verify_file(&local_path, remote, false)?;
+                }
+                remote.clone()
+            }
+
+            else {
+                // Metadata with the head of the chain, got before the content so that later appends are ignored
+                let chain_metadata = match args.is_present("chain") {
+                    true => Some(blob_client.get_properties().execute().await?.blob.metadata),
+                    false => None
+                };
+
+                // Get the remote file
+                let mut request = blob_client.get();
+                if let Some(versioning) = &versioning {
+                    request = request.blob_versioning(versioning);
+                }
+                let res = request
+                    .execute()
+                    .await?;
+
+                // Verify the chain of appends before writing
+                if let Some(metadata) = &chain_metadata {
+                    chain::verify(&storage_client, container, blob, &res.data, metadata.as_ref()).await?;
+                }
+                let data = transforms.decode(res.data.to_vec())?;
+
+                // Verify the signature before writing
+                if args.is_present("verify-sig") {
+                    signature::verify_blob(&storage_client, container, blob, &data, &cfg.verify_key).await?;
+                }
+
+                // Write to a file, if there is space for it
+                download::check_space(&local_path, data.len() as u64, download::file_size(&local_path))?;
+                download::write_file(&local_path, &data)?;
+                if verify {
+                    download::verify_file(&local_path, &res.blob, !transforms.is_empty())?;
+                }
+
+                let got = res.blob.clone();
+                debug_print(res, debug);
+                Some(got)
+            };
+
+            // Write the receipt of the content got next to the local file
+            if let (true, Some(got)) = (args.is_present("receipt"), got) {
+                let receipt = receipt::Receipt::new(&storage_client, cfg, container, &got, &local_path,
+                    args.value_of("version id"), args.value_of("snapshot"))?;
+                let path = receipt.write(cfg)?;
+                if debug {
+                    println!("receipt = {:?}", path);
+                }
+            }
         },
 
         // Delete a blob from remote
@@ -289,9 +1485,412 @@ async fn azure_storage(storage_client: Arc<StorageClient>, mode: Option<&str>, c
                 .execute()
                 .await?;
 
+            audit.record(&storage_client, "delete", container, blob, &res.request_id).await?;
             debug_print(res, debug);
         },
 
+        // Poll a command blob for this device and run it when updated
+        Some("poll-commands") => {
+            let container = container.ok_or(anyhow!("No container name specified"))?;
+            let blob = blob.ok_or(anyhow!("No blob name specified"))?;
+            let response_blob = args.value_of("response blob")
+                .map(String::from)
+                .unwrap_or_else(|| format!("{}.response", blob));
+            let interval: u64 = args.value_of("interval").unwrap().parse()
+                .map_err(|_| anyhow!("Invalid interval"))?;
+
+            reload.listen()?;
+            command_channel::poll_commands(&storage_client, container, blob, &response_blob,
+                interval, &mut reload, &audit, debug).await?;
+        },
+
+        // Run a command only on the device which holds the lease on a well-known blob
+        Some("elect") => {
+            let container = container.ok_or(anyhow!("No container name specified"))?;
+            let blob = blob.ok_or(anyhow!("No blob name specified"))?;
+            let command = args.value_of("command").ok_or(anyhow!("No command specified"))?;
+            let interval: u64 = args.value_of("interval").unwrap().parse()
+                .map_err(|_| anyhow!("Invalid interval"))?;
+
+            reload.listen()?;
+            lease::elect(&storage_client, container, blob, command, interval, &mut reload, debug).await?;
+        },
+
+        // Append a message to an append blob used as a queue
+        Some("publish") => {
+            let container = container.ok_or(anyhow!("No container name specified"))?;
+            let blob = blob.ok_or(anyhow!("No blob name specified"))?;
+            let payload = match (args.value_of("message"), local) {
+                (Some(message), _) => message.as_bytes().to_vec(),
+                (None, Some(local_path)) => std::fs::read(local_path)?,
+                (None, None) => return Err(anyhow!("No message or local path specified").into())
+            };
+
+            queue::publish(&storage_client, container, blob, &payload, &audit).await?;
+        },
+
+        // Print messages appended after the last consumed one
+        Some("consume") => {
+            let container = container.ok_or(anyhow!("No container name specified"))?;
+            let blob = blob.ok_or(anyhow!("No blob name specified"))?;
+            let offset_file = args.value_of("offset file")
+                .map(PathBuf::from)
+                .unwrap_or_else(|| PathBuf::from(format!(".{}_{}.offset", container, blob.replace('/', "_"))));
+
+            queue::consume(&storage_client, container, blob, &offset_file, debug).await?;
+        },
+
+        // Key-value store of JSON values with ETag based optimistic concurrency
+        Some(mode @ ("kv-get" | "kv-set" | "kv-delete")) => {
+            let container = container.ok_or(anyhow!("No container name specified"))?;
+            let key = args.value_of("key").ok_or(anyhow!("No key specified"))?;
+            let if_match = args.value_of("if-match");
+            let kv = kv::KvStore::new(&storage_client, container, args.value_of("prefix").unwrap_or("kv/"), &audit);
+
+            match mode {
+                "kv-get" => {
+                    let (value, etag) = kv.get(key).await?;
+                    println!("{}", value);
+                    eprintln!("ETag: {}", etag);
+                },
+                "kv-set" => {
+                    let value = args.value_of("value").ok_or(anyhow!("No value specified"))?;
+                    let value: serde_json::Value = serde_json::from_str(value)
+                        .map_err(|e| anyhow!("Value is not JSON: {}", e))?;
+                    let etag = match args.is_present("if-absent") {
+                        true => kv.set_if_absent(key, &value).await?,
+                        false => kv.set(key, &value, if_match).await?
+                    };
+                    eprintln!("ETag: {}", etag);
+                },
+                _ => kv.delete(key, if_match).await?
+            }
+        },
+
+        // Measure upload throughput to find the best block size and concurrency
+        Some("bench") => {
+            let container = container.ok_or(anyhow!("No container name specified"))?;
+            let size = parse_size(args.value_of("bench size").unwrap())?;
+            let block_sizes = args.value_of("bench block sizes").unwrap()
+                .split(',').map(parse_size).collect::<Result<Vec<_>, _>>()?;
+            let concurrency_levels = args.value_of("bench concurrency").unwrap()
+                .split(',').map(|v| v.trim().parse().map_err(|_| anyhow!("Invalid concurrency '{}'", v)))
+                .collect::<Result<Vec<usize>, _>>()?;
+
+            bench::bench(&storage_client, container, size, &block_sizes, &concurrency_levels).await?;
+        },
+
+        // Replace a local config file by a blob only if its hash is verified
+        Some("fetch-config") => {
+            let local_path = local.ok_or(anyhow!("No local path specified"))?;
+            let container = container.ok_or(anyhow!("No container name specified"))?;
+            let blob = blob.ok_or(anyhow!("No blob name specified"))?;
+            let hash_blob = args.value_of("hash blob")
+                .map(String::from)
+                .unwrap_or_else(|| format!("{}.sha256", blob));
+            let verification = if args.is_present("verify-sig") {
+                fetch_config::Verification::Signature(&cfg.verify_key)
+            } else {
+                fetch_config::Verification::Hash(&hash_blob)
+            };
+
+            fetch_config::fetch_config(&storage_client, container, blob, verification,
+                Path::new(local_path), debug).await?;
+        },
+
+        // Back up a page blob by copying only pages changed since the last copy
+        Some("incremental-copy") => {
+            let container = container.ok_or(anyhow!("No container name specified"))?;
+            let blob = blob.ok_or(anyhow!("No blob name specified"))?;
+            let target_container = args.value_of("target container")
+                .ok_or(anyhow!("No target container name specified"))?;
+            let target_blob = args.value_of("target blob").unwrap_or(blob);
+
+            // The source is read by the SAS token if configured, which needs the read permission
+            let sas_token = match cfg.sas_token.is_empty() {
+                true => {
+                    let signer = signer::from_config(cfg)?;
+                    signer::account_sas(account, signer.as_ref(), Utc::now() + Duration::hours(SAS_LIFETIME_HOURS))?
+                },
+                false => cfg.sas_token.expose().trim_start_matches('?').into()
+            };
+            incremental_copy::incremental_copy(&storage_client, container, blob, target_container, target_blob,
+                &sas_token, &audit, debug).await?;
+        },
+
+        // Show the account type and the transfer defaults for it
+        Some("account-info") => {
+            let info = account::AccountInfo::get(&storage_client).await?;
+            let defaults = info.transfer_defaults();
+            println!("Storage account: {}", account);
+            println!(" SKU: {}", info.sku_name);
+            println!(" Kind: {}", info.account_kind);
+            println!(" Premium block blob: {}", info.is_premium_block_blob());
+            println!(" Default block size: {}", defaults.block_size);
+            println!(" Default concurrency: {}", defaults.concurrency);
+        },
+
+        // Get only blobs changed since the last run into a local directory
+        Some("sync") if args.value_of("direction") == Some("down") => {
+            let local_dir = local.ok_or(anyhow!("No local path specified"))?;
+            let container = container.ok_or(anyhow!("No container name specified"))?;
+            let prefix = args.value_of("prefix").unwrap_or("");
+            let state_file = args.value_of("state file")
+                .map(PathBuf::from)
+                .unwrap_or_else(|| PathBuf::from(format!(".{}_{}.sync", container, prefix.replace('/', "_"))));
+            let mirror = sync::Mirror { delete: args.is_present("delete extras"), dry_run: args.is_present("dry run") };
+
+            sync::sync_down(&storage_client, container, prefix, &blob_filter(args)?, Path::new(local_dir), &state_file,
+                &transforms, mirror, block_size, !args.is_present("no verify"), concurrency, &progress, debug).await?;
+        },
+
+        // Put only new or changed files under a local directory
+        Some("sync") => {
+            let local_dir = local.ok_or(anyhow!("No local path specified"))?;
+            let container = container.ok_or(anyhow!("No container name specified"))?;
+            let prefix = args.value_of("prefix").unwrap_or("");
+            let manifest_file = args.value_of("manifest").map(Path::new);
+            let manifest_blob = args.value_of("manifest blob").map(|v| (container, v));
+            let mut manifest = manifest::Manifest::new(cfg);
+            let mirror = sync::Mirror { delete: args.is_present("delete extras"), dry_run: args.is_present("dry run") };
+            let cache = hash_cache::HashCache::load(&cfg.hash_cache, hash)?;
+
+            let after_upload: after_upload::AfterUpload = args.value_of("after upload").unwrap_or("keep").parse()?;
+            after_upload.check_sync(Path::new(local_dir), mirror.delete)?;
+
+            if args.is_present("create container") {
+                upload::create_container(&storage_client, container, &cfg.public_access, &audit).await?;
+            }
+
+            // Files to put are known only while syncing, so the quota is checked by the current usage
+            let mut quota = quota::Quota::from_config(cfg)?;
+            quota.check(&storage_client, container, 0).await?;
+
+            // Hashes computed before a failure are still cached
+            let result = sync::sync_up(&storage_client, Path::new(local_dir), container, prefix, naming.as_ref(),
+                &transforms, mirror, &cache, block_size, concurrency, &progress, &audit, debug).await;
+            cache.save()?;
+            let entries = result?;
+            if !mirror.dry_run {
+                quota.add(container, entries.iter().map(|entry| entry.size).sum())?;
+                if after_upload != after_upload::AfterUpload::Keep {
+                    verify::read_back(&storage_client, &entries, &transforms, block_size, debug).await?;
+                    after_upload.apply(&entries, Path::new(local_dir), debug)?;
+                }
+            }
+            manifest.add(entries);
+            if !mirror.dry_run && (manifest_file.is_some() || manifest_blob.is_some()) {
+                manifest.write(&storage_client, manifest_file, manifest_blob, cfg, &audit).await?;
+            }
+        },
+
+        // Put new or changed files in the local outbox directory to the outbox of this device
+        Some("push-outbox") => {
+            let local_dir = local.ok_or(anyhow!("No local path specified"))?;
+            let container = container.ok_or(anyhow!("No container name specified"))?;
+            let outbox = device::prefix(&cfg.outbox, device::DEFAULT_OUTBOX, &cfg.device_id)?;
+            let mirror = sync::Mirror { delete: false, dry_run: args.is_present("dry run") };
+            let cache = hash_cache::HashCache::load(&cfg.hash_cache, hash)?;
+            let after_upload: after_upload::AfterUpload = args.value_of("after upload").unwrap_or("keep").parse()?;
+            after_upload.check_sync(Path::new(local_dir), mirror.delete)?;
+
+            // Hashes computed before a failure are still cached
+            let result = sync::sync_up(&storage_client, Path::new(local_dir), container, &outbox, naming.as_ref(),
+                &transforms, mirror, &cache, block_size, concurrency, &progress, &audit, debug).await;
+            cache.save()?;
+            let entries = result?;
+            if !mirror.dry_run && after_upload != after_upload::AfterUpload::Keep {
+                verify::read_back(&storage_client, &entries, &transforms, block_size, debug).await?;
+                after_upload.apply(&entries, Path::new(local_dir), debug)?;
+            }
+        },
+
+        // Get the messages in the inbox of this device into the local directory, and remove them from the inbox
+        Some("pull-inbox") => {
+            let local_dir = local.ok_or(anyhow!("No local path specified"))?;
+            let container = container.ok_or(anyhow!("No container name specified"))?;
+            let inbox = device::prefix(&cfg.inbox, device::DEFAULT_INBOX, &cfg.device_id)?;
+            device::pull_inbox(&storage_client, container, &inbox, Path::new(local_dir), &transforms, block_size,
+                !args.is_present("no verify"), concurrency, args.is_present("dry run"), &progress, &audit).await?;
+        },
+
+        // Put files handed off into a local directory, as they are closed or renamed into it
+        Some("watch") => {
+            let local_dir = local.ok_or(anyhow!("No local path specified"))?;
+            let container = container.ok_or(anyhow!("No container name specified"))?;
+            let prefix = args.value_of("prefix").unwrap_or("");
+            let interval: u64 = args.value_of("interval").unwrap().parse()
+                .map_err(|_| anyhow!("Invalid interval"))?;
+            let blocks = upload::Blocks { size: block_size, concurrency, resume: false,
+                hashes: args.is_present("block hashes") };
+            let after_upload = match (args.is_present("delete uploaded"), args.is_present("move uploaded")) {
+                (true, _) => after_upload::AfterUpload::Delete,
+                (_, true) => after_upload::AfterUpload::Move(Path::new(local_dir).join(watch::UPLOADED_DIR)),
+                _ => args.value_of("after upload").unwrap_or("keep").parse()?
+            };
+
+            if args.is_present("create container") {
+                upload::create_container(&storage_client, container, &cfg.public_access, &audit).await?;
+            }
+            reload.listen()?;
+            watch::watch(&storage_client, Path::new(local_dir), container, prefix, naming.as_ref(), &transforms,
+                blocks, hash, interval, &after_upload, &mut reload, &progress, &audit, debug).await?;
+        },
+
+        // Send a signed REST request, with the content of the local file as the body
+        Some("rest") => {
+            let path = args.value_of("path").ok_or(anyhow!("No path specified"))?;
+            // Only the command line, since the local path in config files is for transfers
+            let body = match args.value_of("local") {
+                Some(local_path) => std::fs::read(local_path)
+                    .map_err(|e| anyhow!("Cannot read {:?}: {}", local_path, e))?,
+                None => Vec::new()
+            };
+            rest::rest(&storage_client, cfg, args.value_of("method").unwrap(), path, body, &audit).await?;
+        },
+
+        // Delete blobs past their expiry
+        Some("expire") => {
+            let container = container.ok_or(anyhow!("No container name specified"))?;
+            let prefix = args.value_of("prefix").unwrap_or("");
+            expire::expire(&storage_client, container, prefix, args.is_present("dry run"), &audit, debug).await?;
+        },
+
+        // Tag blobs put before, such as historical uploads
+        Some("set-metadata") => {
+            let container = container.ok_or(anyhow!("No container name specified"))?;
+            let prefix = args.value_of("prefix").ok_or(anyhow!("No prefix specified"))?;
+            let pairs = metadata::parse_pairs(&args.values_of("metadata").map(|values| values.collect::<Vec<_>>())
+                .unwrap_or_default())?;
+            if pairs.is_empty() {
+                return Err(anyhow!("No metadata to set specified").into());
+            }
+            metadata::set_metadata(&storage_client, container, prefix, &pairs, concurrency, args.is_present("dry run"),
+                &audit).await?;
+        },
+
+        // Record or show the number and the total size of blobs over time, for capacity planning
+        Some(mode @ ("stats-record" | "stats-show")) => {
+            let container = container.ok_or(anyhow!("No container name specified"))?;
+            let blob = blob.ok_or(anyhow!("No stats blob name specified"))?;
+            let prefix = args.value_of("prefix").unwrap_or("");
+            match mode {
+                "stats-record" => stats::record(&storage_client, container, prefix, blob, &audit).await?,
+                _ => stats::show(&storage_client, container, prefix, blob).await?
+            }
+        },
+
+        // Check the content of the blob, or blobs with a prefix, by their hashes without writing local files
+        Some("verify") => {
+            let container = container.ok_or(anyhow!("No container name specified"))?;
+            let prefix = args.value_of("prefix").unwrap_or("");
+            let spot_check = args.value_of("spot check")
+                .map(|v| v.parse::<usize>().map_err(|_| anyhow!("Invalid spot check '{}'", v)))
+                .transpose()?;
+            verify::verify(&storage_client, container, blob, prefix, &transforms, block_size, spot_check, debug).await?;
+        },
+
+        // Restore the files uploaded by a backup run, as recorded in its manifest
+        Some("restore") => {
+            let local_dir = local.ok_or(anyhow!("No local path specified"))?;
+            let manifest_path = args.value_of("manifest").ok_or(anyhow!("No manifest specified"))?;
+
+            // Verify the manifest itself by its signature
+            if args.is_present("verify-sig") {
+                let data = std::fs::read(manifest_path)?;
+                let sig_path = format!("{}.sig", manifest_path);
+                let sig = std::fs::read(&sig_path).map_err(|e| anyhow!("Cannot read {}: {}", sig_path, e))?;
+                signature::verify(&data, &sig, &cfg.verify_key)
+                    .map_err(|e| anyhow!("Signature of the manifest is not valid: {}", e))?;
+            }
+
+            let manifest = manifest::Manifest::from_file(Path::new(manifest_path))?;
+            restore::restore(&storage_client, &manifest, Path::new(local_dir), &transforms, concurrency).await?;
+        },
+
+        // Find blobs by name pattern in a container, or in all containers if not specified
+        Some("find") => {
+            let pattern = args.value_of("pattern").ok_or(anyhow!("No pattern specified"))?;
+            let pattern = match args.is_present("glob") {
+                true => glob_to_regex(pattern),
+                false => pattern.to_string()
+            };
+            let re = Regex::new(&pattern)?;
+            if debug {
+                println!("pattern = {:?}", re);
+            }
+
+            let containers = match container {
+                Some(container) => vec![container.to_string()],
+                None => list_all_containers(&storage_client).await?
+                    .into_iter().map(|c| c.name).collect()
+            };
+
+            // Matches are printed as soon as each page is listed
+            stream::iter(containers.iter().map(Ok))
+                .try_for_each_concurrent(concurrency.max(1), |container| find_blobs(&storage_client, container, &re))
+                .await?;
+        },
+
+        // Compare blobs under a prefix of two containers, possibly in another account
+        Some("compare") => {
+            let container = container.ok_or(anyhow!("No container name specified"))?;
+            let target_container = args.value_of("target container")
+                .ok_or(anyhow!("No target container name specified"))?;
+            let prefix = args.value_of("prefix");
+            let target_prefix = args.value_of("target prefix").or(prefix);
+
+            // Use another storage account for the target if specified
+            let target_client = match (args.value_of("target account"), args.value_of("target master key")) {
+                (Some(account), Some(master_key)) => {
+                    let http_client = transport::new_http_client(cfg, account, &master_key.into())?;
+                    StorageAccountClient::new_access_key(http_client, account, master_key).as_storage_client()
+                },
+                _ => storage_client.clone()
+            };
+
+            let (source, target) = futures::try_join!(
+                list_all_blobs(&storage_client, container, prefix),
+                list_all_blobs(&target_client, target_container, target_prefix)
+            )?;
+
+            // Index both sides by the blob name relative to the prefix
+            let index = |blobs: &[Blob], prefix: Option<&str>| -> BTreeMap<String, Blob> {
+                blobs.iter()
+                    .map(|blob| {
+                        let name = blob.name.strip_prefix(prefix.unwrap_or("")).unwrap_or(&blob.name);
+                        (name.to_string(), blob.clone())
+                    })
+                    .collect()
+            };
+            let source = index(&source, prefix);
+            let target = index(&target, target_prefix);
+
+            let mut differences = 0;
+            for (name, blob) in source.iter() {
+                match target.get(name) {
+                    None => println!("- {}", name),
+                    Some(other) if blob.properties.content_length != other.properties.content_length =>
+                        println!("* {} (size {} != {})", name,
+                            blob.properties.content_length, other.properties.content_length),
+                    Some(other) if blob.properties.content_md5 != other.properties.content_md5 =>
+                        println!("* {} (md5 {:?} != {:?})", name,
+                            blob.properties.content_md5, other.properties.content_md5),
+                    Some(_) => continue,
+                }
+                differences += 1;
+            }
+            for name in target.keys().filter(|name| !source.contains_key(*name)) {
+                println!("+ {}", name);
+                differences += 1;
+            }
+
+            if differences > 0 {
+                return Err(anyhow!("{} differences found", differences).into());
+            }
+            println!("No differences in {} blobs", source.len());
+        },
+
         // Error
         Some(_) => {
             return Err(anyhow!("Invalid mode").into())
@@ -301,9 +1900,252 @@ async fn azure_storage(storage_client: Arc<StorageClient>, mode: Option<&str>, c
     Ok(())
 }
 
+// Check the storage account is accessible by a cheap request
+#[tokio::main]
+async fn check_access(storage_client: Arc<StorageClient>) -> Result<(), Box<dyn Error + Send + Sync>> {
+    storage_client
+        .list_containers()
+        .execute()
+        .await?;
+    Ok(())
+}
+
 fn debug_print<T>(obj: T, debug: bool) where T: fmt::Debug
 {
     if debug {
-        println!("\n{:#?}", obj);
+        println!("\n{}", secret::redact_signatures(&format!("{:#?}", obj)));
     }
-}
\ No newline at end of file
+}
+
+// List all containers in the storage account, following continuation markers
+async fn list_all_containers(storage_client: &StorageClient)
+    -> Result<Vec<Container>, Box<dyn Error + Send + Sync>>
+{
+    let mut containers = Vec::new();
+    let mut pages = Box::pin(storage_client.list_containers().stream());
+    while let Some(res) = pages.next().await {
+        containers.extend(res?.incomplete_vector.iter().cloned());
+    }
+    Ok(containers)
+}
+
+// List all blobs in a container, following continuation markers
+async fn list_all_blobs(storage_client: &StorageClient, container: &str, prefix: Option<&str>)
+    -> Result<Vec<Blob>, Box<dyn Error + Send + Sync>>
+{
+    let container_client = storage_client.as_container_client(container);
+    // Metadata has the hashes of blobs by algorithms other than MD5
+    let mut request = container_client.list_blobs().include_metadata(true);
+    if let Some(prefix) = prefix {
+        request = request.prefix(prefix);
+    }
+
+    let mut blobs = Vec::new();
+    let mut pages = Box::pin(request.stream());
+    while let Some(res) = pages.next().await {
+        blobs.extend(res?.blobs.blobs);
+    }
+    Ok(blobs)
+}
+
+// Print blobs in a container whose name matches the pattern, page by page
+async fn find_blobs(storage_client: &StorageClient, container: &str, re: &Regex)
+    -> Result<(), Box<dyn Error + Send + Sync>>
+{
+    let container_client = storage_client.as_container_client(container);
+    let mut pages = Box::pin(container_client.list_blobs().stream());
+    while let Some(res) = pages.next().await {
+        for blob in res?.blobs.blobs.iter().filter(|blob| re.is_match(&blob.name)) {
+            println!(" {} {:>8} {:>10} {}/{}",
+                blob.properties.last_modified,
+                blob.properties.content_length,
+                blob.properties.blob_type.to_string(),
+                container,
+                blob.name);
+        }
+    }
+    Ok(())
+}
+
+// Print blobs in a container page by page until the time budget runs out. The continuation token of the next page
+// is saved in the state file to resume from there by the next run, and the file is removed when all pages are listed.
+// A page which cannot be got within the budget is left to the next run. The token given overrides the state file.
+async fn list_blobs_within(storage_client: &StorageClient, container: &str, budget: std::time::Duration,
+    state_file: &Path, token: Option<&str>, out: &mut output::Output<'_>) -> Result<(), Box<dyn Error + Send + Sync>>
+{
+    let started = std::time::Instant::now();
+    let container_client = storage_client.as_container_client(container);
+    let mut marker = token.map(String::from)
+        .or_else(|| std::fs::read_to_string(state_file).ok())
+        .map(|v| v.trim().to_string())
+        .filter(|v| !v.is_empty());
+    if marker.is_some() && token.is_none() {
+        println!("Resuming list of container '{}' by {:?}", container, state_file);
+    }
+
+    let mut listed = 0;
+    loop {
+        let mut request = container_client.list_blobs();
+        if let Some(marker) = &marker {
+            request = request.next_marker(NextMarker::new(marker.clone()));
+        }
+        let res = match tokio::time::timeout(budget.saturating_sub(started.elapsed()), request.execute()).await {
+            Ok(res) => res?,
+            Err(_) => break
+        };
+        for blob in res.blobs.blobs.iter() {
+            out.line(format!(" {} {:>8} {:>10} {}",
+                blob.properties.last_modified,
+                blob.properties.content_length,
+                blob.properties.blob_type.to_string(),
+                blob.name)).await?;
+        }
+        listed += res.blobs.blobs.len();
+        marker = res.next_marker.map(|marker| marker.as_str().to_string());
+        if marker.is_none() {
+            if state_file.exists() {
+                std::fs::remove_file(state_file)?;
+            }
+            println!("List of {} blobs in container '{}' completed", listed, container);
+            return Ok(());
+        }
+        if started.elapsed() >= budget {
+            break;
+        }
+    }
+
+    // Stopped by the time budget. Save the continuation token unless no page was listed from the beginning.
+    if let Some(marker) = &marker {
+        std::fs::write(state_file, marker)?;
+        println!("Continuation token: {}", marker);
+    }
+    println!("List of {} blobs in container '{}' stopped by the time budget, continued by the next run from {:?}",
+        listed, container, state_file);
+    Ok(())
+}
+
+// Parse a size in bytes with an optional binary unit suffix, such as "512", "4K", "16M" or "1G"
+fn parse_size(size: &str) -> Result<u64, Box<dyn Error + Send + Sync>> {
+    let size = size.trim();
+    let (number, unit) = match size.find(|c: char| !c.is_ascii_digit()) {
+        Some(pos) => size.split_at(pos),
+        None => (size, "")
+    };
+    let multiplier: u64 = match unit.to_ascii_uppercase().trim_end_matches('B') {
+        "" => 1,
+        "K" => 1 << 10,
+        "M" => 1 << 20,
+        "G" => 1 << 30,
+        _ => return Err(anyhow!("Invalid size '{}'", size).into())
+    };
+    let number: u64 = number.parse().map_err(|_| anyhow!("Invalid size '{}'", size))?;
+    number.checked_mul(multiplier).ok_or(anyhow!("Size '{}' is too large", size).into())
+}
+
+// Parse a byte range "<start>-<end>" with the end inclusive as HTTP ranges, or "<start>-" to the end.
+// Each position may have a unit suffix. Returns the start and the exclusive end if any.
+fn parse_range(range: &str) -> Result<(u64, Option<u64>), Box<dyn Error + Send + Sync>> {
+    let (start, end) = range.split_once('-')
+        .ok_or(anyhow!("Invalid range '{}'. Specify as <start>-<end> or <start>-", range))?;
+    let start = parse_size(start)?;
+    let end = match end.trim() {
+        "" => None,
+        end => Some(parse_size(end)? + 1)
+    };
+    if matches!(end, Some(end) if end <= start) {
+        return Err(anyhow!("Invalid range '{}'. The end is before the start", range).into());
+    }
+    Ok((start, end))
+}
+
+// Parse a duration with a unit suffix, such as "45s", "90m", "12h", "30d" or "2w". Seconds if no unit.
+fn parse_duration(duration: &str) -> Result<Duration, Box<dyn Error + Send + Sync>> {
+    let duration = duration.trim();
+    let (number, unit) = match duration.find(|c: char| !c.is_ascii_digit()) {
+        Some(pos) => duration.split_at(pos),
+        None => (duration, "")
+    };
+    let number: i64 = number.parse().map_err(|_| anyhow!("Invalid duration '{}'", duration))?;
+    match unit {
+        "" | "s" => Ok(Duration::seconds(number)),
+        "m" => Ok(Duration::minutes(number)),
+        "h" => Ok(Duration::hours(number)),
+        "d" => Ok(Duration::days(number)),
+        "w" => Ok(Duration::weeks(number)),
+        _ => Err(anyhow!("Invalid duration '{}'. Specify with s, m, h, d or w", duration).into())
+    }
+}
+
+// Filter of blobs to get by --filter-meta, --modified-after and --modified-before
+fn blob_filter(args: &ArgMatches<'_>) -> Result<filter::BlobFilter, Box<dyn Error + Send + Sync>> {
+    let metadata = args.values_of("filter meta").map(|values| values.collect::<Vec<_>>()).unwrap_or_default();
+    filter::BlobFilter::new(&metadata, args.value_of("modified after"), args.value_of("modified before"))
+}
+
+// Convert a glob pattern ('*' and '?' wildcards) to an anchored regular expression
+fn glob_to_regex(glob: &str) -> String {
+    let mut re = String::from("^");
+    for c in glob.chars() {
+        match c {
+            '*' => re.push_str(".*"),
+            '?' => re.push('.'),
+            _ => re.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    re.push('$');
+    re
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_size_units() {
+        assert_eq!(parse_size("512").unwrap(), 512);
+        assert_eq!(parse_size("1K").unwrap(), 1024);
+        assert_eq!(parse_size("4M").unwrap(), 4 << 20);
+        assert_eq!(parse_size("16mb").unwrap(), 16 << 20);
+        assert_eq!(parse_size(" 2G ").unwrap(), 2 << 30);
+        assert!(parse_size("").is_err());
+        assert!(parse_size("1.5M").is_err());
+        assert!(parse_size("4T").is_err());
+        assert!(parse_size("M").is_err());
+        assert!(parse_size("99999999999G").is_err());
+    }
+
+    #[test]
+    fn parse_range_inclusive_end() {
+        assert_eq!(parse_range("0-99").unwrap(), (0, Some(100)));
+        assert_eq!(parse_range("1M-2M").unwrap(), (1 << 20, Some((2 << 20) + 1)));
+        assert_eq!(parse_range("1K-").unwrap(), (1024, None));
+        assert!(parse_range("100").is_err());
+        assert!(parse_range("100-99").is_err());
+        assert!(parse_range("-100").is_err());
+    }
+
+    #[test]
+    fn parse_duration_units() {
+        assert_eq!(parse_duration("45").unwrap(), Duration::seconds(45));
+        assert_eq!(parse_duration("45s").unwrap(), Duration::seconds(45));
+        assert_eq!(parse_duration("90m").unwrap(), Duration::minutes(90));
+        assert_eq!(parse_duration("12h").unwrap(), Duration::hours(12));
+        assert_eq!(parse_duration("30d").unwrap(), Duration::days(30));
+        assert_eq!(parse_duration("2w").unwrap(), Duration::weeks(2));
+        assert!(parse_duration("").is_err());
+        assert!(parse_duration("7y").is_err());
+        assert!(parse_duration("-1d").is_err());
+    }
+
+    #[test]
+    fn glob_to_regex_anchors_and_escapes() {
+        assert_eq!(glob_to_regex("*.log"), r"^.*\.log$");
+        assert_eq!(glob_to_regex("a?c"), "^a.c$");
+        let re = Regex::new(&glob_to_regex("logs/*.tar.gz")).unwrap();
+        assert!(re.is_match("logs/2021-07-27.tar.gz"));
+        assert!(!re.is_match("logs/2021-07-27.tar.gz.sig"));
+        assert!(!re.is_match("old/logs/2021-07-27.tar.gz"));
+        assert!(!re.is_match("logs/2021-07-27Xtar.gz"));
+        assert!(Regex::new(&glob_to_regex("a+(b)[c]")).unwrap().is_match("a+(b)[c]"));
+    }
+}
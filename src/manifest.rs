@@ -0,0 +1,129 @@
+use azure_storage::blob::prelude::*;
+use azure_storage::core::prelude::*;
+
+use std::fs;
+use std::path::Path;
+use std::error::Error;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use chrono::Utc;
+use anyhow::anyhow;
+use crate::audit::AuditLog;
+use crate::config::Configs;
+use crate::signature;
+use crate::transport;
+
+// Manifest of a backup run, as evidence of which files were uploaded where and when.
+// It is signed by the minisign signature in "<manifest>.sig" if sign_key or sign_command is configured.
+#[derive(Serialize, Deserialize, Default, Debug)]
+#[serde(default)]
+pub struct Manifest {
+    pub client_request_id: String,
+    pub account: String,
+    pub started: String,
+    pub finished: String,
+    pub files: Vec<ManifestEntry>,
+}
+
+#[derive(Serialize, Deserialize, Default, Debug, Clone)]
+#[serde(default)]
+pub struct ManifestEntry {
+    pub local: String,
+    pub container: String,
+    pub blob: String,
+    pub url: String,
+    pub size: u64,
+    pub sha256: String,
+    pub time: String,
+}
+
+impl ManifestEntry {
+    // Entry of a file to upload, with the hash of its content. The time is set when it is uploaded.
+    pub fn new(storage_client: &StorageClient, local: &Path, container: &str, blob: &str, data: &[u8])
+        -> Result<ManifestEntry, Box<dyn Error + Send + Sync>>
+    {
+        ManifestEntry::with_hash(storage_client, local, container, blob, data.len() as u64,
+            format!("{:x}", Sha256::digest(data)))
+    }
+
+    // Entry of a file whose size and hash are computed while uploading by blocks
+    pub fn with_hash(storage_client: &StorageClient, local: &Path, container: &str, blob: &str, size: u64,
+        sha256: String) -> Result<ManifestEntry, Box<dyn Error + Send + Sync>>
+    {
+        Ok(ManifestEntry {
+            local: local.display().to_string(),
+            container: container.into(),
+            blob: blob.into(),
+            url: transport::blob_url(storage_client, container, blob)?.to_string(),
+            size,
+            sha256,
+            time: String::new(),
+        })
+    }
+}
+
+impl Manifest {
+    pub fn new(cfg: &Configs) -> Manifest {
+        Manifest {
+            client_request_id: cfg.client_request_id.clone(),
+            account: cfg.storage_account.clone(),
+            started: Utc::now().to_rfc3339(),
+            ..Default::default()
+        }
+    }
+
+    // Read a manifest file
+    pub fn from_file(path: &Path) -> Result<Manifest, Box<dyn Error + Send + Sync>> {
+        let data = fs::read(path).map_err(|e| anyhow!("Cannot read manifest {:?}: {}", path, e))?;
+        serde_json::from_slice(&data).map_err(|e| anyhow!("Cannot parse manifest {:?}: {}", path, e).into())
+    }
+
+    // Add uploaded files
+    pub fn add(&mut self, entries: Vec<ManifestEntry>) {
+        self.files.extend(entries);
+    }
+
+    // Finish the run, and write the manifest to the local file and/or the blob "<container>/<blob>",
+    // with the signature next to each.
+    pub async fn write(&mut self, storage_client: &StorageClient, file: Option<&Path>, blob: Option<(&str, &str)>,
+        cfg: &Configs, audit: &AuditLog) -> Result<(), Box<dyn Error + Send + Sync>>
+    {
+        self.finished = Utc::now().to_rfc3339();
+        let data = serde_json::to_vec_pretty(self)?;
+        let signature = match cfg.sign_key.is_empty() && cfg.sign_command.is_empty() {
+            true => None,
+            false => Some(signature::sign(&data, cfg)?)
+        };
+
+        if let Some(path) = file {
+            fs::write(path, &data)?;
+            if let Some(signature) = &signature {
+                fs::write(format!("{}.sig", path.display()), signature)?;
+            }
+            println!("Manifest of {} files written to {:?}", self.files.len(), path);
+        }
+
+        if let Some((container, blob)) = blob {
+            let container_client = storage_client.as_container_client(container);
+            let res = container_client
+                .as_blob_client(blob)
+                .put_block_blob(data)
+                .content_type("application/json")
+                .execute()
+                .await?;
+            audit.record(storage_client, "put", container, blob, &res.request_id).await?;
+
+            if let Some(signature) = signature {
+                let sig_blob = format!("{}.sig", blob);
+                let res = container_client
+                    .as_blob_client(sig_blob.as_str())
+                    .put_block_blob(signature.into_bytes())
+                    .execute()
+                    .await?;
+                audit.record(storage_client, "put", container, &sig_blob, &res.request_id).await?;
+            }
+            println!("Manifest of {} files uploaded to '{}/{}'", self.files.len(), container, blob);
+        }
+        Ok(())
+    }
+}
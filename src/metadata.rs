@@ -0,0 +1,80 @@
+use azure_core::prelude::*;
+use azure_storage::blob::prelude::*;
+use azure_storage::core::prelude::*;
+
+use std::error::Error;
+use anyhow::anyhow;
+use futures::stream::{self, StreamExt};
+use crate::audit::AuditLog;
+
+// Parse metadata given as <name>=<value>
+pub fn parse_pairs(pairs: &[&str]) -> Result<Vec<(String, String)>, Box<dyn Error + Send + Sync>> {
+    pairs.iter()
+        .map(|pair| match pair.split_once('=') {
+            Some((name, value)) if !name.trim().is_empty() => Ok((name.trim().to_string(), value.to_string())),
+            _ => Err(anyhow!("Invalid metadata '{}'. Specify as <name>=<value>", pair).into())
+        })
+        .collect()
+}
+
+// Set the metadata on all blobs with the prefix, keeping other metadata of each blob, such as to tag
+// historical uploads. Blobs which already have the same values are skipped, so that it can be run again
+// after a failure. Fails after trying all blobs if any failed.
+pub async fn set_metadata(storage_client: &StorageClient, container: &str, prefix: &str, pairs: &[(String, String)],
+    concurrency: usize, dry_run: bool, audit: &AuditLog) -> Result<(), Box<dyn Error + Send + Sync>>
+{
+    let blobs = crate::list_all_blobs(storage_client, container, Some(prefix)).await?;
+    let container_client = storage_client.as_container_client(container);
+
+    let targets: Vec<_> = blobs.iter()
+        .filter(|blob| {
+            let current = blob.metadata.as_ref();
+            pairs.iter().any(|(name, value)| current.and_then(|metadata| metadata.get(name)) != Some(value))
+        })
+        .collect();
+
+    if dry_run {
+        for blob in targets.iter() {
+            println!(" set metadata of {} (dry run)", blob.name);
+        }
+        println!("Would set metadata of {} blobs of {} in '{}/{}'", targets.len(), blobs.len(), container, prefix);
+        return Ok(());
+    }
+
+    let results: Vec<_> = stream::iter(targets.iter())
+        .map(|blob| {
+            let container_client = &container_client;
+            async move {
+                let mut metadata = Metadata::new();
+                for (name, value) in blob.metadata.clone().unwrap_or_default() {
+                    metadata.insert(name, value);
+                }
+                for (name, value) in pairs.iter() {
+                    metadata.insert(name.as_str(), value.as_str());
+                }
+                let result = async {
+                    let res = container_client.as_blob_client(blob.name.as_str())
+                        .set_metadata()
+                        .metadata(&metadata)
+                        .execute()
+                        .await?;
+                    audit.record(storage_client, "set-metadata", container, &blob.name, &res.request_id).await
+                }.await;
+                match &result {
+                    Ok(()) => println!(" set metadata of {}", blob.name),
+                    Err(e) => eprintln!(" set metadata of {} failed: {}", blob.name, e)
+                }
+                result
+            }
+        })
+        .buffer_unordered(concurrency.max(1))
+        .collect()
+        .await;
+
+    let failed = results.iter().filter(|result| result.is_err()).count();
+    if failed > 0 {
+        return Err(anyhow!("{} of {} blobs failed to set metadata", failed, targets.len()).into());
+    }
+    println!("Set metadata of {} blobs of {} in '{}/{}'", targets.len(), blobs.len(), container, prefix);
+    Ok(())
+}
@@ -0,0 +1,101 @@
+use azure_core::{HttpClient, HttpError};
+use azure_storage::core::prelude::*;
+
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::error::Error;
+use async_trait::async_trait;
+use bytes::Bytes;
+use http::{HeaderMap, Method, Request, Response, StatusCode, Uri};
+use http::response::Builder;
+
+// Account and key of the storage emulator, which requests of tests are signed by
+pub const ACCOUNT: &str = "devstoreaccount1";
+pub const KEY: &str = "Eby8vdM02xNOcqFlqUwJPLlmEtlCDXJ1OUzFT50uSRZ6IFsuFq2UVErCz4I6tq/K1SZFPTOtr/KBHBeksoGMGw==";
+
+// Request received by the mock
+#[derive(Clone, Debug)]
+pub struct Recorded {
+    pub method: Method,
+    pub uri: Uri,
+    pub headers: HeaderMap,
+    pub body: Bytes,
+}
+
+impl Recorded {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name).and_then(|v| v.to_str().ok())
+    }
+
+    // Whether the query has the parameter, such as "comp=blocklist"
+    pub fn has_query(&self, param: &str) -> bool {
+        self.uri.query().unwrap_or("").split('&').any(|v| v == param)
+    }
+}
+
+type Handler = dyn Fn(&Recorded) -> Response<Bytes> + Send + Sync;
+
+// HTTP client which records requests and responds by the handler, instead of sending them to the storage
+pub struct MockHttpClient {
+    handler: Box<Handler>,
+    requests: Mutex<Vec<Recorded>>,
+}
+
+impl fmt::Debug for MockHttpClient {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("MockHttpClient")
+    }
+}
+
+impl MockHttpClient {
+    pub fn requests(&self) -> Vec<Recorded> {
+        self.requests.lock().unwrap().clone()
+    }
+}
+
+#[async_trait]
+impl HttpClient for MockHttpClient {
+    async fn execute_request(&self, request: Request<Bytes>)
+        -> Result<Response<Bytes>, Box<dyn Error + Sync + Send>>
+    {
+        let (parts, body) = request.into_parts();
+        let recorded = Recorded { method: parts.method, uri: parts.uri, headers: parts.headers, body };
+        let res = (self.handler)(&recorded);
+        self.requests.lock().unwrap().push(recorded);
+        Ok(res)
+    }
+
+    async fn execute_request2(&self, _request: &azure_core::Request)
+        -> Result<azure_core::Response, HttpError>
+    {
+        Err(HttpError::StatusCode { status: StatusCode::NOT_IMPLEMENTED, body: "Not mocked".into() })
+    }
+}
+
+// Storage client of the emulator account whose requests are handled by the handler
+pub fn storage_client<F>(handler: F) -> (Arc<StorageClient>, Arc<MockHttpClient>)
+    where F: Fn(&Recorded) -> Response<Bytes> + Send + Sync + 'static
+{
+    let http_client = Arc::new(MockHttpClient { handler: Box::new(handler), requests: Mutex::new(Vec::new()) });
+    let storage_client = StorageAccountClient::new_access_key(http_client.clone(), ACCOUNT, KEY).as_storage_client();
+    (storage_client, http_client)
+}
+
+// Response of the status with the headers which the service returns to all requests
+pub fn response(status: StatusCode) -> Builder {
+    Response::builder()
+        .status(status)
+        .header("date", "Fri, 01 Oct 2021 00:00:00 GMT")
+        .header("last-modified", "Fri, 01 Oct 2021 00:00:00 GMT")
+        .header("etag", "\"0x8D98452B1F0E5A1\"")
+        .header("x-ms-request-id", "00000000-0000-0000-0000-000000000000")
+        .header("x-ms-version", "2019-12-12")
+        .header("x-ms-request-server-encrypted", "true")
+        .header("content-md5", "AAAAAAAAAAAAAAAAAAAAAA==")
+        .header("x-ms-content-crc64", "AAAAAAAAAAA=")
+}
+
+// Empty response of the status
+pub fn status(status: StatusCode) -> Response<Bytes> {
+    response(status).header("content-length", "0").body(Bytes::new()).unwrap()
+}
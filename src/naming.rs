@@ -0,0 +1,16 @@
+use std::error::Error;
+
+/// Policy to name blobs of local files uploaded from a directory
+pub trait NamingPolicy: Send + Sync {
+    /// Blob name of a file by the prefix and the relative path of the file, separated by '/'
+    fn blob_name(&self, prefix: &str, relative_path: &str) -> Result<String, Box<dyn Error + Send + Sync>>;
+}
+
+/// The prefix followed by the relative path
+pub struct DefaultNaming;
+
+impl NamingPolicy for DefaultNaming {
+    fn blob_name(&self, prefix: &str, relative_path: &str) -> Result<String, Box<dyn Error + Send + Sync>> {
+        Ok(format!("{}{}", prefix, relative_path))
+    }
+}
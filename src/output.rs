@@ -0,0 +1,89 @@
+use azure_storage::blob::prelude::*;
+use azure_storage::core::prelude::*;
+
+use std::error::Error;
+use anyhow::anyhow;
+use crate::audit::AuditLog;
+
+// Maximum number of blocks in a block blob
+const MAX_BLOCKS: usize = 50_000;
+
+// Lines of a listing written to stdout, or to a blob in the same account instead of local disk,
+// for scheduled runs on devices with tiny storage. Lines are staged as blocks of the block size as they are written,
+// so that only a block is in memory, and committed at the end. The blob is not modified if the listing fails.
+pub struct Output<'a> {
+    storage_client: &'a StorageClient,
+    target: Option<(String, String)>,
+    block_size: usize,
+    buffer: Vec<u8>,
+    block_ids: Vec<BlockId>,
+}
+
+impl<'a> Output<'a> {
+    // Output to the blob given as <container>/<blob>, or to stdout if None
+    pub fn new(storage_client: &'a StorageClient, target: Option<&str>, block_size: u64)
+        -> Result<Output<'a>, Box<dyn Error + Send + Sync>>
+    {
+        let target = match target {
+            Some(target) => match target.split_once('/') {
+                Some((container, blob)) if !container.is_empty() && !blob.is_empty() =>
+                    Some((container.to_string(), blob.to_string())),
+                _ => return Err(anyhow!("Invalid output blob '{}'. Specify as <container>/<blob>", target).into())
+            },
+            None => None
+        };
+        Ok(Output { storage_client, target, block_size: block_size as usize, buffer: Vec::new(), block_ids: Vec::new() })
+    }
+
+    pub async fn line(&mut self, line: String) -> Result<(), Box<dyn Error + Send + Sync>> {
+        if self.target.is_none() {
+            println!("{}", line);
+            return Ok(());
+        }
+        self.buffer.extend_from_slice(line.as_bytes());
+        self.buffer.push(b'\n');
+        if self.buffer.len() >= self.block_size {
+            self.put_block().await?;
+        }
+        Ok(())
+    }
+
+    // Commit the lines written to the blob, replacing the blob of the last run
+    pub async fn finish(mut self, audit: &AuditLog, debug: bool) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let (container, blob) = match self.target.clone() {
+            Some(target) => target,
+            None => return Ok(())
+        };
+        if !self.buffer.is_empty() {
+            self.put_block().await?;
+        }
+        let blob_client = self.storage_client.as_container_client(container.as_str()).as_blob_client(blob.as_str());
+        let block_list = BlockList {
+            blocks: self.block_ids.drain(..).map(BlobBlockType::new_uncommitted).collect()
+        };
+        let res = blob_client.put_block_list(&block_list).execute().await?;
+        audit.record(self.storage_client, "put", &container, &blob, &res.request_id).await?;
+        crate::debug_print(res, debug);
+        println!("Output written to '{}/{}'", container, blob);
+        Ok(())
+    }
+
+    async fn put_block(&mut self) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let (container, blob) = self.target.as_ref().ok_or(anyhow!("No output blob"))?;
+        if self.block_ids.len() >= MAX_BLOCKS {
+            return Err(anyhow!("Output exceeds {} blocks of {} bytes. Specify larger --block-size",
+                MAX_BLOCKS, self.block_size).into());
+        }
+        // Block IDs must have the same length in a blob
+        let block_id = BlockId::new(format!("{:08}", self.block_ids.len()).into_bytes());
+        let buffer = std::mem::take(&mut self.buffer);
+        let md5 = md5::compute(&buffer).into();
+        self.storage_client.as_container_client(container.as_str()).as_blob_client(blob.as_str())
+            .put_block(block_id.clone(), buffer)
+            .hash(&md5)
+            .execute()
+            .await?;
+        self.block_ids.push(block_id);
+        Ok(())
+    }
+}
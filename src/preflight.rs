@@ -0,0 +1,103 @@
+use azure_core::prelude::*;
+use azure_storage::blob::prelude::*;
+use azure_storage::core::prelude::*;
+
+use std::error::Error;
+use anyhow::anyhow;
+use http::StatusCode;
+use crate::transport;
+
+// Blob permissions needed by operations, probed one by one
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Permission {
+    Read,
+    List,
+    Write,
+    Add,
+    Delete,
+}
+
+impl Permission {
+    fn name(self) -> &'static str {
+        match self {
+            Permission::Read => "read",
+            Permission::List => "list",
+            Permission::Write => "write",
+            Permission::Add => "add",
+            Permission::Delete => "delete",
+        }
+    }
+
+    // Permission of SAS tokens and data action of Azure RBAC which grant it
+    fn grants(self) -> (&'static str, &'static str) {
+        match self {
+            Permission::Read => ("r", "blobs/read"),
+            Permission::List => ("l", "blobs/read"),
+            Permission::Write => ("w", "blobs/write"),
+            Permission::Add => ("a", "blobs/add/action"),
+            Permission::Delete => ("d", "blobs/delete"),
+        }
+    }
+}
+
+// Permissions needed by the planned operation
+fn permissions(operation: &str) -> Result<&'static [Permission], Box<dyn Error + Send + Sync>> {
+    use Permission::*;
+    Ok(match operation {
+        "list" | "find" => &[List],
+        "get" | "cat" | "head" | "tail" | "verify" => &[Read],
+        "put" | "watch" => &[Write],
+        "append" => &[Write, Add],
+        "delete" => &[Delete],
+        "sync" => &[List, Read, Write, Delete],
+        "expire" => &[List, Delete],
+        "set-metadata" => &[List, Write],
+        "push-outbox" => &[List, Write],
+        "pull-inbox" => &[List, Read, Delete],
+        v => return Err(anyhow!("Unknown operation '{}' to check", v).into())
+    })
+}
+
+// Check whether the credentials allow the planned operation in the container, by cheap probe requests
+// to a blob which does not exist. Nothing is modified: writes are conditional on an ETag which never matches,
+// and appends and deletes fail on the missing blob. The service checks the authorization first,
+// so 403 means the permission is missing, and other failures mean it is granted.
+pub async fn preflight(storage_client: &StorageClient, container: &str, operation: &str)
+    -> Result<(), Box<dyn Error + Send + Sync>>
+{
+    let probe = format!(".azst-preflight-{}", uuid::Uuid::new_v4());
+    let container_client = storage_client.as_container_client(container);
+    let blob_client = container_client.as_blob_client(probe.as_str());
+
+    let mut missing = 0;
+    for &permission in permissions(operation)? {
+        let result = match permission {
+            Permission::Read => blob_client.get_properties().execute().await.map(|_| ()),
+            Permission::List => container_client.list_blobs().prefix(probe.as_str()).execute().await.map(|_| ()),
+            Permission::Write => blob_client.put_block_blob(Vec::new())
+                .if_match_condition(IfMatchCondition::Match("\"0x0\"".into()))
+                .execute().await.map(|_| ()),
+            Permission::Add => blob_client.append_block(b"\n".to_vec()).execute().await.map(|_| ()),
+            Permission::Delete => blob_client.delete().execute().await.map(|_| ()),
+        };
+        let (sas, rbac) = permission.grants();
+        match result.as_ref().map_err(|e| transport::error_status(e.as_ref())) {
+            Ok(_) | Err(Some(StatusCode::NOT_FOUND | StatusCode::PRECONDITION_FAILED | StatusCode::CONFLICT)) =>
+                println!(" OK      {:<6}", permission.name()),
+            Err(Some(StatusCode::FORBIDDEN)) => {
+                println!(" MISSING {:<6} SAS permission '{}' or RBAC data action '{}'", permission.name(), sas, rbac);
+                missing += 1;
+            },
+            Err(_) => {
+                println!(" UNKNOWN {:<6} {}", permission.name(), result.unwrap_err());
+                missing += 1;
+            }
+        }
+    }
+
+    if missing > 0 {
+        return Err(anyhow!("{} permissions for {} in '{}' are missing or unknown", missing, operation, container).into());
+    }
+    println!("Credentials allow {} in '{}'", operation, container);
+    Ok(())
+}
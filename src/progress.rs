@@ -0,0 +1,76 @@
+use std::path::Path;
+use std::sync::Mutex;
+use indicatif::{MultiProgress, ProgressBar, ProgressDrawTarget, ProgressStyle};
+
+const FILE_TEMPLATE: &str = "{msg:40!} {bytes:>10}/{total_bytes:10} {bytes_per_sec:>12} {eta:>4}";
+const TOTAL_TEMPLATE: &str = "{msg:40!} {bytes:>10}/{total_bytes:10} {bytes_per_sec:>12} {eta:>4} [{bar:20}]";
+
+// Progress of transfers shown on stderr, with a bar per file and the overall bar of bulk transfers.
+// Nothing is shown if disabled or stderr is not a terminal, such as in scripts and services.
+pub struct Progress {
+    multi: Option<MultiProgress>,
+    total: Mutex<ProgressBar>,
+}
+
+impl Progress {
+    pub fn new(enabled: bool) -> Progress {
+        let target = ProgressDrawTarget::stderr();
+        let multi = match enabled && !target.is_hidden() {
+            true => Some(MultiProgress::with_draw_target(target)),
+            false => None
+        };
+        Progress { multi, total: Mutex::new(ProgressBar::hidden()) }
+    }
+
+    // Start the overall progress of the files and bytes to transfer
+    pub fn start_total(&self, files: usize, bytes: u64) {
+        if let Some(multi) = &self.multi {
+            let bar = multi.add(ProgressBar::new(bytes).with_style(style(TOTAL_TEMPLATE)));
+            bar.set_message(format!("Total {} files", files));
+            *self.total.lock().unwrap() = bar;
+        }
+    }
+
+    // Progress of a file of the size, counted in the overall progress too
+    pub fn file(&self, name: &Path, size: u64) -> FileProgress {
+        let total = self.total.lock().unwrap().clone();
+        let bar = match &self.multi {
+            Some(multi) => {
+                // File bars are shown above the overall bar
+                let bar = ProgressBar::new(size).with_style(style(FILE_TEMPLATE));
+                let bar = if total.is_hidden() { multi.add(bar) } else { multi.insert_before(&total, bar) };
+                bar.set_message(name.display().to_string());
+                bar
+            },
+            None => ProgressBar::hidden()
+        };
+        FileProgress { bar, total }
+    }
+
+    pub fn finish(&self) {
+        self.total.lock().unwrap().finish_and_clear();
+    }
+}
+
+fn style(template: &str) -> ProgressStyle {
+    ProgressStyle::with_template(template).unwrap_or_else(|_| ProgressStyle::default_bar())
+}
+
+// Progress of a file, cleared when dropped
+pub struct FileProgress {
+    bar: ProgressBar,
+    total: ProgressBar,
+}
+
+impl FileProgress {
+    pub fn inc(&self, bytes: u64) {
+        self.bar.inc(bytes);
+        self.total.inc(bytes);
+    }
+}
+
+impl Drop for FileProgress {
+    fn drop(&mut self) {
+        self.bar.finish_and_clear();
+    }
+}
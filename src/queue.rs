@@ -0,0 +1,167 @@
+use azure_core::prelude::*;
+use azure_storage::blob::prelude::*;
+use azure_storage::core::prelude::*;
+
+use std::io::prelude::*;
+use std::io;
+use std::fs;
+use std::path::Path;
+use std::convert::TryInto;
+use std::error::Error;
+use anyhow::anyhow;
+use crate::audit::AuditLog;
+use crate::transport;
+use crate::upload::{self, APPEND_BLOCK_SIZE};
+
+// Each record is a 4 bytes big endian length followed by the payload
+const LENGTH_SIZE: usize = 4;
+
+// Append a record to the append blob. The blob is created if not exist.
+// A record is appended by one request, so that records from multiple publishers never interleave.
+// Fails before sending if the record exceeds the maximum size of an append block.
+pub async fn publish(storage_client: &StorageClient, container: &str, blob: &str, payload: &[u8],
+    audit: &AuditLog) -> Result<(), Box<dyn Error + Send + Sync>>
+{
+    let blob_client = storage_client
+        .as_container_client(container)
+        .as_blob_client(blob);
+
+    if (LENGTH_SIZE + payload.len()) as u64 > APPEND_BLOCK_SIZE {
+        return Err(anyhow!("Message is too large. Maximum size is {} bytes", APPEND_BLOCK_SIZE - LENGTH_SIZE as u64).into());
+    }
+    let record = frame(payload);
+    let hash = md5::compute(&record).into();
+
+    let res = match blob_client.append_block(record.clone()).hash(&hash).execute().await {
+        Ok(res) => res,
+        Err(e) if transport::is_not_found(e.as_ref()) => {
+            upload::create_append_blob(storage_client, container, blob, Some(audit)).await?;
+            blob_client.append_block(record).hash(&hash).execute().await?
+        },
+        Err(e) => return Err(e)
+    };
+    audit.record(storage_client, "publish", container, blob, &res.request_id).await
+}
+
+// Print records after the offset saved in the offset file, one record per line, and save the new offset.
+// The offset is saved after all records are printed, so that each record is consumed at least once.
+pub async fn consume(storage_client: &StorageClient, container: &str, blob: &str, offset_file: &Path,
+    debug: bool) -> Result<(), Box<dyn Error + Send + Sync>>
+{
+    let blob_client = storage_client
+        .as_container_client(container)
+        .as_blob_client(blob);
+
+    let offset: u64 = match fs::read_to_string(offset_file) {
+        Ok(v) => v.trim().parse().map_err(|_| anyhow!("Invalid offset in {:?}", offset_file))?,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => 0,
+        Err(e) => return Err(e.into())
+    };
+
+    let size = blob_client.get_properties().execute().await?.blob.properties.content_length;
+    if debug {
+        println!("offset = {}, blob size = {}", offset, size);
+    }
+    if offset >= size {
+        return Ok(());
+    }
+
+    let res = blob_client
+        .get()
+        .range(Range::new(offset, size))
+        .execute()
+        .await?;
+
+    // Print complete records only
+    let (records, pos) = unframe(&res.data);
+    let stdout = io::stdout();
+    let mut stdout = stdout.lock();
+    for record in records {
+        stdout.write_all(record)?;
+        stdout.write_all(b"\n")?;
+    }
+    stdout.flush()?;
+
+    fs::write(offset_file, (offset + pos as u64).to_string())?;
+    Ok(())
+}
+
+// Record of the payload with its length
+fn frame(payload: &[u8]) -> Vec<u8> {
+    let mut record = (payload.len() as u32).to_be_bytes().to_vec();
+    record.extend_from_slice(payload);
+    record
+}
+
+// Payloads of the complete records in the data, and the size of them. A record being appended is left.
+fn unframe(data: &[u8]) -> (Vec<&[u8]>, usize) {
+    let mut records = Vec::new();
+    let mut pos = 0;
+    while pos + LENGTH_SIZE <= data.len() {
+        let length = u32::from_be_bytes(data[pos..pos + LENGTH_SIZE].try_into().unwrap()) as usize;
+        let end = pos + LENGTH_SIZE + length;
+        if end > data.len() {
+            break;
+        }
+        records.push(&data[pos + LENGTH_SIZE..end]);
+        pos = end;
+    }
+    (records, pos)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http::StatusCode;
+    use crate::config::Configs;
+    use crate::mock;
+
+    fn appended(_: &mock::Recorded) -> http::Response<bytes::Bytes> {
+        mock::response(StatusCode::CREATED)
+            .header("x-ms-blob-append-offset", "0")
+            .header("x-ms-blob-committed-block-count", "1")
+            .header("content-length", "0")
+            .body(bytes::Bytes::new())
+            .unwrap()
+    }
+
+    #[test]
+    fn unframe_returns_framed_payloads() {
+        let mut data = frame(b"first");
+        data.extend(frame(b""));
+        data.extend(frame(b"third"));
+        let (records, size) = unframe(&data);
+        assert_eq!(records, vec![&b"first"[..], &b""[..], &b"third"[..]]);
+        assert_eq!(size, data.len());
+    }
+
+    #[test]
+    fn unframe_leaves_incomplete_records() {
+        let mut data = frame(b"first");
+        let first = data.len();
+        data.extend(&frame(b"second")[..7]);
+        assert_eq!(unframe(&data), (vec![&b"first"[..]], first));
+        assert_eq!(unframe(&data[..2]), (vec![], 0));
+    }
+
+    #[tokio::test]
+    async fn publish_appends_one_record_per_request() {
+        let (storage_client, http_client) = mock::storage_client(appended);
+        let audit = AuditLog::new(&Configs::default()).unwrap();
+        publish(&storage_client, "queue", "events", b"hello", &audit).await.unwrap();
+
+        let requests = http_client.requests();
+        assert_eq!(requests.len(), 1);
+        assert!(requests[0].has_query("comp=appendblock"));
+        assert_eq!(&requests[0].body[..], &frame(b"hello")[..]);
+    }
+
+    #[tokio::test]
+    async fn publish_rejects_too_large_messages() {
+        let (storage_client, http_client) = mock::storage_client(appended);
+        let audit = AuditLog::new(&Configs::default()).unwrap();
+        let payload = vec![0; APPEND_BLOCK_SIZE as usize];
+        assert!(publish(&storage_client, "queue", "events", &payload, &audit).await.is_err());
+        assert!(http_client.requests().is_empty());
+    }
+}
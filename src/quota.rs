@@ -0,0 +1,117 @@
+use azure_storage::core::prelude::*;
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::error::Error;
+use anyhow::anyhow;
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use crate::config::Configs;
+use crate::transport;
+
+// Usage cached longer than this is measured again by listing the container
+const USAGE_TTL_MINUTES: i64 = 60;
+
+// Total size of blobs in a container measured at the time, plus the size put since
+#[derive(Serialize, Deserialize, Default, Debug, Clone)]
+#[serde(default)]
+struct Usage {
+    size: u64,
+    measured: String,
+}
+
+// Client-side quotas of the total size of blobs per container, checked before uploads so that a runaway device
+// does not fill the storage. The usage is measured by listing the container, and cached in the usage cache file
+// across runs with the size put since. Containers without a quota are not checked.
+pub struct Quota {
+    limits: BTreeMap<String, u64>,
+    warn_only: bool,
+    file: Option<PathBuf>,
+    usage: BTreeMap<String, Usage>,
+}
+
+impl Quota {
+    // Parse the quotas given as "<container>:<size>", and load the usage cache file if configured
+    pub fn from_config(cfg: &Configs) -> Result<Quota, Box<dyn Error + Send + Sync>> {
+        let mut limits = BTreeMap::new();
+        for quota in cfg.quotas.iter() {
+            let (container, size) = quota.split_once(':')
+                .ok_or(anyhow!("Invalid quota '{}'. Specify as <container>:<size>", quota))?;
+            limits.insert(container.trim().to_string(), crate::parse_size(size)?);
+        }
+
+        let file = Some(PathBuf::from(&cfg.quota_cache)).filter(|_| !cfg.quota_cache.is_empty());
+        let usage = match &file {
+            Some(file) => match fs::read(file) {
+                Ok(data) => serde_json::from_slice(&data)
+                    .map_err(|e| anyhow!("Cannot parse usage cache {:?}: {}", file, e))?,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => BTreeMap::new(),
+                Err(e) => return Err(anyhow!("Cannot read usage cache {:?}: {}", file, e).into())
+            },
+            None => BTreeMap::new()
+        };
+        Ok(Quota { limits, warn_only: cfg.quota_warn.unwrap_or(false), file, usage })
+    }
+
+    // Check that the size to put does not exceed the quota of the container with the current usage.
+    // Refused unless warning only.
+    pub async fn check(&mut self, storage_client: &StorageClient, container: &str, size: u64)
+        -> Result<(), Box<dyn Error + Send + Sync>>
+    {
+        let limit = match self.limits.get(container) {
+            Some(&limit) => limit,
+            None => return Ok(())
+        };
+        let used = self.usage(storage_client, container).await?;
+        if used + size <= limit {
+            return Ok(());
+        }
+        let message = format!("Quota of container '{}' would be exceeded: {} used + {} to put > {} bytes",
+            container, used, size, limit);
+        if self.warn_only {
+            eprintln!("Warning: {}", message);
+            return Ok(());
+        }
+        Err(anyhow!(message).into())
+    }
+
+    // Add the size put to the cached usage of the container
+    pub fn add(&mut self, container: &str, size: u64) -> Result<(), Box<dyn Error + Send + Sync>> {
+        if let Some(usage) = self.usage.get_mut(container) {
+            usage.size += size;
+        }
+        self.save()
+    }
+
+    // Usage of the container from the cache, or measured by listing the container if expired
+    async fn usage(&mut self, storage_client: &StorageClient, container: &str) -> Result<u64, Box<dyn Error + Send + Sync>> {
+        let fresh = self.usage.get(container)
+            .and_then(|usage| DateTime::parse_from_rfc3339(&usage.measured).ok())
+            .map_or(false, |measured| Utc::now() - measured.with_timezone(&Utc) < Duration::minutes(USAGE_TTL_MINUTES));
+        if !fresh {
+            // A container which does not exist yet is empty
+            let blobs = match crate::list_all_blobs(storage_client, container, None).await {
+                Ok(blobs) => blobs,
+                Err(e) if transport::is_not_found(e.as_ref()) => Vec::new(),
+                Err(e) => return Err(e)
+            };
+            let usage = Usage {
+                size: blobs.iter().map(|blob| blob.properties.content_length).sum(),
+                measured: Utc::now().to_rfc3339(),
+            };
+            self.usage.insert(container.into(), usage);
+            self.save()?;
+        }
+        Ok(self.usage[container].size)
+    }
+
+    fn save(&self) -> Result<(), Box<dyn Error + Send + Sync>> {
+        if let Some(file) = &self.file {
+            fs::write(file, serde_json::to_vec_pretty(&self.usage)?)
+                .map_err(|e| anyhow!("Cannot write usage cache {:?}: {}", file, e))?;
+        }
+        Ok(())
+    }
+}
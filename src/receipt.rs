@@ -0,0 +1,61 @@
+use azure_storage::blob::prelude::*;
+use azure_storage::core::prelude::*;
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::error::Error;
+use anyhow::anyhow;
+use chrono::Utc;
+use serde::Serialize;
+use crate::config::Configs;
+use crate::hash::HashAlgorithm;
+use crate::signature;
+use crate::transport;
+
+// Receipt of a got blob, as evidence of which content was downloaded from where and when.
+// It is written next to the local file as "<file>.receipt.json", and signed by the minisign signature
+// in "<file>.receipt.json.sig" if sign_key or sign_command is configured.
+#[derive(Serialize, Debug)]
+pub struct Receipt {
+    pub account: String,
+    pub client_request_id: String,
+    pub url: String,
+    pub version_id: String,
+    pub snapshot: String,
+    pub etag: String,
+    pub size: u64,
+    pub sha256: String,
+    pub local: String,
+    pub time: String,
+}
+
+impl Receipt {
+    // Receipt of the blob got into the local file, with the hash of the written file
+    pub fn new(storage_client: &StorageClient, cfg: &Configs, container: &str, blob: &Blob, local: &Path,
+        version_id: Option<&str>, snapshot: Option<&str>) -> Result<Receipt, Box<dyn Error + Send + Sync>>
+    {
+        Ok(Receipt {
+            account: cfg.storage_account.clone(),
+            client_request_id: cfg.client_request_id.clone(),
+            url: transport::blob_url(storage_client, container, &blob.name)?.to_string(),
+            version_id: version_id.unwrap_or_default().into(),
+            snapshot: snapshot.unwrap_or_default().into(),
+            etag: blob.properties.etag.to_string(),
+            size: fs::metadata(local)?.len(),
+            sha256: HashAlgorithm::Sha256.file_digest(local)?,
+            local: fs::canonicalize(local)?.display().to_string(),
+            time: Utc::now().to_rfc3339(),
+        })
+    }
+
+    // Write the receipt next to the local file, with the signature if configured
+    pub fn write(&self, cfg: &Configs) -> Result<PathBuf, Box<dyn Error + Send + Sync>> {
+        let path = PathBuf::from(format!("{}.receipt.json", self.local));
+        let data = serde_json::to_vec_pretty(self)?;
+        fs::write(&path, &data).map_err(|e| anyhow!("Cannot write receipt {:?}: {}", path, e))?;
+        if !cfg.sign_key.is_empty() || !cfg.sign_command.is_empty() {
+            fs::write(format!("{}.sig", path.display()), signature::sign(&data, cfg)?)?;
+        }
+        Ok(path)
+    }
+}
@@ -0,0 +1,88 @@
+use azure_storage::core::prelude::*;
+
+use std::sync::Arc;
+use std::error::Error;
+use futures::FutureExt;
+#[cfg(unix)]
+use tokio::signal::unix::{signal, Signal, SignalKind};
+use tokio::task;
+
+type Load = dyn Fn() -> Result<Arc<StorageClient>, Box<dyn Error + Send + Sync>> + Send + Sync;
+
+// Reload of the config on SIGHUP in long-running modes, so that rotated credentials are used without a restart.
+// The storage client is replaced between polls, so that transfers in flight are completed by the current one.
+pub struct Reload {
+    load: Arc<Load>,
+    #[cfg(unix)]
+    hangup: Option<Signal>,
+}
+
+impl Reload {
+    // Reload by the function, which loads the config layers again and creates a storage client by them
+    pub fn new<F>(load: F) -> Reload
+        where F: Fn() -> Result<Arc<StorageClient>, Box<dyn Error + Send + Sync>> + Send + Sync + 'static
+    {
+        Reload {
+            load: Arc::new(load),
+            #[cfg(unix)]
+            hangup: None,
+        }
+    }
+
+    // Handle SIGHUP instead of being terminated by it
+    pub fn listen(&mut self) -> Result<(), Box<dyn Error + Send + Sync>> {
+        #[cfg(unix)]
+        {
+            self.hangup = Some(signal(SignalKind::hangup())?);
+        }
+        Ok(())
+    }
+
+    // Storage client by the reloaded config, if SIGHUP is received since the last call
+    pub async fn poll(&mut self) -> Option<Arc<StorageClient>> {
+        match self.hangup().now_or_never() {
+            Some(()) => self.load().await,
+            None => None
+        }
+    }
+
+    // Wait until SIGHUP is received, and return the storage client by the reloaded config.
+    // Never returns unless listening.
+    pub async fn reloaded(&mut self) -> Arc<StorageClient> {
+        loop {
+            self.hangup().await;
+            if let Some(storage_client) = self.load().await {
+                return storage_client;
+            }
+        }
+    }
+
+    #[cfg(unix)]
+    async fn hangup(&mut self) {
+        match &mut self.hangup {
+            Some(hangup) => { hangup.recv().await; },
+            None => futures::future::pending().await
+        }
+    }
+
+    #[cfg(not(unix))]
+    async fn hangup(&mut self) {
+        futures::future::pending().await
+    }
+
+    // Load the config. The current one is kept if the new one is invalid, so that a broken edit does not stop the
+    // service. Credential commands and token requests block, so they run outside of the runtime threads.
+    async fn load(&self) -> Option<Arc<StorageClient>> {
+        let load = self.load.clone();
+        let e: Box<dyn Error + Send + Sync> = match task::spawn_blocking(move || load()).await {
+            Ok(Ok(storage_client)) => {
+                println!("Config reloaded");
+                return Some(storage_client);
+            },
+            Ok(Err(e)) => e,
+            Err(e) => e.into()
+        };
+        eprintln!("Cannot reload config, kept the current one: {}", e);
+        None
+    }
+}
@@ -0,0 +1,88 @@
+use azure_storage::core::prelude::*;
+
+use std::sync::Arc;
+use std::error::Error;
+use std::future::Future;
+use anyhow::anyhow;
+use crate::config::Configs;
+use crate::manifest::ManifestEntry;
+use crate::transport;
+
+// Storage account and container files are put to
+pub struct Destination {
+    pub name: String,
+    pub storage_client: Arc<StorageClient>,
+    pub container: String,
+}
+
+// The container of the storage account in use, followed by the replicas in the config
+pub fn destinations(storage_client: &Arc<StorageClient>, cfg: &Configs, container: &str)
+    -> Result<Vec<Destination>, Box<dyn Error + Send + Sync>>
+{
+    if cfg.replicas.is_empty() {
+        return Err(anyhow!("No replicas are configured").into());
+    }
+
+    let mut destinations = vec![Destination {
+        name: format!("{}/{}", cfg.storage_account, container),
+        storage_client: storage_client.clone(),
+        container: container.into(),
+    }];
+    for replica in cfg.replicas.iter() {
+        if replica.storage_account.is_empty() || replica.storage_master_key.is_empty() {
+            return Err(anyhow!("storage_account and storage_master_key of replicas are required").into());
+        }
+        let container = if replica.container.is_empty() { container } else { &replica.container };
+        let http_client = transport::new_http_client(cfg, &replica.storage_account, &replica.storage_master_key)?;
+        let client = StorageAccountClient::new_access_key(
+            http_client, &replica.storage_account, replica.storage_master_key.expose());
+        destinations.push(Destination {
+            name: format!("{}/{}", replica.storage_account, container),
+            storage_client: client.as_storage_client(),
+            container: container.into(),
+        });
+    }
+    Ok(destinations)
+}
+
+// Put to all destinations one by one, or all at once if parallel, and print the status of each.
+// A failed destination does not stop the others. Fails if any destination failed.
+pub async fn put<F, Fut>(destinations: Vec<Destination>, parallel: bool, put: F)
+    -> Result<Vec<ManifestEntry>, Box<dyn Error + Send + Sync>>
+where
+    F: Fn(Arc<StorageClient>, String) -> Fut,
+    Fut: Future<Output = Result<Vec<ManifestEntry>, Box<dyn Error + Send + Sync>>>,
+{
+    let results = if parallel {
+        futures::future::join_all(destinations.iter()
+            .map(|destination| put(destination.storage_client.clone(), destination.container.clone())))
+            .await
+    } else {
+        let mut results = Vec::new();
+        for destination in destinations.iter() {
+            results.push(put(destination.storage_client.clone(), destination.container.clone()).await);
+        }
+        results
+    };
+
+    let mut entries = Vec::new();
+    let mut failed = 0;
+    for (destination, result) in destinations.iter().zip(results) {
+        match result {
+            Ok(put) => {
+                println!(" OK {} ({} files)", destination.name, put.len());
+                entries.extend(put);
+            },
+            Err(e) => {
+                println!(" FAILED {}: {}", destination.name, e);
+                failed += 1;
+            }
+        }
+    }
+
+    println!("Put to {} of {} destinations", destinations.len() - failed, destinations.len());
+    if failed > 0 {
+        return Err(anyhow!("{} destinations failed", failed).into());
+    }
+    Ok(entries)
+}
@@ -0,0 +1,90 @@
+use azure_storage::core::prelude::*;
+
+use std::io::{self, Write};
+use std::error::Error;
+use anyhow::anyhow;
+use bytes::Bytes;
+use chrono::Utc;
+use http::{HeaderValue, Method, Request, Uri};
+use http::header::{AUTHORIZATION, CONTENT_LENGTH};
+use crate::audit::AuditLog;
+use crate::config::Configs;
+use crate::signer;
+use crate::transport;
+
+// Service version of requests if api_version is not configured
+const API_VERSION: &str = "2019-12-12";
+
+// Sign and send a REST request to the blob endpoint, for operations not wrapped by other modes.
+// The path is relative to the blob endpoint, with the query such as "/container/blob?comp=metadata".
+// Headers given by --header are signed with the request. The status, headers and body of the response are printed.
+pub async fn rest(storage_client: &StorageClient, cfg: &Configs, method: &str, path: &str, body: Vec<u8>,
+    audit: &AuditLog) -> Result<(), Box<dyn Error + Send + Sync>>
+{
+    let method = Method::from_bytes(method.to_ascii_uppercase().as_bytes())
+        .map_err(|_| anyhow!("Invalid method '{}'", method))?;
+    let account_client = storage_client.storage_account_client();
+
+    // The path is relative to the account also for path-style endpoints such as the emulator
+    let mut base = account_client.blob_storage_url().clone();
+    if !base.path().ends_with('/') {
+        base.set_path(&format!("{}/", base.path()));
+    }
+    let url = base.join(path.trim_start_matches('/'))
+        .map_err(|e| anyhow!("Invalid path '{}': {}", path, e))?;
+
+    // Authorized by the SAS token in the query if configured, otherwise signed by the master key or the signing command.
+    // The URL without the token is used for the audit and messages.
+    let uri: Uri = match cfg.sas_token.is_empty() {
+        true => url.as_str().parse()?,
+        false => {
+            let mut signed = url.clone();
+            let token = cfg.sas_token.expose().trim_start_matches('?');
+            let query = match url.query().filter(|query| !query.is_empty()) {
+                Some(query) => format!("{}&{}", query, token),
+                None => token.to_string()
+            };
+            signed.set_query(Some(&query));
+            signed.as_str().parse()?
+        }
+    };
+
+    let api_version = if cfg.api_version.is_empty() { API_VERSION } else { cfg.api_version.as_str() };
+    let mut request = Request::builder()
+        .method(method.clone())
+        .uri(uri.clone())
+        .header("x-ms-date", Utc::now().format("%a, %d %b %Y %H:%M:%S GMT").to_string())
+        .header("x-ms-version", api_version)
+        .header(CONTENT_LENGTH, body.len())
+        .body(Bytes::from(body))?;
+    request.headers_mut().extend(transport::parse_headers(&cfg.headers)?);
+
+    if cfg.sas_token.is_empty() {
+        let authorization = signer::shared_key_authorization(&cfg.storage_account, signer::from_config(cfg)?.as_ref(),
+            &method, &uri, request.headers())?;
+        request.headers_mut().insert(AUTHORIZATION, HeaderValue::from_str(&authorization)?);
+    }
+
+    let res = account_client.http_client().execute_request(request).await?;
+
+    // Record mutating requests, with the container and the blob in the path
+    if method != Method::GET && method != Method::HEAD && res.status().is_success() {
+        let path = url.path().strip_prefix(base.path()).unwrap_or_else(|| url.path().trim_start_matches('/'));
+        let (container, blob) = path.split_once('/').unwrap_or((path, ""));
+        let request_id = res.headers().get("x-ms-request-id").and_then(|v| v.to_str().ok()).unwrap_or_default();
+        audit.record(storage_client, &format!("rest {}", method), container, blob, &request_id).await?;
+    }
+
+    println!("{:?} {}", res.version(), res.status());
+    for (name, value) in res.headers() {
+        println!("{}: {}", name, String::from_utf8_lossy(value.as_bytes()));
+    }
+    println!();
+    io::stdout().write_all(res.body())?;
+    io::stdout().flush()?;
+
+    if !res.status().is_success() {
+        return Err(anyhow!("{} {}", res.status(), url).into());
+    }
+    Ok(())
+}
@@ -0,0 +1,60 @@
+use azure_storage::blob::prelude::*;
+use azure_storage::core::prelude::*;
+
+use std::path::Path;
+use std::error::Error;
+use anyhow::anyhow;
+use sha2::{Digest, Sha256};
+use futures::stream::{self, StreamExt};
+use azst::Pipeline;
+use crate::download;
+use crate::manifest::{Manifest, ManifestEntry};
+
+// Get exactly the blobs recorded in the manifest into the directory, and verify each by the recorded hash
+// after decoding by the transform pipeline. A blob which does not match is not written,
+// and the restore fails after trying all blobs.
+pub async fn restore(storage_client: &StorageClient, manifest: &Manifest, dir: &Path, transforms: &Pipeline,
+    concurrency: usize)
+    -> Result<(), Box<dyn Error + Send + Sync>>
+{
+    let results: Vec<_> = stream::iter(manifest.files.iter())
+        .map(|entry| async move {
+            let result = restore_entry(storage_client, entry, dir, transforms).await;
+            match &result {
+                Ok(()) => println!(" {}/{} ok", entry.container, entry.blob),
+                Err(e) => eprintln!(" {}/{} failed: {}", entry.container, entry.blob, e)
+            }
+            result
+        })
+        .buffer_unordered(concurrency.max(1))
+        .collect()
+        .await;
+
+    let failed = results.iter().filter(|result| result.is_err()).count();
+    if failed > 0 {
+        return Err(anyhow!("{} of {} files failed to restore", failed, manifest.files.len()).into());
+    }
+    println!("Restored {} files of the run {} started at {}", manifest.files.len(),
+        manifest.client_request_id, manifest.started);
+    Ok(())
+}
+
+async fn restore_entry(storage_client: &StorageClient, entry: &ManifestEntry, dir: &Path, transforms: &Pipeline)
+    -> Result<(), Box<dyn Error + Send + Sync>>
+{
+    let path = download::local_path(dir, &entry.blob)?;
+    let res = storage_client
+        .as_container_client(entry.container.as_str())
+        .as_blob_client(entry.blob.as_str())
+        .get()
+        .execute()
+        .await?;
+    let data = transforms.decode(res.data.to_vec())?;
+
+    let sha256 = format!("{:x}", Sha256::digest(&data));
+    if data.len() as u64 != entry.size || sha256 != entry.sha256 {
+        return Err(anyhow!("SHA-256 {} does not match {} in the manifest", sha256, entry.sha256).into());
+    }
+    download::write_file(&path, &data)?;
+    Ok(())
+}
@@ -0,0 +1,37 @@
+use std::str::FromStr;
+use anyhow::anyhow;
+use chrono::{DateTime, Utc};
+
+// Window to roll over append blobs by, so that each window of logs is appended to its own blob
+#[derive(Clone, Copy, Debug)]
+pub enum Rollover {
+    Hourly,
+    Daily,
+}
+
+impl FromStr for Rollover {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "hourly" => Ok(Rollover::Hourly),
+            "daily" => Ok(Rollover::Daily),
+            _ => Err(anyhow!("Invalid rollover '{}'", s))
+        }
+    }
+}
+
+impl Rollover {
+    // Blob name of the UTC window of the time, such as "logs/2024/05/17/13.log" hourly
+    // or "logs/2024/05/17.log" daily for the prefix "logs/" and the extension "log"
+    pub fn blob_name(self, prefix: &str, extension: Option<&str>, time: DateTime<Utc>) -> String {
+        let window = match self {
+            Rollover::Hourly => time.format("%Y/%m/%d/%H"),
+            Rollover::Daily => time.format("%Y/%m/%d"),
+        };
+        match extension {
+            Some(extension) => format!("{}{}.{}", prefix, window, extension),
+            None => format!("{}{}", prefix, window)
+        }
+    }
+}
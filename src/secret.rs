@@ -0,0 +1,119 @@
+use std::fmt;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+const REDACTED: &str = "********";
+
+// Query parameter of the signature of a SAS token
+const SIGNATURE: &str = "sig=";
+
+// A string which must never be printed, such as a storage master key or a SAS token.
+// Debug, Display and Serialize always redact the value. Use expose() to get the actual value.
+#[derive(Clone, Default, PartialEq)]
+pub struct Secret(String);
+
+impl Secret {
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    // Redacted representation. Empty secrets are shown as empty to tell they are not set.
+    fn redacted(&self) -> &str {
+        if self.0.is_empty() { "" } else { REDACTED }
+    }
+}
+
+impl From<String> for Secret {
+    fn from(s: String) -> Self {
+        Secret(s)
+    }
+}
+
+impl From<&str> for Secret {
+    fn from(s: &str) -> Self {
+        Secret(s.into())
+    }
+}
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self.redacted())
+    }
+}
+
+impl fmt::Display for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.redacted())
+    }
+}
+
+impl Serialize for Secret {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.redacted())
+    }
+}
+
+impl<'de> Deserialize<'de> for Secret {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer).map(Secret)
+    }
+}
+
+// Text with the signatures of SAS tokens in URLs redacted, such as errors of requests which show the URL.
+// The rest of the SAS token (such as the expiry and the permissions) is kept for troubleshooting.
+pub fn redact_signatures(text: &str) -> String {
+    let mut redacted = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(pos) = find_signature(rest) {
+        let (head, tail) = rest.split_at(pos + SIGNATURE.len());
+        redacted.push_str(head);
+        redacted.push_str(REDACTED);
+        let end = tail.find(|c: char| matches!(c, '&' | '#' | '"' | '\'' | ')' | '>') || c.is_whitespace())
+            .unwrap_or(tail.len());
+        rest = &tail[end..];
+    }
+    redacted.push_str(rest);
+    redacted
+}
+
+// Position of the first signature parameter in the text, which is at the start of a query or after another parameter
+fn find_signature(text: &str) -> Option<usize> {
+    text.match_indices(SIGNATURE)
+        .map(|(pos, _)| pos)
+        .find(|&pos| pos > 0 && matches!(text.as_bytes()[pos - 1], b'?' | b'&'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn secret_is_redacted() {
+        let secret = Secret::from("key");
+        assert_eq!(format!("{} {:?}", secret, secret), "******** \"********\"");
+        assert_eq!(Secret::default().to_string(), "");
+        assert_eq!(secret.expose(), "key");
+    }
+
+    #[test]
+    fn signatures_in_urls_are_redacted() {
+        let error = "error sending request for url (https://acct.blob.core.windows.net/logs/a.log?sv=2019-12-12&sp=r\
+            &sig=AbC%2Bd%3D&se=2021-10-01): connection reset";
+        assert_eq!(redact_signatures(error), "error sending request for url (https://acct.blob.core.windows.net/logs/\
+            a.log?sv=2019-12-12&sp=r&sig=********&se=2021-10-01): connection reset");
+        assert_eq!(redact_signatures("GET https://acct/c?sig=abc"), "GET https://acct/c?sig=********");
+        assert_eq!(redact_signatures("\"https://a/c?sig=x\" and https://b/c?sv=1&sig=y"),
+            "\"https://a/c?sig=********\" and https://b/c?sv=1&sig=********");
+    }
+
+    #[test]
+    fn other_parameters_are_kept() {
+        assert_eq!(redact_signatures("https://acct/c?restype=container&comp=list"),
+            "https://acct/c?restype=container&comp=list");
+        assert_eq!(redact_signatures("https://acct/c?xsig=abc"), "https://acct/c?xsig=abc");
+        assert_eq!(redact_signatures("sig=abc"), "sig=abc");
+    }
+}
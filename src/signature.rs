@@ -0,0 +1,61 @@
+use azure_storage::blob::prelude::*;
+use azure_storage::core::prelude::*;
+
+use std::io::Cursor;
+use std::fs;
+use std::path::Path;
+use std::error::Error;
+use anyhow::anyhow;
+use minisign_verify::{PublicKey, Signature};
+use crate::config::{self, Configs};
+
+// Create a minisign signature of the data, by sign_command if set, or by the secret key file.
+// sign_command gets the data from stdin and prints the signature, so that the key can be kept in an agent.
+pub fn sign(data: &[u8], cfg: &Configs) -> Result<String, Box<dyn Error + Send + Sync>> {
+    if !cfg.sign_command.is_empty() {
+        let signature = config::run_command(&cfg.sign_command, Some(data))
+            .map_err(|e| anyhow!("sign_command: {}", e))?;
+        return Ok(signature + "\n");
+    }
+    if cfg.sign_key.is_empty() {
+        return Err(anyhow!("sign_key or sign_command is not defined").into());
+    }
+    let secret_key = minisign::SecretKey::from_file(&cfg.sign_key, None)
+        .map_err(|e| anyhow!("Cannot read sign_key {:?}: {}", cfg.sign_key, e))?;
+    let signature = minisign::sign(None, &secret_key, Cursor::new(data), None, None)?;
+    Ok(signature.into_string())
+}
+
+// Verify the data of a blob by the minisign signature in "<blob>.sig".
+// The public key is the base64 string printed by `minisign -G`, or the path of the public key file.
+pub async fn verify_blob(storage_client: &StorageClient, container: &str, blob: &str, data: &[u8],
+    public_key: &str) -> Result<(), Box<dyn Error + Send + Sync>>
+{
+    if public_key.is_empty() {
+        return Err(anyhow!("verify_key is not defined").into());
+    }
+    let sig_blob = format!("{}.sig", blob);
+    let signature = storage_client
+        .as_container_client(container)
+        .as_blob_client(sig_blob.as_str())
+        .get()
+        .execute()
+        .await
+        .map_err(|e| anyhow!("Cannot get signature '{}': {}", sig_blob, e))?
+        .data;
+
+    verify(data, &signature, public_key)
+        .map_err(|e| anyhow!("Signature of '{}' is not valid: {}", blob, e).into())
+}
+
+// Verify the data by a minisign signature with the public key
+pub fn verify(data: &[u8], signature: &[u8], public_key: &str) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let public_key = if Path::new(public_key).is_file() {
+        PublicKey::decode(&fs::read_to_string(public_key)?)?
+    } else {
+        PublicKey::from_base64(public_key.trim())?
+    };
+    let signature = Signature::decode(std::str::from_utf8(signature)?)?;
+    public_key.verify(data, &signature, false)?;
+    Ok(())
+}
@@ -0,0 +1,191 @@
+use std::error::Error;
+use std::fmt;
+use anyhow::anyhow;
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac, NewMac};
+use http::{HeaderMap, Method, Uri};
+use sha2::Sha256;
+use url::form_urlencoded;
+use crate::config::{self, Configs};
+use crate::secret::Secret;
+
+// Service version used to sign SAS tokens
+const SAS_VERSION: &str = "2019-12-12";
+
+// Signs a SAS string-to-sign with the account key.
+// Implementations may keep the account key outside of this process, such as in a TPM or HSM.
+pub trait Signer {
+    // Base64 encoded HMAC-SHA256 of the string-to-sign
+    fn sign(&self, string_to_sign: &str) -> Result<String, Box<dyn Error + Send + Sync>>;
+}
+
+// Signer which runs an external command.
+// The string-to-sign is given to stdin of the command, and the command prints the signature to stdout.
+pub struct CommandSigner {
+    command: String,
+}
+
+impl CommandSigner {
+    pub fn new(command: &str) -> Self {
+        CommandSigner { command: command.into() }
+    }
+}
+
+impl Signer for CommandSigner {
+    fn sign(&self, string_to_sign: &str) -> Result<String, Box<dyn Error + Send + Sync>> {
+        config::run_command(&self.command, Some(string_to_sign.as_bytes()))
+    }
+}
+
+// Signer with the account key in this process
+pub struct KeySigner {
+    key: Vec<u8>,
+}
+
+impl KeySigner {
+    pub fn new(key: &Secret) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        let key = base64::decode(key.expose()).map_err(|_| anyhow!("STORAGE_MASTER_KEY is not base64"))?;
+        Ok(KeySigner { key })
+    }
+}
+
+// The key is never printed
+impl fmt::Debug for KeySigner {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("KeySigner")
+    }
+}
+
+impl Signer for KeySigner {
+    fn sign(&self, string_to_sign: &str) -> Result<String, Box<dyn Error + Send + Sync>> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(&self.key).map_err(|e| anyhow!("{}", e))?;
+        mac.update(string_to_sign.as_bytes());
+        Ok(base64::encode(mac.finalize().into_bytes()))
+    }
+}
+
+// Signer by the master key if available, or by the signing command
+pub fn from_config(cfg: &Configs) -> Result<Box<dyn Signer>, Box<dyn Error + Send + Sync>> {
+    if !cfg.storage_master_key.is_empty() {
+        return Ok(Box::new(KeySigner::new(&cfg.storage_master_key)?));
+    }
+    if !cfg.signing_command.is_empty() {
+        return Ok(Box::new(CommandSigner::new(&cfg.signing_command)));
+    }
+    Err(anyhow!("STORAGE_MASTER_KEY is not defined").into())
+}
+
+// Authorization header of a request by Shared Key.
+// Used to sign again a request whose x-ms-* headers are modified after signed by the SDK.
+pub fn shared_key_authorization(account: &str, signer: &dyn Signer, method: &Method, uri: &Uri, headers: &HeaderMap)
+    -> Result<String, Box<dyn Error + Send + Sync>>
+{
+    let header = |name: &str| headers.get(name).and_then(|v| v.to_str().ok()).unwrap_or("");
+    let content_length = match header("content-length") {
+        "0" => "",
+        v => v
+    };
+
+    // x-ms-* headers sorted by name
+    let mut ms_headers: Vec<(String, String)> = Vec::new();
+    for name in headers.keys().filter(|name| name.as_str().starts_with("x-ms-")) {
+        let values: Vec<_> = headers.get_all(name).iter().filter_map(|v| v.to_str().ok()).map(str::trim).collect();
+        ms_headers.push((name.as_str().to_string(), values.join(",")));
+    }
+    ms_headers.sort();
+
+    // Query parameters sorted by name, with values of the same name joined
+    let mut params: Vec<(String, String)> = Vec::new();
+    for (name, value) in form_urlencoded::parse(uri.query().unwrap_or("").as_bytes()) {
+        let name = name.to_lowercase();
+        match params.iter_mut().find(|(n, _)| *n == name) {
+            Some((_, values)) => *values = format!("{},{}", values, value),
+            None => params.push((name, value.into_owned()))
+        }
+    }
+    params.sort();
+
+    let mut string_to_sign = format!("{}\n{}\n{}\n{}\n{}\n{}\n{}\n{}\n{}\n{}\n{}\n{}\n",
+        method, header("content-encoding"), header("content-language"), content_length, header("content-md5"),
+        header("content-type"), header("date"), header("if-modified-since"), header("if-match"),
+        header("if-none-match"), header("if-unmodified-since"), header("range"));
+    for (name, value) in ms_headers {
+        string_to_sign += &format!("{}:{}\n", name, value);
+    }
+    string_to_sign += &format!("/{}{}", account, uri.path());
+    for (name, value) in params {
+        string_to_sign += &format!("\n{}:{}", name, value);
+    }
+
+    Ok(format!("SharedKey {}:{}", account, signer.sign(&string_to_sign)?))
+}
+
+// Create an account SAS token for blob service, valid until the expiry
+pub fn account_sas(account: &str, signer: &dyn Signer, expiry: DateTime<Utc>)
+    -> Result<Secret, Box<dyn Error + Send + Sync>>
+{
+    let permissions = "rwdlacup";
+    let services = "b";
+    let resource_types = "sco";
+    let expiry = expiry.format("%Y-%m-%dT%H:%M:%SZ").to_string();
+    let protocol = "https";
+
+    // accountname, permissions, services, resource types, start, expiry, IP, protocol, version
+    let string_to_sign = format!("{}\n{}\n{}\n{}\n\n{}\n\n{}\n{}\n",
+        account, permissions, services, resource_types, expiry, protocol, SAS_VERSION);
+    let signature = signer.sign(&string_to_sign)?;
+
+    let token = form_urlencoded::Serializer::new(String::new())
+        .append_pair("sv", SAS_VERSION)
+        .append_pair("ss", services)
+        .append_pair("srt", resource_types)
+        .append_pair("sp", permissions)
+        .append_pair("se", &expiry)
+        .append_pair("spr", protocol)
+        .append_pair("sig", &signature)
+        .finish();
+    Ok(token.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Well-known key of the storage emulator
+    const KEY: &str = "Eby8vdM02xNOcqFlqUwJPLlmEtlCDXJ1OUzFT50uSRZ6IFsuFq2UVErCz4I6tq/K1SZFPTOtr/KBHBeksoGMGw==";
+    const DATE: &str = "Fri, 01 Oct 2021 00:00:00 GMT";
+
+    fn headers(pairs: &[(&'static str, &'static str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in pairs {
+            headers.insert(*name, value.parse().unwrap());
+        }
+        headers
+    }
+
+    #[test]
+    fn shared_key_authorization_signs_headers() {
+        let signer = KeySigner::new(&KEY.into()).unwrap();
+        let uri: Uri = "https://devstoreaccount1.blob.core.windows.net/logs/a.txt".parse().unwrap();
+        let headers = headers(&[("x-ms-version", "2019-12-12"), ("content-type", "text/plain"),
+            ("x-ms-date", DATE), ("x-ms-client-request-id", "device-1"), ("content-length", "11"),
+            ("x-ms-blob-type", "BlockBlob")]);
+        assert_eq!(shared_key_authorization("devstoreaccount1", &signer, &Method::PUT, &uri, &headers).unwrap(),
+            "SharedKey devstoreaccount1:mrUygSveRNDnh/I3wNhgsC/fc24mvms9cbP1psxO4LY=");
+    }
+
+    #[test]
+    fn shared_key_authorization_signs_query() {
+        let signer = KeySigner::new(&KEY.into()).unwrap();
+        let uri: Uri = "https://devstoreaccount1.blob.core.windows.net/logs?restype=container&comp=list"
+            .parse().unwrap();
+        let headers = headers(&[("x-ms-date", DATE), ("x-ms-version", "2019-12-12"), ("content-length", "0")]);
+        assert_eq!(shared_key_authorization("devstoreaccount1", &signer, &Method::GET, &uri, &headers).unwrap(),
+            "SharedKey devstoreaccount1:m471Sggqk9NpY6IcxKSTQTl1wGiToO5RmpUY70HskIw=");
+    }
+
+    #[test]
+    fn key_signer_rejects_invalid_key() {
+        assert!(KeySigner::new(&"not base64!".into()).is_err());
+    }
+}
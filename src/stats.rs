@@ -0,0 +1,127 @@
+use azure_storage::blob::prelude::*;
+use azure_storage::core::prelude::*;
+
+use std::error::Error;
+use anyhow::anyhow;
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde::{Deserialize, Serialize};
+use crate::audit::AuditLog;
+use crate::transport;
+use crate::upload;
+
+// Width of the bars of stats-show
+const BAR_WIDTH: usize = 40;
+
+// Snapshot of the blobs with a prefix in a container, recorded as a JSON line in the stats blob
+#[derive(Serialize, Deserialize, Debug)]
+struct Snapshot {
+    time: String,
+    container: String,
+    prefix: String,
+    count: u64,
+    size: u64,
+}
+
+// Append a snapshot of the number and the total size of blobs with the prefix to the stats blob,
+// which is an append blob in the container created on the first record. The stats blob itself is not counted.
+pub async fn record(storage_client: &StorageClient, container: &str, prefix: &str, stats_blob: &str,
+    audit: &AuditLog) -> Result<(), Box<dyn Error + Send + Sync>>
+{
+    let blobs = crate::list_all_blobs(storage_client, container, Some(prefix)).await?;
+    let blobs = blobs.iter().filter(|blob| blob.name != stats_blob);
+    let (count, size) = blobs.fold((0, 0), |(count, size), blob| (count + 1, size + blob.properties.content_length));
+    let snapshot = Snapshot {
+        time: Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true),
+        container: container.into(),
+        prefix: prefix.into(),
+        count,
+        size,
+    };
+    let line = serde_json::to_string(&snapshot)? + "\n";
+
+    let blob_client = storage_client
+        .as_container_client(container)
+        .as_blob_client(stats_blob);
+    let res = match blob_client.append_block(line.clone().into_bytes()).execute().await {
+        Ok(res) => res,
+        Err(e) if transport::is_not_found(e.as_ref()) => {
+            upload::create_append_blob(storage_client, container, stats_blob, Some(audit)).await?;
+            blob_client.append_block(line.into_bytes()).execute().await?
+        },
+        Err(e) => return Err(e)
+    };
+    audit.record(storage_client, "append", container, stats_blob, &res.request_id).await?;
+    println!("{} blobs, {} in '{}/{}'", count, human_size(size), container, prefix);
+    Ok(())
+}
+
+// Print the snapshots of the prefix in the stats blob of the container, with bars of the total size
+// and the growth from the previous snapshot
+pub async fn show(storage_client: &StorageClient, container: &str, prefix: &str, stats_blob: &str)
+    -> Result<(), Box<dyn Error + Send + Sync>>
+{
+    let data = storage_client
+        .as_container_client(container)
+        .as_blob_client(stats_blob)
+        .get()
+        .execute()
+        .await
+        .map_err(|e| anyhow!("Cannot get stats '{}/{}': {}", container, stats_blob, e))?
+        .data;
+
+    let snapshots: Vec<Snapshot> = String::from_utf8_lossy(&data)
+        .lines()
+        .filter_map(|line| serde_json::from_str(line).ok())
+        .filter(|snapshot: &Snapshot| snapshot.container == container && snapshot.prefix == prefix)
+        .collect();
+    if snapshots.is_empty() {
+        println!("No stats of '{}/{}' in '{}'", container, prefix, stats_blob);
+        return Ok(());
+    }
+
+    let max = snapshots.iter().map(|snapshot| snapshot.size).max().unwrap_or(0).max(1);
+    let mut prev: Option<&Snapshot> = None;
+    for snapshot in snapshots.iter() {
+        let bar = "#".repeat((snapshot.size as u128 * BAR_WIDTH as u128 / max as u128) as usize);
+        let growth = match prev {
+            Some(prev) => signed_size(snapshot.size as f64 - prev.size as f64),
+            None => String::new()
+        };
+        println!("{:<20} {:>10} {:>10} {:<width$} {}", snapshot.time,
+            snapshot.count, human_size(snapshot.size), bar, growth, width = BAR_WIDTH);
+        prev = Some(snapshot);
+    }
+
+    // Average growth per day over the whole period, for capacity planning
+    let (first, last) = (&snapshots[0], &snapshots[snapshots.len() - 1]);
+    let days = match (DateTime::parse_from_rfc3339(&first.time), DateTime::parse_from_rfc3339(&last.time)) {
+        (Ok(first), Ok(last)) => (last - first).num_seconds() as f64 / 86400.0,
+        _ => 0.0
+    };
+    if days > 0.0 {
+        let per_day = (last.size as f64 - first.size as f64) / days;
+        println!("Growth: {}/day, {:+.0} blobs/day over {:.1} days", signed_size(per_day),
+            (last.count as f64 - first.count as f64) / days, days);
+    }
+    Ok(())
+}
+
+// Difference of sizes with the sign, such as "+1.5G"
+fn signed_size(diff: f64) -> String {
+    format!("{}{}", if diff < 0.0 { "-" } else { "+" }, human_size(diff.abs() as u64))
+}
+
+// Size in a binary unit, such as "1.5G"
+fn human_size(size: u64) -> String {
+    let units = ["", "K", "M", "G", "T", "P"];
+    let mut value = size as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < units.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    match unit {
+        0 => format!("{}", size),
+        _ => format!("{:.1}{}", value, units[unit])
+    }
+}
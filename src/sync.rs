@@ -0,0 +1,342 @@
+use azure_storage::blob::prelude::*;
+use azure_storage::core::prelude::*;
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::error::Error;
+use anyhow::anyhow;
+use serde::{Deserialize, Serialize};
+use chrono::{DateTime, Utc};
+use futures::stream::{self, StreamExt, TryStreamExt};
+use azst::{NamingPolicy, Pipeline};
+use crate::audit::AuditLog;
+use crate::download;
+use crate::filter::BlobFilter;
+use crate::hash_cache::HashCache;
+use crate::manifest::ManifestEntry;
+use crate::progress::Progress;
+use crate::upload;
+
+// How to treat blobs or local files which exist only at the destination
+#[derive(Clone, Copy, Default, Debug)]
+pub struct Mirror {
+    // Delete them, so that the destination becomes the same as the source
+    pub delete: bool,
+    // Only print what would be put, got and deleted, without changing anything
+    pub dry_run: bool,
+}
+
+// Put only new or changed files under the local directory to the container, named by the naming policy
+// from the prefix and the relative path. Returns the manifest entries of the uploaded files.
+pub async fn sync_up(storage_client: &StorageClient, dir: &Path, container: &str, prefix: &str,
+    naming: &dyn NamingPolicy, transforms: &Pipeline, mirror: Mirror, cache: &HashCache, block_size: u64,
+    concurrency: usize, progress: &Progress, audit: &AuditLog, debug: bool)
+    -> Result<Vec<ManifestEntry>, Box<dyn Error + Send + Sync>>
+{
+    let blobs: HashMap<String, Blob> = crate::list_all_blobs(storage_client, container, Some(prefix)).await?
+        .into_iter()
+        .map(|blob| (blob.name.clone(), blob))
+        .collect();
+
+    let mut files = Vec::new();
+    upload::walk(dir, &mut files)?;
+    files.sort();
+
+    let mut changed: Vec<(PathBuf, String)> = Vec::new();
+    let mut names = HashSet::new();
+    for path in files {
+        let name = upload::blob_name(naming, prefix, path.strip_prefix(dir)?)?;
+        let reason = is_changed(&path, blobs.get(&name), !transforms.is_empty(), cache)?;
+        if debug {
+            println!(" {} -> {}: {}", path.display(), name, reason.unwrap_or("unchanged"));
+        }
+        if reason.is_some() {
+            changed.push((path, name.clone()));
+        }
+        names.insert(name);
+    }
+
+    // Blobs without local files, except directory markers
+    let mut extras: Vec<&String> = blobs.keys().filter(|name| !name.ends_with('/') && !names.contains(*name)).collect();
+    extras.sort();
+
+    if mirror.dry_run {
+        for (path, name) in changed.iter() {
+            println!(" {} -> {} (dry run)", path.display(), name);
+        }
+        if mirror.delete {
+            for name in extras.iter() {
+                println!(" delete {} (dry run)", name);
+            }
+        }
+        println!("Would put {} new or changed files to '{}/{}'{}", changed.len(), container, prefix,
+            deleted_summary(mirror, extras.len(), "blobs"));
+        return Ok(Vec::new());
+    }
+
+    let paths: Vec<PathBuf> = changed.iter().map(|(path, _)| path.clone()).collect();
+    progress.start_total(paths.len(), upload::total_size(&paths));
+    let entries: Vec<ManifestEntry> = stream::iter(changed.iter())
+        .map(|(path, name)| async move {
+            // Files are put concurrently, so blocks of each file are staged one by one
+            let blocks = upload::Blocks { size: block_size, concurrency: 1, resume: false, hashes: false };
+            let entry = upload::put_file(storage_client, path, container, name, transforms, None, blocks,
+                cache.algorithm(), None, progress, audit, debug).await?;
+            println!(" {} -> {}", path.display(), name);
+            Ok::<_, Box<dyn Error + Send + Sync>>(entry)
+        })
+        .buffer_unordered(concurrency.max(1))
+        .try_collect()
+        .await?;
+    progress.finish();
+
+    // Delete extra blobs only after all files are put, so that a failed run does not leave the prefix half deleted
+    if mirror.delete {
+        let container_client = storage_client.as_container_client(container);
+        for name in extras.iter() {
+            let res = container_client.as_blob_client(name.as_str()).delete().execute().await?;
+            audit.record(storage_client, "delete", container, name, &res.request_id).await?;
+            println!(" delete {}", name);
+        }
+    }
+
+    println!("Put {} new or changed files to '{}/{}'{}", entries.len(), container, prefix,
+        deleted_summary(mirror, extras.len(), "blobs"));
+    Ok(entries)
+}
+
+// Number of deleted blobs or files appended to the summary, if deleting
+fn deleted_summary(mirror: Mirror, count: usize, kind: &str) -> String {
+    match mirror.delete {
+        true => format!(", {} extra {} deleted", count, kind),
+        false => String::new()
+    }
+}
+
+// Why the local file needs to be uploaded, or None if the blob is the same.
+// A blob is the same if it is modified after the local file with the same size, or has the same hash.
+// With transforms, the size and the Content-MD5 of the blob differ from the local file, so only the time
+// and the hash in the metadata, which is recorded for MD5 too with transforms, are compared.
+fn is_changed(path: &Path, blob: Option<&Blob>, transformed: bool, cache: &HashCache)
+    -> Result<Option<&'static str>, Box<dyn Error + Send + Sync>>
+{
+    match blob {
+        Some(blob) => {
+            let digest = cache.algorithm().blob_digest(blob, transformed);
+            compare(path, blob.properties.content_length, blob.properties.last_modified, digest, transformed, cache)
+        },
+        None => Ok(Some("new"))
+    }
+}
+
+// Compare the local file with the size, the last modified time and the hash of the content recorded of the blob
+fn compare(path: &Path, size: u64, last_modified: DateTime<Utc>, digest: Option<String>, transformed: bool,
+    cache: &HashCache) -> Result<Option<&'static str>, Box<dyn Error + Send + Sync>>
+{
+    let metadata = fs::metadata(path)?;
+    let modified = DateTime::<Utc>::from(metadata.modified()?);
+
+    if !transformed && metadata.len() != size {
+        return Ok(Some("size"));
+    }
+    if modified <= last_modified {
+        return Ok(None);
+    }
+    match digest {
+        Some(digest) if cache.digest(path)? == digest => Ok(None),
+        Some(_) => Ok(Some("hash")),
+        None if transformed => Ok(Some("modified")),
+        None => Ok(Some("hash"))
+    }
+}
+
+// State of a blob got by sync_down, to find blobs changed since the last run
+#[derive(Serialize, Deserialize, Default, Debug, Clone, PartialEq)]
+#[serde(default)]
+struct BlobState {
+    etag: String,
+    last_modified: String,
+    content_md5: String,
+    // Size and modification time of the local file written from the blob
+    local_size: u64,
+    local_modified: String,
+}
+
+impl BlobState {
+    fn of_blob(blob: &Blob) -> BlobState {
+        BlobState {
+            etag: blob.properties.etag.to_string(),
+            last_modified: blob.properties.last_modified.to_rfc3339(),
+            content_md5: blob.properties.content_md5.as_ref().map(|md5| base64::encode(md5.bytes())).unwrap_or_default(),
+            ..Default::default()
+        }
+    }
+
+    // Record the local file written from the blob
+    fn with_local(mut self, path: &Path) -> Result<BlobState, Box<dyn Error + Send + Sync>> {
+        let metadata = fs::metadata(path)?;
+        self.local_size = metadata.len();
+        self.local_modified = DateTime::<Utc>::from(metadata.modified()?).to_rfc3339();
+        Ok(self)
+    }
+}
+
+// Get only blobs with the prefix changed since the last run into the local directory.
+// The ETag, last modified time and MD5 hash of each blob got are saved in the state file, and a blob is got again
+// if any of them changes, or the local file is modified or removed. Blobs which do not match the filter are not got,
+// but their local files are not extras to delete either.
+pub async fn sync_down(storage_client: &StorageClient, container: &str, prefix: &str, filter: &BlobFilter, dir: &Path,
+    state_file: &Path, transforms: &Pipeline, mirror: Mirror, chunk_size: u64, verify: bool, concurrency: usize,
+    progress: &Progress, debug: bool)
+    -> Result<(), Box<dyn Error + Send + Sync>>
+{
+    let mut state: BTreeMap<String, BlobState> = match fs::read(state_file) {
+        Ok(data) => serde_json::from_slice(&data).map_err(|e| anyhow!("Cannot parse {:?}: {}", state_file, e))?,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => BTreeMap::new(),
+        Err(e) => return Err(e.into())
+    };
+
+    let container_client = storage_client.as_container_client(container);
+    let blobs = crate::list_all_blobs(storage_client, container, Some(prefix)).await?;
+
+    let mut changed = Vec::new();
+    let mut paths = HashSet::new();
+    for blob in blobs.iter().filter(|blob| !blob.name.ends_with('/')) {
+        let path = match download::local_path(dir, &blob.name) {
+            Ok(path) => path,
+            Err(e) => {
+                eprintln!("Skipped: {}", e);
+                continue;
+            }
+        };
+        paths.insert(path.clone());
+        if !filter.matches(blob) {
+            continue;
+        }
+        let current = BlobState::of_blob(blob);
+        let unchanged = match (state.get(&blob.name), path.exists()) {
+            (Some(last), true) => current.clone().with_local(&path)? == *last,
+            _ => false
+        };
+        if debug {
+            println!(" {} -> {}: {}", blob.name, path.display(), if unchanged { "unchanged" } else { "changed" });
+        }
+        if !unchanged {
+            changed.push((blob, path, current));
+        }
+    }
+
+    // Local files with the prefix without blobs. Blobs got from the container are under the prefix,
+    // so other files in the directory are not touched.
+    let mut extras = Vec::new();
+    if mirror.delete && dir.is_dir() {
+        let mut files = Vec::new();
+        upload::walk(dir, &mut files)?;
+        files.sort();
+        let state_file = fs::canonicalize(state_file).ok();
+        for path in files {
+            let relative: Vec<_> = path.strip_prefix(dir)?.components()
+                .map(|component| component.as_os_str().to_string_lossy().into_owned())
+                .collect();
+            if relative.join("/").starts_with(prefix) && !paths.contains(&path)
+                && fs::canonicalize(&path).ok() != state_file
+            {
+                extras.push((relative.join("/"), path));
+            }
+        }
+    }
+
+    if mirror.dry_run {
+        for (blob, path, _) in changed.iter() {
+            println!(" {} -> {} (dry run)", blob.name, path.display());
+        }
+        for (_, path) in extras.iter() {
+            println!(" delete {} (dry run)", path.display());
+        }
+        println!("Would get {} changed blobs from '{}/{}'{}", changed.len(), container, prefix,
+            deleted_summary(mirror, extras.len(), "files"));
+        return Ok(());
+    }
+
+    // Get changed blobs, and save the state of blobs got even if some fail.
+    // Page blobs are written as sparse files, so their sizes are not counted in the space needed.
+    let blocks = changed.iter().filter(|(blob, _, _)| !matches!(blob.properties.blob_type, BlobType::PageBlob));
+    let (size, replaced) = blocks.fold((0, 0), |(size, replaced), (blob, path, _)|
+        (size + blob.properties.content_length, replaced + download::file_size(path)));
+    download::check_space(dir, size, replaced)?;
+    progress.start_total(changed.len(), changed.iter().map(|(blob, _, _)| blob.properties.content_length).sum());
+    let container_client = &container_client;
+    let results: Vec<_> = stream::iter(changed.into_iter())
+        .map(|(blob, path, current)| async move {
+            download::get_blob(container_client, blob, &path, transforms, chunk_size, verify, progress, debug).await?;
+            println!(" {} -> {}", blob.name, path.display());
+            Ok::<_, Box<dyn Error + Send + Sync>>((blob.name.clone(), current.with_local(&path)?))
+        })
+        .buffer_unordered(concurrency.max(1))
+        .collect()
+        .await;
+    progress.finish();
+
+    let mut got = 0;
+    let mut failed = 0;
+    for result in results {
+        match result {
+            Ok((name, blob_state)) => {
+                state.insert(name, blob_state);
+                got += 1;
+            },
+            Err(e) => {
+                eprintln!("Failed: {}", e);
+                failed += 1;
+            }
+        }
+    }
+
+    // Delete extra files only if all blobs are got
+    if failed == 0 {
+        for (name, path) in extras.iter() {
+            fs::remove_file(path)?;
+            state.remove(name);
+            println!(" delete {}", path.display());
+        }
+    }
+    fs::write(state_file, serde_json::to_vec_pretty(&state)?)?;
+
+    println!("Got {} changed blobs from '{}/{}'{}", got, container, prefix,
+        deleted_summary(mirror, if failed == 0 { extras.len() } else { 0 }, "files"));
+    if failed > 0 {
+        return Err(anyhow!("{} blobs failed to get", failed).into());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use chrono::Duration;
+    use crate::hash::HashAlgorithm;
+
+    #[test]
+    fn compare_by_size_time_and_hash() {
+        let path = env::temp_dir().join(format!("azst-sync-{}", uuid::Uuid::new_v4()));
+        fs::write(&path, b"hello").unwrap();
+        let cache = HashCache::load("", HashAlgorithm::Sha256).unwrap();
+        let digest = HashAlgorithm::Sha256.digest(b"hello");
+        let (before, after) = (Utc::now() - Duration::hours(1), Utc::now() + Duration::hours(1));
+
+        assert_eq!(compare(&path, 4, after, None, false, &cache).unwrap(), Some("size"));
+        assert_eq!(compare(&path, 5, after, None, false, &cache).unwrap(), None);
+        assert_eq!(compare(&path, 5, before, Some(digest.clone()), false, &cache).unwrap(), None);
+        assert_eq!(compare(&path, 5, before, Some("0f34".into()), false, &cache).unwrap(), Some("hash"));
+        assert_eq!(compare(&path, 5, before, None, false, &cache).unwrap(), Some("hash"));
+
+        // With transforms, the size of the blob is not of the local file
+        assert_eq!(compare(&path, 64, after, None, true, &cache).unwrap(), None);
+        assert_eq!(compare(&path, 64, before, Some(digest), true, &cache).unwrap(), None);
+        assert_eq!(compare(&path, 64, before, None, true, &cache).unwrap(), Some("modified"));
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,47 @@
+use std::env;
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use tokio::time::{self, Duration};
+
+static READY: AtomicBool = AtomicBool::new(false);
+
+// Send a state to the service manager, if running under systemd with NOTIFY_SOCKET
+#[cfg(unix)]
+pub fn notify(state: &str) -> io::Result<()> {
+    use std::os::unix::net::UnixDatagram;
+
+    let path = match env::var_os("NOTIFY_SOCKET") {
+        Some(path) => path,
+        None => return Ok(())
+    };
+    let socket = UnixDatagram::unbound()?;
+    socket.send_to(state.as_bytes(), path)?;
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn notify(_state: &str) -> io::Result<()> {
+    Ok(())
+}
+
+// Notify the service is ready. Only the first call sends the notification.
+pub fn notify_ready() {
+    if !READY.swap(true, Ordering::SeqCst) {
+        let _ = notify("READY=1");
+    }
+}
+
+// Send keep-alive pings at the half of the watchdog interval, if the watchdog is enabled
+pub fn spawn_watchdog() {
+    let usec = match env::var("WATCHDOG_USEC").ok().and_then(|v| v.parse::<u64>().ok()) {
+        Some(usec) if usec > 0 => usec,
+        _ => return
+    };
+    tokio::spawn(async move {
+        let mut interval = time::interval(Duration::from_micros(usec / 2));
+        loop {
+            interval.tick().await;
+            let _ = notify("WATCHDOG=1");
+        }
+    });
+}
@@ -0,0 +1,107 @@
+use azure_core::prelude::*;
+use azure_storage::blob::prelude::*;
+
+use std::io::{self, Write};
+use std::time::Duration;
+use tokio::time;
+use std::error::Error;
+
+// Size of the beginning printed by head mode, unless --bytes or --range is specified
+pub const HEAD_BYTES: u64 = 1024;
+
+// Polling interval of --follow, unless --interval is specified
+pub const FOLLOW_INTERVAL: u64 = 2;
+
+// Size of ranges got backward from the end to find the last lines
+const CHUNK_SIZE: u64 = 64 * 1024;
+
+// Part of the end of a blob to print
+#[derive(Clone, Copy, Debug)]
+pub enum Last {
+    Bytes(u64),
+    Lines(usize),
+}
+
+// Print the last bytes or lines of the blob to stdout. If following, poll the length of the blob
+// by the interval in seconds and print content appended since, like tail -f. A blob which gets shorter,
+// such as deleted and created again, is printed from the beginning.
+pub async fn tail(blob_client: &BlobClient, last: Last, follow: Option<u64>)
+    -> Result<(), Box<dyn Error + Send + Sync>>
+{
+    let size = blob_client.get_properties().execute().await?.blob.properties.content_length;
+    match last {
+        Last::Bytes(bytes) => write_range(blob_client, size.saturating_sub(bytes), size).await?,
+        Last::Lines(lines) => write(last_lines(&tail_data(blob_client, size, lines).await?, lines))?
+    }
+
+    let interval = match follow {
+        Some(interval) => interval,
+        None => return Ok(())
+    };
+    let mut offset = size;
+    loop {
+        time::sleep(Duration::from_secs(interval.max(1))).await;
+        let size = blob_client.get_properties().execute().await?.blob.properties.content_length;
+        if size < offset {
+            eprintln!("tail: blob is truncated, printing from the beginning");
+            offset = 0;
+        }
+        if size > offset {
+            write_range(blob_client, offset, size).await?;
+            offset = size;
+        }
+    }
+}
+
+// Content from the end of the blob back to the last lines, got by chunks from the end
+async fn tail_data(blob_client: &BlobClient, size: u64, lines: usize) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+    let mut data = Vec::new();
+    let mut start = size;
+    while start > 0 && lines > 0 && count_lines(&data) <= lines {
+        let from = start.saturating_sub(CHUNK_SIZE);
+        let mut chunk = blob_client.get().range(Range::new(from, start)).execute().await?.data.to_vec();
+        chunk.extend_from_slice(&data);
+        data = chunk;
+        start = from;
+    }
+    Ok(data)
+}
+
+// Last line of the blob of the size without the line break, got by chunks from the end
+pub async fn last_line(blob_client: &BlobClient, size: u64) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+    let data = tail_data(blob_client, size, 1).await?;
+    let line = last_lines(&data, 1);
+    Ok(line.strip_suffix(b"\n").unwrap_or(line).to_vec())
+}
+
+// Number of line breaks, not counting the one at the end
+fn count_lines(data: &[u8]) -> usize {
+    let data = data.strip_suffix(b"\n").unwrap_or(data);
+    data.iter().filter(|&&b| b == b'\n').count()
+}
+
+// Last lines of the data. A line break at the end does not start another line.
+fn last_lines(data: &[u8], lines: usize) -> &[u8] {
+    if lines == 0 {
+        return &[];
+    }
+    let body = data.strip_suffix(b"\n").unwrap_or(data);
+    match body.iter().enumerate().rev().filter(|(_, &b)| b == b'\n').nth(lines - 1) {
+        Some((pos, _)) => &data[pos + 1..],
+        None => data
+    }
+}
+
+// Print the range of the blob by chunks
+pub async fn write_range(blob_client: &BlobClient, start: u64, end: u64) -> Result<(), Box<dyn Error + Send + Sync>> {
+    for from in (start..end).step_by(CHUNK_SIZE as usize) {
+        let to = (from + CHUNK_SIZE).min(end);
+        write(&blob_client.get().range(Range::new(from, to)).execute().await?.data)?;
+    }
+    Ok(())
+}
+
+fn write(data: &[u8]) -> io::Result<()> {
+    let mut stdout = io::stdout();
+    stdout.write_all(data).and_then(|_| stdout.flush())
+}
@@ -0,0 +1,149 @@
+use std::io::prelude::*;
+use std::error::Error;
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use aes_gcm::aead::{Aead, NewAead};
+use flate2::Compression;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use rand::RngCore;
+use regex::bytes::Regex;
+
+/// Transform of blob content, applied on upload and reversed on download.
+///
+/// Implement this to add customer specific processing (such as compression, encryption or redaction)
+/// to transfers, and add it to a `Pipeline`.
+pub trait Transform: Send + Sync {
+    /// Name to show in messages
+    fn name(&self) -> &str;
+
+    /// Transform the content before uploading
+    fn encode(&self, data: Vec<u8>) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>>;
+
+    /// Reverse the transform after downloading
+    fn decode(&self, data: Vec<u8>) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>>;
+}
+
+/// Ordered list of transforms. Encoded in order on upload, and decoded in reverse order on download.
+#[derive(Default)]
+pub struct Pipeline {
+    transforms: Vec<Box<dyn Transform>>,
+}
+
+impl Pipeline {
+    pub fn new() -> Pipeline {
+        Pipeline::default()
+    }
+
+    /// Add a transform to the end of the pipeline
+    pub fn push(&mut self, transform: Box<dyn Transform>) {
+        self.transforms.push(transform);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.transforms.is_empty()
+    }
+
+    pub fn encode(&self, mut data: Vec<u8>) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+        for transform in self.transforms.iter() {
+            data = transform.encode(data).map_err(|e| format!("{}: {}", transform.name(), e))?;
+        }
+        Ok(data)
+    }
+
+    pub fn decode(&self, mut data: Vec<u8>) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+        for transform in self.transforms.iter().rev() {
+            data = transform.decode(data).map_err(|e| format!("{}: {}", transform.name(), e))?;
+        }
+        Ok(data)
+    }
+}
+
+/// Gzip compression
+pub struct Gzip;
+
+impl Transform for Gzip {
+    fn name(&self) -> &str {
+        "gzip"
+    }
+
+    fn encode(&self, data: Vec<u8>) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&data)?;
+        Ok(encoder.finish()?)
+    }
+
+    fn decode(&self, data: Vec<u8>) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+        let mut decoded = Vec::new();
+        GzDecoder::new(&data[..]).read_to_end(&mut decoded)?;
+        Ok(decoded)
+    }
+}
+
+/// AES-256-GCM encryption. The encoded content is a random 12 bytes nonce followed by the ciphertext.
+pub struct Encrypt {
+    cipher: Aes256Gcm,
+}
+
+const NONCE_SIZE: usize = 12;
+
+impl Encrypt {
+    /// Create with a 32 bytes key
+    pub fn new(key: &[u8]) -> Result<Encrypt, Box<dyn Error + Send + Sync>> {
+        if key.len() != 32 {
+            return Err("Encryption key must be 32 bytes".into());
+        }
+        Ok(Encrypt { cipher: Aes256Gcm::new(Key::from_slice(key)) })
+    }
+}
+
+impl Transform for Encrypt {
+    fn name(&self) -> &str {
+        "encrypt"
+    }
+
+    fn encode(&self, data: Vec<u8>) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+        let mut nonce = [0u8; NONCE_SIZE];
+        rand::thread_rng().fill_bytes(&mut nonce);
+        let ciphertext = self.cipher.encrypt(Nonce::from_slice(&nonce), data.as_ref())
+            .map_err(|_| "Cannot encrypt")?;
+        Ok([&nonce[..], &ciphertext].concat())
+    }
+
+    fn decode(&self, data: Vec<u8>) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+        if data.len() < NONCE_SIZE {
+            return Err("Encrypted content is too short".into());
+        }
+        let (nonce, ciphertext) = data.split_at(NONCE_SIZE);
+        Ok(self.cipher.decrypt(Nonce::from_slice(nonce), ciphertext)
+            .map_err(|_| "Cannot decrypt. The key is wrong or the content is modified")?)
+    }
+}
+
+/// Replace matches of the patterns by "[REDACTED]" on upload. It cannot be reversed, so downloads are as is.
+pub struct Redact {
+    patterns: Vec<Regex>,
+}
+
+impl Redact {
+    pub fn new(patterns: &[String]) -> Result<Redact, Box<dyn Error + Send + Sync>> {
+        let patterns = patterns.iter().map(|pattern| Regex::new(pattern)).collect::<Result<_, _>>()?;
+        Ok(Redact { patterns })
+    }
+}
+
+impl Transform for Redact {
+    fn name(&self) -> &str {
+        "redact"
+    }
+
+    fn encode(&self, mut data: Vec<u8>) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+        for pattern in self.patterns.iter() {
+            data = pattern.replace_all(&data, &b"[REDACTED]"[..]).into_owned();
+        }
+        Ok(data)
+    }
+
+    fn decode(&self, data: Vec<u8>) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+        Ok(data)
+    }
+}
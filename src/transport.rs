@@ -0,0 +1,830 @@
+use azure_core::{HttpClient, HttpError};
+use azure_storage::core::prelude::*;
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, ToSocketAddrs};
+use std::io;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::error::Error;
+use anyhow::anyhow;
+use async_trait::async_trait;
+use bytes::Bytes;
+use chrono::NaiveDate;
+use http::{HeaderMap, HeaderName, HeaderValue, Method, Request, Response, StatusCode, Uri};
+use http::header::{AUTHORIZATION, IF_NONE_MATCH};
+use reqwest::tls;
+use tokio::sync::Mutex;
+use tokio::time::{self, Duration, Instant};
+use url::{form_urlencoded, Url};
+use crate::config::Configs;
+use crate::secret::{self, Secret};
+use crate::signer::{self, KeySigner};
+use crate::systemd;
+
+// Header to correlate requests with an invocation in server side logs
+const CLIENT_REQUEST_ID: &str = "x-ms-client-request-id";
+
+// Header of the service version of a request
+const VERSION: &str = "x-ms-version";
+
+// Header of the type of a blob put
+const BLOB_TYPE: &str = "x-ms-blob-type";
+
+// Header of the source of a copy into a blob
+const COPY_SOURCE: &str = "x-ms-copy-source";
+
+// Header of the new size of a page blob to resize
+const BLOB_CONTENT_LENGTH: &str = "x-ms-blob-content-length";
+
+// Header of the condition on the length of an append blob, which makes an append block idempotent
+const APPEND_POSITION: &str = "x-ms-blob-condition-appendpos";
+
+// Boundaries of a certificate in a PEM file
+const PEM_BEGIN: &str = "-----BEGIN CERTIFICATE-----";
+const PEM_END: &str = "-----END CERTIFICATE-----";
+
+// Create an HTTP client which applies the request policies in the config, for a storage client of the account.
+// Requests signed by the master key of the account are signed again after the headers are added. The account may
+// be other than the one in the config, such as the target of compare and replicas.
+pub fn new_http_client(cfg: &Configs, account: &str, master_key: &Secret)
+    -> Result<Arc<dyn HttpClient>, Box<dyn Error + Send + Sync>>
+{
+    let mut builder = reqwest::Client::builder();
+
+    // Force IPv4 or IPv6 by binding to the unspecified address of the family.
+    // Otherwise IPv6 and IPv4 addresses are raced (happy eyeballs).
+    let family: fn(&SocketAddr) -> bool = match cfg.ip_version.as_str() {
+        "" | "auto" => |_| true,
+        "4" => {
+            builder = builder.local_address(IpAddr::from(Ipv4Addr::UNSPECIFIED));
+            SocketAddr::is_ipv4
+        },
+        "6" => {
+            builder = builder.local_address(IpAddr::from(Ipv6Addr::UNSPECIFIED));
+            SocketAddr::is_ipv6
+        },
+        v => return Err(anyhow!("Invalid IP version '{}'", v).into())
+    };
+
+    // TLS below 1.2 is not supported by rustls at all, and the minimum is set explicitly for security baselines
+    builder = builder.min_tls_version(match cfg.tls_min_version.as_str() {
+        "" | "1.2" => tls::Version::TLS_1_2,
+        "1.3" => tls::Version::TLS_1_3,
+        v => return Err(anyhow!("Invalid TLS minimum version '{}'. Specify 1.2 or 1.3", v).into())
+    });
+
+    for cert in root_certificates(cfg)? {
+        builder = builder.add_root_certificate(cert);
+    }
+    if cfg.insecure.unwrap_or(false) {
+        eprintln!("Warning: Server certificates are not verified (insecure)");
+        builder = builder.danger_accept_invalid_certs(true);
+    }
+
+    // Client certificate for mutual TLS, required by some gateways in front of private endpoints
+    if !cfg.tls_client_cert.is_empty() {
+        builder = builder.identity(client_identity(&cfg.tls_client_cert, &cfg.tls_client_key)?);
+    }
+
+    // URL requests are sent to: the endpoint of the emulator or the path style, or the blob endpoint
+    let endpoint = match cfg.endpoint.as_str() {
+        "" => cfg.blob_endpoint_url()?,
+        endpoint => Some(endpoint.to_string())
+    };
+    let endpoint = endpoint.map(|v| Url::parse(&v)).transpose()?;
+    let port = endpoint.as_ref().and_then(Url::port_or_known_default).unwrap_or(443);
+
+    // Pin hosts to explicit addresses, for private endpoints where DNS is unreliable.
+    // Connections use the port of the endpoint, as DNS has no notion of ports.
+    let mut pinned = Vec::new();
+    for entry in cfg.resolve.iter() {
+        let (host, ip) = parse_resolve(entry)?;
+        builder = builder.resolve(host, SocketAddr::new(ip, port));
+        pinned.push(host);
+    }
+
+    // Resolve the blob endpoint only once, and use the address for the process lifetime
+    let host = endpoint.as_ref()
+        .and_then(|url| url.host_str())
+        .map(String::from)
+        .unwrap_or_else(|| format!("{}.blob.core.windows.net", cfg.storage_account));
+    if cfg.warmup.unwrap_or(false) && !cfg.storage_account.is_empty() && cfg.endpoint.is_empty()
+        && !pinned.contains(&host.as_str())
+    {
+        if let Some(addr) = (host.as_str(), port).to_socket_addrs()?.find(family) {
+            builder = builder.resolve(&host, addr);
+        }
+    }
+
+    // Headers added to all requests, for gateway appliances and service features the SDK does not expose
+    let mut headers = parse_headers(&cfg.headers)?;
+    if !cfg.client_request_id.is_empty() {
+        let value = HeaderValue::from_str(&cfg.client_request_id)
+            .map_err(|_| anyhow!("Invalid client request ID '{}'", cfg.client_request_id))?;
+        headers.insert(CLIENT_REQUEST_ID, value);
+    }
+
+    // Pin or bump the service version, for newer service features or regressions of the version of the SDK
+    if !cfg.api_version.is_empty() {
+        NaiveDate::parse_from_str(&cfg.api_version, "%Y-%m-%d")
+            .map_err(|_| anyhow!("Invalid API version '{}'. Specify as YYYY-MM-DD", cfg.api_version))?;
+        headers.insert(VERSION, HeaderValue::from_str(&cfg.api_version)?);
+    }
+
+    Ok(Arc::new(PolicyHttpClient {
+        inner: Arc::new(builder.build()?),
+        headers,
+        key_signer: match master_key.is_empty() {
+            true => None,
+            false => Some((account.to_string(), KeySigner::new(master_key)?))
+        },
+        rate_limiter: cfg.max_requests_per_second
+            .filter(|&rate| rate != 0.0)
+            .map(RateLimiter::new)
+            .transpose()?,
+        bandwidth_limiter: cfg.limit_rate
+            .filter(|&rate| rate > 0)
+            .map(BandwidthLimiter::new)
+            .transpose()?,
+        faults: match cfg.inject_faults.is_empty() {
+            true => None,
+            false => Some(FaultInjector::parse(&cfg.inject_faults)?)
+        },
+        retry: RetryPolicy::from_config(cfg)?,
+        append_only: cfg.append_only_containers.clone(),
+        path_style: !cfg.endpoint.is_empty(),
+        sd_notify: cfg.sd_notify.unwrap_or(false),
+    }))
+}
+
+// Parse <host>:<ip> of a DNS override. IPv6 addresses may be in brackets, such as host:[::1].
+pub fn parse_resolve(entry: &str) -> Result<(&str, IpAddr), Box<dyn Error + Send + Sync>> {
+    let (host, ip) = entry.split_once(':')
+        .filter(|(host, _)| !host.is_empty())
+        .ok_or(anyhow!("Invalid resolve '{}'. Specify as <host>:<ip>", entry))?;
+    let ip = ip.strip_prefix('[').and_then(|v| v.strip_suffix(']')).unwrap_or(ip);
+    let ip = ip.parse().map_err(|_| anyhow!("Invalid IP address '{}' in resolve '{}'", ip, entry))?;
+    Ok((host, ip))
+}
+
+// Root CA certificates in the PEM file of ca_cert, trusted in addition to the built-in ones,
+// such as of TLS-intercepting corporate proxies. The file can have multiple certificates.
+pub fn root_certificates(cfg: &Configs) -> Result<Vec<tls::Certificate>, Box<dyn Error + Send + Sync>> {
+    if cfg.ca_cert.is_empty() {
+        return Ok(Vec::new());
+    }
+    let pem = std::fs::read_to_string(&cfg.ca_cert)
+        .map_err(|e| anyhow!("Cannot read CA certificates {:?}: {}", cfg.ca_cert, e))?;
+    let certs = pem.split_inclusive(PEM_END)
+        .filter(|cert| cert.contains(PEM_BEGIN))
+        .map(|cert| tls::Certificate::from_pem(cert.as_bytes())
+            .map_err(|e| anyhow!("Invalid CA certificate in {:?}: {}", cfg.ca_cert, e).into()))
+        .collect::<Result<Vec<_>, Box<dyn Error + Send + Sync>>>()?;
+    if certs.is_empty() {
+        return Err(anyhow!("No CA certificate in {:?}", cfg.ca_cert).into());
+    }
+    Ok(certs)
+}
+
+// Identity of the client certificate in the PEM file, with the private key in the same file
+// or in the key file if given
+fn client_identity(cert_file: &str, key_file: &str) -> Result<tls::Identity, Box<dyn Error + Send + Sync>> {
+    let mut pem = std::fs::read(cert_file)
+        .map_err(|e| anyhow!("Cannot read client certificate {:?}: {}", cert_file, e))?;
+    if !key_file.is_empty() {
+        pem.push(b'\n');
+        pem.extend(std::fs::read(key_file).map_err(|e| anyhow!("Cannot read client key {:?}: {}", key_file, e))?);
+    }
+    tls::Identity::from_pem(&pem)
+        .map_err(|e| anyhow!("Invalid client certificate or key in {:?}: {}", cert_file, e).into())
+}
+
+// Parse headers given as "<name>: <value>"
+pub fn parse_headers(headers: &[String]) -> Result<HeaderMap, Box<dyn Error + Send + Sync>> {
+    let mut map = HeaderMap::new();
+    for header in headers.iter() {
+        let (name, value) = header.split_once(':')
+            .ok_or(anyhow!("Invalid header '{}'. Specify as <name>: <value>", header))?;
+        let name = HeaderName::from_bytes(name.trim().as_bytes())
+            .map_err(|_| anyhow!("Invalid header name in '{}'", header))?;
+        let value = HeaderValue::from_str(value.trim())
+            .map_err(|_| anyhow!("Invalid header value in '{}'", header))?;
+        map.append(name, value);
+    }
+    Ok(map)
+}
+
+// Open a connection to the blob endpoint in advance, so that DNS resolution and TLS handshake
+// are not included in the time of the following transfers. Any response means connected.
+pub async fn warmup(storage_client: &StorageClient) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let account_client = storage_client.storage_account_client();
+    let request = Request::head(account_client.blob_storage_url().as_str()).body(Bytes::new())?;
+    account_client.http_client().execute_request(request).await?;
+    Ok(())
+}
+
+// URL of a blob, without query
+pub fn blob_url(storage_client: &StorageClient, container: &str, blob: &str) -> Result<Url, Box<dyn Error + Send + Sync>> {
+    let mut url = storage_client.storage_account_client().blob_storage_url().clone();
+    url.path_segments_mut()
+        .map_err(|_| anyhow!("Invalid blob storage URL"))?
+        .pop_if_empty()
+        .push(container)
+        .extend(blob.split('/'));
+    Ok(url)
+}
+
+// HTTP status code of an error response, if the error is caused by it
+pub fn error_status(e: &(dyn Error + Send + Sync + 'static)) -> Option<StatusCode> {
+    match e.downcast_ref::<HttpError>() {
+        Some(HttpError::StatusCode { status, .. }) => Some(*status),
+        _ => None
+    }
+}
+
+// Check if an error is caused by 404 Not Found, such as a blob which does not exist
+pub fn is_not_found(e: &(dyn Error + Send + Sync + 'static)) -> bool {
+    error_status(e) == Some(StatusCode::NOT_FOUND)
+}
+
+// HTTP client which applies the request policies before passing requests to the inner client
+#[derive(Debug)]
+struct PolicyHttpClient {
+    inner: Arc<dyn HttpClient>,
+    headers: HeaderMap,
+    key_signer: Option<(String, KeySigner)>,
+    rate_limiter: Option<RateLimiter>,
+    bandwidth_limiter: Option<BandwidthLimiter>,
+    faults: Option<FaultInjector>,
+    retry: RetryPolicy,
+    append_only: Vec<String>,
+    path_style: bool,
+    sd_notify: bool,
+}
+
+impl PolicyHttpClient {
+    // Add the configured headers, replacing the same headers set by the SDK.
+    // The Shared Key signature covers x-ms-* headers, so the request is signed again.
+    fn add_headers(&self, method: &Method, uri: &Uri, headers: &mut HeaderMap)
+        -> Result<(), Box<dyn Error + Send + Sync>>
+    {
+        if self.headers.is_empty() {
+            return Ok(());
+        }
+        for name in self.headers.keys() {
+            headers.remove(name);
+        }
+        for (name, value) in self.headers.iter() {
+            headers.append(name, value.clone());
+        }
+
+        let shared_key = headers.get(AUTHORIZATION)
+            .map_or(false, |v| v.as_bytes().starts_with(b"SharedKey "));
+        if let (true, Some((account, key_signer))) = (shared_key, &self.key_signer) {
+            let authorization = signer::shared_key_authorization(account, key_signer, method, uri, headers)?;
+            headers.insert(AUTHORIZATION, HeaderValue::from_str(&authorization)?);
+        }
+        Ok(())
+    }
+
+    async fn before_request(&self, size: u64) {
+        if let Some(rate_limiter) = &self.rate_limiter {
+            rate_limiter.wait().await;
+        }
+        if let Some(bandwidth_limiter) = &self.bandwidth_limiter {
+            bandwidth_limiter.take(size).await;
+        }
+    }
+
+    // Pace responses by the size of the body received, before the next request is sent
+    async fn after_body(&self, size: u64) {
+        if let Some(bandwidth_limiter) = &self.bandwidth_limiter {
+            bandwidth_limiter.take(size).await;
+        }
+    }
+
+    fn after_response(&self, success: bool) {
+        // The first successful response proves the credentials are valid
+        if self.sd_notify && success {
+            systemd::notify_ready();
+        }
+    }
+
+    // Send a request once, applying the limits and the faults
+    async fn send(&self, request: Request<Bytes>) -> Result<Response<Bytes>, Box<dyn Error + Sync + Send>> {
+        let size = request.body().len() as u64;
+        self.before_request(size).await;
+        if let Some(faults) = &self.faults {
+            match faults.inject(size) {
+                Some(Fault::Status(status)) =>
+                    return Ok(Response::builder().status(status).body(Bytes::from_static(b"Injected failure"))?),
+                Some(Fault::Drop) => return Err(Box::new(dropped())),
+                None => {}
+            }
+        }
+        let res = self.inner.execute_request(request).await;
+        self.after_response(matches!(&res, Ok(res) if res.status().is_success()));
+        if let Ok(res) = &res {
+            self.after_body(res.body().len() as u64).await;
+        }
+        res
+    }
+
+    async fn send2(&self, request: &azure_core::Request) -> Result<azure_core::Response, HttpError> {
+        let size = match request.body() {
+            azure_core::Body::Bytes(bytes) => bytes.len() as u64,
+            _ => 0
+        };
+        self.before_request(size).await;
+        if let Some(faults) = &self.faults {
+            match faults.inject(size) {
+                Some(Fault::Status(status)) =>
+                    return Err(HttpError::StatusCode { status, body: "Injected failure".into() }),
+                Some(Fault::Drop) => return Err(HttpError::ExecuteRequest(Box::new(dropped()))),
+                None => {}
+            }
+        }
+        let res = self.inner.execute_request2(request).await;
+        self.after_response(matches!(&res, Ok(res) if res.status().is_success()));
+        // Response bodies read as a stream are not paced
+        res
+    }
+}
+
+#[async_trait]
+impl HttpClient for PolicyHttpClient {
+    async fn execute_request(&self, mut request: Request<Bytes>)
+        -> Result<Response<Bytes>, Box<dyn Error + Sync + Send>>
+    {
+        let (method, uri) = (request.method().clone(), request.uri().clone());
+        check_append_only(&self.append_only, self.path_style, &method, &uri, request.headers())?;
+        self.add_headers(&method, &uri, request.headers_mut())?;
+        let idempotent = is_idempotent(&method, &uri, request.headers());
+        let mut attempt = 0;
+        loop {
+            let res = self.send(clone_request(&request)?).await;
+            let failure = match &res {
+                Ok(res) if self.retry.is_retryable(res.status(), idempotent) => res.status().to_string(),
+                Err(e) if idempotent => e.to_string(),
+                Ok(_) | Err(_) => return res
+            };
+            if attempt >= self.retry.retries {
+                return res;
+            }
+            self.retry.wait(&method, &uri, attempt, &failure).await;
+            attempt += 1;
+        }
+    }
+
+    async fn execute_request2(&self, request: &azure_core::Request)
+        -> Result<azure_core::Response, HttpError>
+    {
+        let mut request = request.clone();
+        let (method, uri) = (request.method().clone(), request.uri().clone());
+        check_append_only(&self.append_only, self.path_style, &method, &uri, request.headers())
+            .map_err(HttpError::ExecuteRequest)?;
+        self.add_headers(&method, &uri, request.headers_mut())
+            .map_err(HttpError::ExecuteRequest)?;
+        let idempotent = is_idempotent(&method, &uri, request.headers());
+        let mut attempt = 0;
+        loop {
+            let res = self.send2(&request).await;
+            let failure = match &res {
+                Ok(res) if self.retry.is_retryable(res.status(), idempotent) => res.status().to_string(),
+                Err(HttpError::StatusCode { status, .. }) if self.retry.is_retryable(*status, idempotent) =>
+                    status.to_string(),
+                Ok(_) | Err(HttpError::StatusCode { .. }) => return res,
+                Err(e) if idempotent => e.to_string(),
+                Err(_) => return res
+            };
+            if attempt >= self.retry.retries {
+                return res;
+            }
+            self.retry.wait(&method, &uri, attempt, &failure).await;
+            attempt += 1;
+        }
+    }
+}
+
+// Refuse requests which modify history in append-only containers, so that it is never modified: deletes,
+// block blob puts, puts of other blobs unless created only if not exist (If-None-Match: *), copies into blobs,
+// page writes and resizes. Appends and reads are allowed. Path-style URLs of an endpoint such as the emulator
+// start with the account.
+fn check_append_only(append_only: &[String], path_style: bool, method: &Method, uri: &Uri, headers: &HeaderMap)
+    -> Result<(), Box<dyn Error + Send + Sync>>
+{
+    let mut path = uri.path().trim_start_matches('/');
+    if path_style {
+        path = path.split_once('/').map_or("", |(_, path)| path);
+    }
+    let container = path.split('/').next().unwrap_or("");
+    if !append_only.iter().any(|v| v == container) {
+        return Ok(());
+    }
+    let comp = form_urlencoded::parse(uri.query().unwrap_or("").as_bytes())
+        .find(|(name, _)| name == "comp")
+        .map(|(_, value)| value.into_owned())
+        .unwrap_or_default();
+    let refused = match *method {
+        Method::DELETE => true,
+        Method::PUT => match comp.as_str() {
+            "block" | "blocklist" | "page" | "incrementalcopy" => true,
+            "properties" => headers.contains_key(BLOB_CONTENT_LENGTH),
+            // Append Block From URL has the source too
+            "appendblock" => false,
+            "" => match headers.get(BLOB_TYPE).map(HeaderValue::as_bytes) {
+                Some(b"BlockBlob") => true,
+                Some(_) => headers.get(IF_NONE_MATCH).map_or(true, |v| v.as_bytes() != b"*"),
+                None => headers.contains_key(COPY_SOURCE)
+            },
+            _ => headers.contains_key(COPY_SOURCE)
+        },
+        _ => false
+    };
+    if refused {
+        return Err(anyhow!("Refused {} '{}': container '{}' is append-only", method, path, container).into());
+    }
+    Ok(())
+}
+
+// Whether the request can be sent again after a failure which may have been applied by the service, such as a
+// lost response. Appending a block again would duplicate it, unless it is conditional on the append position.
+fn is_idempotent(method: &Method, uri: &Uri, headers: &HeaderMap) -> bool {
+    let append_block = form_urlencoded::parse(uri.query().unwrap_or("").as_bytes())
+        .any(|(name, value)| name == "comp" && value == "appendblock");
+    *method != Method::POST && (!append_block || headers.contains_key(APPEND_POSITION))
+}
+
+// Copy of a request to send it again. The body is shared.
+fn clone_request(request: &Request<Bytes>) -> Result<Request<Bytes>, http::Error> {
+    let mut builder = Request::builder()
+        .method(request.method().clone())
+        .uri(request.uri().clone())
+        .version(request.version());
+    if let Some(headers) = builder.headers_mut() {
+        *headers = request.headers().clone();
+    }
+    builder.body(request.body().clone())
+}
+
+// Status codes of transient failures retried by default: timeout, throttling and server errors
+const RETRY_STATUS_CODES: &[u16] = &[408, 429, 500, 502, 503, 504];
+
+// Upper limit of the delay between retries
+const MAX_RETRY_DELAY: f64 = 60.0;
+
+// Retry of transient failures, which are connection errors, timeouts and the retryable status codes.
+// The delay doubles from the backoff base on each retry, reduced by a random ratio up to the jitter
+// so that devices failed at the same time do not retry at the same time.
+#[derive(Debug)]
+struct RetryPolicy {
+    retries: u32,
+    backoff: f64,
+    jitter: f64,
+    status_codes: Vec<StatusCode>,
+}
+
+impl RetryPolicy {
+    fn from_config(cfg: &Configs) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        let status_codes = match cfg.retry_status_codes.is_empty() {
+            true => RETRY_STATUS_CODES.to_vec(),
+            false => cfg.retry_status_codes.clone()
+        };
+        let status_codes = status_codes.into_iter()
+            .map(|code| StatusCode::from_u16(code).map_err(|_| anyhow!("Invalid retry status code {}", code)))
+            .collect::<Result<Vec<_>, _>>()?;
+        let jitter = cfg.retry_jitter.unwrap_or(0.5);
+        if !(0.0..=1.0).contains(&jitter) {
+            return Err(anyhow!("Invalid retry jitter {}. Specify between 0 and 1", jitter).into());
+        }
+        Ok(RetryPolicy {
+            retries: cfg.retries.unwrap_or(3),
+            backoff: cfg.retry_backoff.unwrap_or(1.0).max(0.0),
+            jitter,
+            status_codes,
+        })
+    }
+
+    // Whether the status is retried. Requests which are not idempotent are retried only when throttled,
+    // which means they are rejected before processed.
+    fn is_retryable(&self, status: StatusCode, idempotent: bool) -> bool {
+        self.status_codes.contains(&status) && (idempotent || status == StatusCode::TOO_MANY_REQUESTS)
+    }
+
+    // Wait before the retry of the attempt (from 0) failed by the reason
+    async fn wait(&self, method: &Method, uri: &Uri, attempt: u32, failure: &str) {
+        let delay = (self.backoff * 2f64.powi(attempt as i32)).min(MAX_RETRY_DELAY);
+        let delay = delay * (1.0 - self.jitter * rand::random::<f64>());
+        eprintln!("Retrying {} {} in {:.1}s ({}/{}): {}", method, uri.path(), delay, attempt + 1, self.retries,
+            secret::redact_signatures(failure));
+        time::sleep(Duration::from_secs_f64(delay)).await;
+    }
+}
+
+// Failure injected into a request
+enum Fault {
+    // Respond the status without sending the request
+    Status(StatusCode),
+    // Fail as if the connection is dropped
+    Drop,
+}
+
+// Injects failures into requests, to test retry and resume logic end to end.
+// drop-after=<size> drops the connection each time the request bodies sent exceed the size in total,
+// and status-every=<n> responds 503 Service Unavailable to every n-th request.
+#[derive(Debug, Default)]
+struct FaultInjector {
+    drop_after: Option<u64>,
+    status_every: Option<u64>,
+    sent: AtomicU64,
+    requests: AtomicU64,
+}
+
+impl FaultInjector {
+    fn parse(spec: &str) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        let mut injector = FaultInjector::default();
+        for option in spec.split(',').map(str::trim).filter(|v| !v.is_empty()) {
+            let (name, value) = option.split_once('=')
+                .ok_or(anyhow!("Invalid fault '{}'. Specify as <name>=<value>", option))?;
+            let value: u64 = value.parse().map_err(|_| anyhow!("Invalid value of fault '{}'", option))?;
+            match name {
+                "drop-after" => injector.drop_after = Some(value.max(1)),
+                "status-every" => injector.status_every = Some(value.max(1)),
+                _ => return Err(anyhow!("Unknown fault '{}'", name).into())
+            }
+        }
+        Ok(injector)
+    }
+
+    // Fault to inject into a request with the body size, if any
+    fn inject(&self, size: u64) -> Option<Fault> {
+        let requests = self.requests.fetch_add(1, Ordering::SeqCst) + 1;
+        if matches!(self.status_every, Some(every) if requests % every == 0) {
+            return Some(Fault::Status(StatusCode::SERVICE_UNAVAILABLE));
+        }
+        if let Some(drop_after) = self.drop_after {
+            let sent = self.sent.fetch_add(size, Ordering::SeqCst) + size;
+            if sent > drop_after {
+                self.sent.store(0, Ordering::SeqCst);
+                return Some(Fault::Drop);
+            }
+        }
+        None
+    }
+}
+
+fn dropped() -> io::Error {
+    io::Error::new(io::ErrorKind::ConnectionReset, "Injected connection drop")
+}
+
+// Lowest limit of the request rate, one request per hour
+const MIN_REQUESTS_PER_SECOND: f64 = 1.0 / 3600.0;
+
+// Lowest limit of the throughput. A body of the maximum block size takes more than a day below it.
+const MIN_BYTES_PER_SECOND: u64 = 1024;
+
+// Limits the number of requests per second, by spacing requests at even intervals
+#[derive(Debug)]
+struct RateLimiter {
+    interval: Duration,
+    next: Mutex<Instant>,
+}
+
+impl RateLimiter {
+    // The interval is up to an hour, since longer ones (or NaN) are not representable as a duration
+    fn new(requests_per_second: f64) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        if !(requests_per_second >= MIN_REQUESTS_PER_SECOND && requests_per_second.is_finite()) {
+            return Err(anyhow!("Invalid max requests per second {}. Specify at least one per hour ({:.6})",
+                requests_per_second, MIN_REQUESTS_PER_SECOND).into());
+        }
+        Ok(RateLimiter {
+            interval: Duration::from_secs_f64(1.0 / requests_per_second),
+            next: Mutex::new(Instant::now()),
+        })
+    }
+
+    // Wait until the next request is allowed
+    async fn wait(&self) {
+        let mut next = self.next.lock().await;
+        let now = Instant::now();
+        if *next > now {
+            time::sleep_until(*next).await;
+        }
+        *next = (*next).max(now) + self.interval;
+    }
+}
+
+// Limits the throughput of request and response bodies by a token bucket of bytes.
+// The bucket holds up to one second of the rate, and a body larger than the tokens is sent or received
+// after the tokens are refilled, so the throughput is limited on average over bodies.
+#[derive(Debug)]
+struct BandwidthLimiter {
+    bytes_per_second: f64,
+    // Tokens in bytes, negative while a body exceeding them is paced, and the time of the last refill
+    bucket: Mutex<(f64, Instant)>,
+}
+
+impl BandwidthLimiter {
+    fn new(bytes_per_second: u64) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        if bytes_per_second < MIN_BYTES_PER_SECOND {
+            return Err(anyhow!("Invalid limit rate {} bytes per second. Specify at least {}",
+                bytes_per_second, MIN_BYTES_PER_SECOND).into());
+        }
+        let bytes_per_second = bytes_per_second as f64;
+        Ok(BandwidthLimiter { bytes_per_second, bucket: Mutex::new((bytes_per_second, Instant::now())) })
+    }
+
+    // Take tokens for a body of the size, and wait until the bucket is refilled to cover them
+    async fn take(&self, size: u64) {
+        if size == 0 {
+            return;
+        }
+        let wait = {
+            let mut bucket = self.bucket.lock().await;
+            let now = Instant::now();
+            let refilled = bucket.0 + now.duration_since(bucket.1).as_secs_f64() * self.bytes_per_second;
+            *bucket = (refilled.min(self.bytes_per_second) - size as f64, now);
+            match bucket.0 < 0.0 {
+                true => Duration::from_secs_f64(-bucket.0 / self.bytes_per_second),
+                false => Duration::ZERO
+            }
+        };
+        time::sleep(wait).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check(method: Method, uri: &str, headers: &[(&'static str, &'static str)]) -> bool {
+        let mut map = HeaderMap::new();
+        for (name, value) in headers {
+            map.insert(*name, HeaderValue::from_static(*value));
+        }
+        let append_only = vec!["logs".to_string()];
+        check_append_only(&append_only, false, &method, &uri.parse().unwrap(), &map).is_ok()
+    }
+
+    #[test]
+    fn parse_resolve_accepts_ipv4_and_ipv6() {
+        let (host, ip) = parse_resolve("id.blob.core.windows.net:10.0.0.5").unwrap();
+        assert_eq!(host, "id.blob.core.windows.net");
+        assert_eq!(ip, IpAddr::from(Ipv4Addr::new(10, 0, 0, 5)));
+        let (_, ip) = parse_resolve("id.blob.core.windows.net:[fd00::5]").unwrap();
+        assert_eq!(ip, "fd00::5".parse::<IpAddr>().unwrap());
+        assert_eq!(parse_resolve("id.blob.core.windows.net:::1").unwrap().1, IpAddr::from(Ipv6Addr::LOCALHOST));
+    }
+
+    #[test]
+    fn parse_resolve_rejects_invalid_entries() {
+        assert!(parse_resolve("id.blob.core.windows.net").is_err());
+        assert!(parse_resolve(":10.0.0.5").is_err());
+        assert!(parse_resolve("id.blob.core.windows.net:example.com").is_err());
+        assert!(parse_resolve("id.blob.core.windows.net:10.0.0.5:443").is_err());
+    }
+
+    #[test]
+    fn idempotent_requests() {
+        let uri = |query: &str| format!("https://a.blob.core.windows.net/logs/a{}", query).parse::<Uri>().unwrap();
+        let mut headers = HeaderMap::new();
+        assert!(is_idempotent(&Method::PUT, &uri("?comp=block&blockid=AA"), &headers));
+        assert!(is_idempotent(&Method::GET, &uri(""), &headers));
+        assert!(!is_idempotent(&Method::POST, &uri(""), &headers));
+        assert!(!is_idempotent(&Method::PUT, &uri("?comp=appendblock"), &headers));
+
+        headers.insert(APPEND_POSITION, HeaderValue::from_static("1024"));
+        assert!(is_idempotent(&Method::PUT, &uri("?comp=appendblock"), &headers));
+    }
+
+    #[test]
+    fn retry_policy_from_config() {
+        let policy = RetryPolicy::from_config(&Configs::default()).unwrap();
+        assert_eq!((policy.retries, policy.backoff, policy.jitter), (3, 1.0, 0.5));
+        assert!(policy.is_retryable(StatusCode::SERVICE_UNAVAILABLE, true));
+        assert!(!policy.is_retryable(StatusCode::NOT_FOUND, true));
+
+        let cfg = Configs { retries: Some(5), retry_status_codes: vec![500], ..Default::default() };
+        let policy = RetryPolicy::from_config(&cfg).unwrap();
+        assert_eq!(policy.retries, 5);
+        assert!(policy.is_retryable(StatusCode::INTERNAL_SERVER_ERROR, true));
+        assert!(!policy.is_retryable(StatusCode::SERVICE_UNAVAILABLE, true));
+
+        assert!(RetryPolicy::from_config(&Configs { retry_status_codes: vec![1000], ..Default::default() }).is_err());
+        assert!(RetryPolicy::from_config(&Configs { retry_jitter: Some(1.5), ..Default::default() }).is_err());
+    }
+
+    #[test]
+    fn retry_policy_retries_only_throttling_of_non_idempotent_requests() {
+        let policy = RetryPolicy::from_config(&Configs::default()).unwrap();
+        assert!(policy.is_retryable(StatusCode::TOO_MANY_REQUESTS, false));
+        assert!(!policy.is_retryable(StatusCode::SERVICE_UNAVAILABLE, false));
+        assert!(!policy.is_retryable(StatusCode::INTERNAL_SERVER_ERROR, false));
+    }
+
+    #[test]
+    fn fault_injector_parse() {
+        let injector = FaultInjector::parse("drop-after=1048576, status-every=0").unwrap();
+        assert_eq!(injector.drop_after, Some(1048576));
+        assert_eq!(injector.status_every, Some(1));
+        let injector = FaultInjector::parse("").unwrap();
+        assert_eq!((injector.drop_after, injector.status_every), (None, None));
+
+        assert!(FaultInjector::parse("drop-after").is_err());
+        assert!(FaultInjector::parse("drop-after=1M").is_err());
+        assert!(FaultInjector::parse("timeout=5").is_err());
+    }
+
+    #[test]
+    fn fault_injector_injects_faults() {
+        let injector = FaultInjector::parse("status-every=3").unwrap();
+        let faults: Vec<bool> = (0..6).map(|_| matches!(injector.inject(0), Some(Fault::Status(_)))).collect();
+        assert_eq!(faults, vec![false, false, true, false, false, true]);
+
+        let injector = FaultInjector::parse("drop-after=100").unwrap();
+        assert!(injector.inject(60).is_none());
+        assert!(matches!(injector.inject(60), Some(Fault::Drop)));
+        assert!(injector.inject(60).is_none());
+    }
+
+    #[test]
+    fn append_only_refuses_modification() {
+        let url = "https://acct.blob.core.windows.net/logs/device.log";
+        assert!(!check(Method::DELETE, url, &[]));
+        assert!(!check(Method::DELETE, "https://acct.blob.core.windows.net/logs?restype=container", &[]));
+        assert!(!check(Method::PUT, url, &[(BLOB_TYPE, "BlockBlob")]));
+        assert!(!check(Method::PUT, url, &[(BLOB_TYPE, "BlockBlob"), ("if-none-match", "*")]));
+        assert!(!check(Method::PUT, url, &[(BLOB_TYPE, "AppendBlob")]));
+        assert!(!check(Method::PUT, url, &[(BLOB_TYPE, "PageBlob"), ("if-none-match", "\"0x1\"")]));
+        assert!(!check(Method::PUT, &format!("{}?comp=block&blockid=AAAA", url), &[]));
+        assert!(!check(Method::PUT, &format!("{}?comp=blocklist", url), &[]));
+        assert!(!check(Method::PUT, &format!("{}?comp=page", url), &[("x-ms-page-write", "update")]));
+        assert!(!check(Method::PUT, &format!("{}?comp=incrementalcopy", url), &[(COPY_SOURCE, "https://src/vhd")]));
+        assert!(!check(Method::PUT, &format!("{}?comp=properties", url), &[(BLOB_CONTENT_LENGTH, "512")]));
+        assert!(!check(Method::PUT, url, &[(COPY_SOURCE, "https://src/device.log")]));
+    }
+
+    #[test]
+    fn append_only_allows_appends_and_reads() {
+        let url = "https://acct.blob.core.windows.net/logs/device.log";
+        assert!(check(Method::GET, url, &[]));
+        assert!(check(Method::HEAD, url, &[]));
+        assert!(check(Method::PUT, url, &[(BLOB_TYPE, "AppendBlob"), ("if-none-match", "*")]));
+        assert!(check(Method::PUT, &format!("{}?comp=appendblock", url), &[]));
+        assert!(check(Method::PUT, &format!("{}?comp=appendblock", url), &[(COPY_SOURCE, "https://src/part")]));
+        assert!(check(Method::PUT, &format!("{}?comp=metadata", url), &[]));
+        assert!(check(Method::PUT, &format!("{}?comp=lease", url), &[("x-ms-lease-action", "acquire")]));
+        // Other containers are not restricted
+        assert!(check(Method::DELETE, "https://acct.blob.core.windows.net/tmp/device.log", &[]));
+        assert!(check(Method::PUT, "https://acct.blob.core.windows.net/logsx/a", &[(BLOB_TYPE, "BlockBlob")]));
+    }
+
+    #[test]
+    fn rate_limiter_rejects_invalid_rates() {
+        for rate in [f64::NAN, f64::INFINITY, f64::NEG_INFINITY, -1.0, 1e-300, 0.0001] {
+            assert!(RateLimiter::new(rate).is_err(), "{}", rate);
+        }
+        assert_eq!(RateLimiter::new(0.5).unwrap().interval, Duration::from_secs(2));
+        assert_eq!(RateLimiter::new(1.0 / 3600.0).unwrap().interval, Duration::from_secs(3600));
+        assert!(RateLimiter::new(1e9).is_ok());
+    }
+
+    #[tokio::test]
+    async fn rate_limiter_spaces_requests() {
+        let rate_limiter = RateLimiter::new(100.0).unwrap();
+        let start = Instant::now();
+        for _ in 0..5 {
+            rate_limiter.wait().await;
+        }
+        // The first request is not delayed
+        assert!(start.elapsed() >= Duration::from_millis(40));
+    }
+
+    #[test]
+    fn bandwidth_limiter_rejects_invalid_rates() {
+        assert!(BandwidthLimiter::new(0).is_err());
+        assert!(BandwidthLimiter::new(1023).is_err());
+        assert!(BandwidthLimiter::new(1024).is_ok());
+        assert!(BandwidthLimiter::new(u64::MAX).is_ok());
+    }
+
+    #[tokio::test]
+    async fn bandwidth_limiter_paces_bodies() {
+        let bandwidth_limiter = BandwidthLimiter::new(10240).unwrap();
+        let start = Instant::now();
+        // A burst up to one second of the rate is not delayed
+        bandwidth_limiter.take(10240).await;
+        assert!(start.elapsed() < Duration::from_millis(200));
+        bandwidth_limiter.take(5120).await;
+        assert!(start.elapsed() >= Duration::from_millis(450));
+    }
+
+    #[test]
+    fn append_only_with_path_style() {
+        let append_only = vec!["logs".to_string()];
+        let uri = "http://127.0.0.1:10000/devstoreaccount1/logs/device.log".parse().unwrap();
+        assert!(check_append_only(&append_only, true, &Method::DELETE, &uri, &HeaderMap::new()).is_err());
+        let uri = "http://127.0.0.1:10000/logs/tmp/device.log".parse().unwrap();
+        assert!(check_append_only(&append_only, true, &Method::DELETE, &uri, &HeaderMap::new()).is_ok());
+    }
+}
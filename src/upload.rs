@@ -0,0 +1,539 @@
+use azure_core::prelude::*;
+use azure_storage::blob::prelude::*;
+use azure_storage::core::prelude::*;
+
+use std::io::prelude::*;
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::error::Error;
+use anyhow::anyhow;
+use azst::{NamingPolicy, Pipeline};
+use chrono::{DateTime, Utc};
+use futures::stream::{self, Stream, StreamExt, TryStreamExt};
+use http::StatusCode;
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use tokio::sync::mpsc;
+use crate::audit::AuditLog;
+use crate::block_hashes::BlockHashes;
+use crate::chain;
+use crate::config::Configs;
+use crate::expire;
+use crate::hash::{HashAlgorithm, Hasher};
+use crate::journal::BlockJournal;
+use crate::manifest::ManifestEntry;
+use crate::progress::{FileProgress, Progress};
+use crate::signature;
+use crate::transport;
+
+// Maximum number of blocks in a block blob
+const MAX_BLOCKS: u64 = 50_000;
+
+// Maximum size of an append block
+pub const APPEND_BLOCK_SIZE: u64 = 4 * 1024 * 1024;
+
+// How to put files by blocks
+#[derive(Clone, Copy, Debug)]
+pub struct Blocks {
+    // Files larger than it are put by blocks of the size
+    pub size: u64,
+    // Number of blocks staged concurrently
+    pub concurrency: usize,
+    // Skip blocks staged by the last interrupted put, recorded in the journal
+    pub resume: bool,
+    // Record the hashes of the blocks in the sidecar "<blob>.blocks" for spot verification
+    pub hashes: bool,
+}
+
+// Put a local file as a block blob, transformed by the pipeline. If signing config is given,
+// the minisign signature is uploaded next to it as "<blob>.sig". The signature is created before uploading,
+// so that the file is never uploaded without it. The signature and the manifest entry are of the file
+// before transforms, so that they can be verified after getting.
+// Files larger than the block size are uploaded by blocks with bounded memory, unless they are transformed
+// or signed which need the whole content. The hash of the file by the algorithm is set to the metadata
+// unless it is MD5 of a blob put at once, which is the Content-MD5, with the expiry if given. Returns the manifest entry of the uploaded file.
+pub async fn put_file(storage_client: &StorageClient, path: &Path, container: &str, blob: &str,
+    transforms: &Pipeline, sign: Option<&Configs>, blocks: Blocks, hash: HashAlgorithm,
+    expires: Option<DateTime<Utc>>, progress: &Progress, audit: &AuditLog, debug: bool)
+    -> Result<ManifestEntry, Box<dyn Error + Send + Sync>>
+{
+    let size = fs::metadata(path).map_err(|e| anyhow!("Cannot read {:?}: {}", path, e))?.len();
+    let file_progress = progress.file(path, size);
+    if transforms.is_empty() && sign.is_none() && size > blocks.size {
+        return put_blocks(storage_client, path, container, blob, size, blocks, hash, expires, &file_progress, audit,
+            debug).await;
+    }
+
+    let buffer = fs::read(path).map_err(|e| anyhow!("Cannot read {:?}: {}", path, e))?;
+    let signature = sign.map(|cfg| signature::sign(&buffer, cfg)).transpose()?;
+    let mut entry = ManifestEntry::new(storage_client, path, container, blob, &buffer)?;
+    let metadata = blob_metadata(hash, hash.in_metadata(!transforms.is_empty()).then(|| hash.digest(&buffer)), expires);
+    let buffer = transforms.encode(buffer)?;
+
+    // this is not mandatory but it helps preventing spurious data to be uploaded
+    let md5 = md5::compute(&buffer).into();
+
+    let container_client = storage_client.as_container_client(container);
+    let res = container_client
+        .as_blob_client(blob)
+        .put_block_blob(buffer)
+        .hash(&md5)
+        .metadata(&metadata)
+        .execute()
+        .await?;
+    audit.record(storage_client, "put", container, blob, &res.request_id).await?;
+    crate::debug_print(res, debug);
+    entry.time = Utc::now().to_rfc3339();
+    file_progress.inc(size);
+
+    // Upload the signature next to the payload
+    if let Some(signature) = signature {
+        let sig_blob = format!("{}.sig", blob);
+        let res = container_client
+            .as_blob_client(sig_blob.as_str())
+            .put_block_blob(signature.into_bytes())
+            .execute()
+            .await?;
+        audit.record(storage_client, "put", container, &sig_blob, &res.request_id).await?;
+    }
+    Ok(entry)
+}
+
+// Put a local file by staging blocks of the block size concurrently and committing the block list in order.
+// Blocks are read in order while staging, so at most as many blocks as the concurrency are in memory.
+// The block size is increased if the file needs too many blocks.
+// Staged blocks are recorded in the journal, so that an interrupted put can be resumed. Skipped blocks are
+// still read to hash the whole file.
+async fn put_blocks(storage_client: &StorageClient, path: &Path, container: &str, blob: &str, size: u64,
+    blocks: Blocks, hash: HashAlgorithm, expires: Option<DateTime<Utc>>, progress: &FileProgress, audit: &AuditLog,
+    debug: bool) -> Result<ManifestEntry, Box<dyn Error + Send + Sync>>
+{
+    let block_size = blocks.size.max((size + MAX_BLOCKS - 1) / MAX_BLOCKS);
+    let mut file = File::open(path).map_err(|e| anyhow!("Cannot read {:?}: {}", path, e))?;
+    let blob_client = storage_client.as_container_client(container).as_blob_client(blob);
+
+    // Block IDs must have the same length in a blob
+    let block_ids: Vec<BlockId> = (0..(size + block_size - 1) / block_size)
+        .map(|i| BlockId::new(format!("{:08}", i).into_bytes()))
+        .collect();
+
+    // Staged blocks are valid only for the same file put by the same block size
+    let metadata = fs::metadata(path)?;
+    let header = json!({
+        "container": container,
+        "blob": blob,
+        "size": size,
+        "modified": DateTime::<Utc>::from(metadata.modified()?).to_rfc3339(),
+        "block_size": block_size,
+    });
+    let journal = BlockJournal::open(&BlockJournal::path(container, blob), &header, blocks.resume)?;
+    if debug && journal.resumed() > 0 {
+        println!("{} of {} blocks of {:?} are already staged", journal.resumed(), block_ids.len(), path);
+    }
+
+    // The service sets no Content-MD5 to a blob put by blocks, so the hash is in the metadata even if it is MD5
+    let mut sha256 = Sha256::new();
+    let mut hasher = Hasher::new(hash);
+    let mut block_hashes = Some(BlockHashes::new(size, block_size)).filter(|_| blocks.hashes);
+    let mut uploaded = 0;
+    let blob_client = &blob_client;
+    let staged = &journal;
+    stream::iter(block_ids.iter().enumerate())
+        .map(|(i, block_id)| {
+            let mut buffer = Vec::with_capacity(block_size as usize);
+            let read = (&mut file).take(block_size).read_to_end(&mut buffer);
+            sha256.update(&buffer);
+            hasher.update(&buffer);
+            if let Some(block_hashes) = block_hashes.as_mut() {
+                block_hashes.add(&buffer);
+            }
+            uploaded += buffer.len() as u64;
+            async move {
+                read.map_err(|e| anyhow!("Cannot read {:?}: {}", path, e))?;
+                let length = buffer.len() as u64;
+                if staged.is_done(i as u64) {
+                    progress.inc(length);
+                    return Ok(());
+                }
+                let md5 = md5::compute(&buffer).into();
+                blob_client.put_block(block_id.clone(), buffer).hash(&md5).execute().await?;
+                staged.add(i as u64)?;
+                progress.inc(length);
+                if debug {
+                    println!(" block {} of {:?}", i, path);
+                }
+                Ok::<_, Box<dyn Error + Send + Sync>>(())
+            }
+        })
+        .buffer_unordered(blocks.concurrency.max(1))
+        .try_collect::<Vec<_>>()
+        .await?;
+    if uploaded != size {
+        return Err(anyhow!("{:?} is modified while putting", path).into());
+    }
+
+    let block_list = BlockList {
+        blocks: block_ids.into_iter().map(BlobBlockType::new_uncommitted).collect()
+    };
+    let metadata = blob_metadata(hash, Some(hasher.finalize()), expires);
+    let res = blob_client.put_block_list(&block_list).metadata(&metadata).execute().await?;
+    audit.record(storage_client, "put", container, blob, &res.request_id).await?;
+    crate::debug_print(res, debug);
+    journal.remove()?;
+    if let Some(block_hashes) = block_hashes {
+        block_hashes.put(storage_client, container, blob, audit).await?;
+    }
+
+    let mut entry = ManifestEntry::with_hash(storage_client, path, container, blob, uploaded,
+        format!("{:x}", sha256.finalize()))?;
+    entry.time = Utc::now().to_rfc3339();
+    Ok(entry)
+}
+
+// Put the data from stdin ("-") or a FIFO as a block blob by staging blocks of the block size as they are read
+// until EOF, so that the total size does not need to be known in advance. Up to 50,000 blocks can be staged,
+// so the size is limited by the block size. Blocks are not journaled, since the stream cannot be read again.
+pub async fn put_stream(storage_client: &StorageClient, source: &Path, container: &str, blob: &str, blocks: Blocks,
+    hash: HashAlgorithm, expires: Option<DateTime<Utc>>, audit: &AuditLog, debug: bool)
+    -> Result<ManifestEntry, Box<dyn Error + Send + Sync>>
+{
+    let blob_client = storage_client.as_container_client(container).as_blob_client(blob);
+    let reader = read_blocks(open_stream(source)?, blocks.size, blocks.concurrency.max(1));
+
+    // The service sets no Content-MD5 to a blob put by blocks, so the hash is in the metadata even if it is MD5
+    let mut sha256 = Sha256::new();
+    let mut hasher = Hasher::new(hash);
+    let mut block_hashes = Some(BlockHashes::new(0, blocks.size)).filter(|_| blocks.hashes);
+    let mut uploaded = 0;
+    let mut block_ids = Vec::new();
+    let blob_client = &blob_client;
+    reader.enumerate()
+        .map(|(i, buffer)| {
+            // Block IDs must have the same length in a blob
+            let block_id = BlockId::new(format!("{:08}", i).into_bytes());
+            block_ids.push(block_id.clone());
+            if let Ok(buffer) = &buffer {
+                sha256.update(buffer);
+                hasher.update(buffer);
+                if let Some(block_hashes) = block_hashes.as_mut() {
+                    block_hashes.add(buffer);
+                }
+                uploaded += buffer.len() as u64;
+            }
+            async move {
+                let buffer = buffer.map_err(|e| anyhow!("Cannot read {:?}: {}", source, e))?;
+                if i as u64 >= MAX_BLOCKS {
+                    return Err(anyhow!("Data from {:?} exceeds {} blocks of {} bytes. Specify larger --block-size",
+                        source, MAX_BLOCKS, blocks.size).into());
+                }
+                let md5 = md5::compute(&buffer).into();
+                blob_client.put_block(block_id, buffer).hash(&md5).execute().await?;
+                if debug {
+                    println!(" block {} of {:?}", i, source);
+                }
+                Ok::<_, Box<dyn Error + Send + Sync>>(())
+            }
+        })
+        .buffer_unordered(blocks.concurrency.max(1))
+        .try_collect::<Vec<_>>()
+        .await?;
+
+    let block_list = BlockList {
+        blocks: block_ids.into_iter().map(BlobBlockType::new_uncommitted).collect()
+    };
+    let metadata = blob_metadata(hash, Some(hasher.finalize()), expires);
+    let res = blob_client.put_block_list(&block_list).metadata(&metadata).execute().await?;
+    audit.record(storage_client, "put", container, blob, &res.request_id).await?;
+    crate::debug_print(res, debug);
+    if let Some(mut block_hashes) = block_hashes {
+        block_hashes.size = uploaded;
+        block_hashes.put(storage_client, container, blob, audit).await?;
+    }
+
+    let mut entry = ManifestEntry::with_hash(storage_client, source, container, blob, uploaded,
+        format!("{:x}", sha256.finalize()))?;
+    entry.time = Utc::now().to_rfc3339();
+    Ok(entry)
+}
+
+// Whether the local path is a stream read until EOF, which is stdin ("-") or a FIFO (named pipe),
+// such as made by mkfifo for software which can only write to a file path
+pub fn is_stream(path: &Path) -> bool {
+    #[cfg(unix)]
+    use std::os::unix::fs::FileTypeExt;
+    #[cfg(unix)]
+    let fifo = fs::metadata(path).map_or(false, |metadata| metadata.file_type().is_fifo());
+    #[cfg(not(unix))]
+    let fifo = false;
+    path == Path::new("-") || fifo
+}
+
+// Open stdin ("-") or a FIFO to read until EOF. Opening a FIFO waits for a writer.
+pub fn open_stream(path: &Path) -> Result<Box<dyn Read + Send>, Box<dyn Error + Send + Sync>> {
+    if path == Path::new("-") {
+        return Ok(Box::new(io::stdin()));
+    }
+    Ok(Box::new(File::open(path).map_err(|e| anyhow!("Cannot read {:?}: {}", path, e))?))
+}
+
+// Blocks of the size read from the input until EOF. The input is read on a thread, so that waiting for the writer
+// of a stream does not block the runtime, and at most as many blocks as the capacity are read ahead.
+// The thread ends at EOF, on an error, or once the blocks are no longer received.
+pub fn read_blocks(mut input: Box<dyn Read + Send>, size: u64, capacity: usize)
+    -> impl Stream<Item = io::Result<Vec<u8>>>
+{
+    let (tx, rx) = mpsc::channel(capacity);
+    thread::spawn(move || loop {
+        let mut buffer = Vec::with_capacity(size as usize);
+        let block = match (&mut input).take(size).read_to_end(&mut buffer) {
+            Ok(0) => return,
+            Ok(_) => Ok(buffer),
+            Err(e) => Err(e)
+        };
+        let failed = block.is_err();
+        if tx.blocking_send(block).is_err() || failed {
+            return;
+        }
+    });
+    stream::unfold(rx, |mut rx| async move { rx.recv().await.map(|block| (block, rx)) })
+}
+
+// Append a block to the append blob, with the hash chain if requested.
+// If creating, the append blob is created if not exist, such as the blob of a new window of rollover.
+pub async fn append_block(storage_client: &StorageClient, container: &str, blob: &str, buffer: Vec<u8>, chain: bool,
+    create: bool, audit: &AuditLog, debug: bool) -> Result<(), Box<dyn Error + Send + Sync>>
+{
+    let blob_client = storage_client
+        .as_container_client(container)
+        .as_blob_client(blob);
+
+    // Append with the integrity chain
+    if chain {
+        match chain::append(storage_client, container, blob, buffer.clone(), audit, debug).await {
+            Err(e) if create && transport::is_not_found(e.as_ref()) => {
+                create_append_blob(storage_client, container, blob, Some(audit)).await?;
+                chain::append(storage_client, container, blob, buffer, audit, debug).await?;
+            },
+            result => result?
+        }
+        return Ok(());
+    }
+
+    // this is not mandatory but it helps preventing spurious data to be uploaded
+    let hash = md5::compute(&buffer).into();
+
+    let res = match blob_client.append_block(buffer.clone()).hash(&hash).execute().await {
+        Ok(res) => res,
+        Err(e) if create && transport::is_not_found(e.as_ref()) => {
+            create_append_blob(storage_client, container, blob, Some(audit)).await?;
+            blob_client.append_block(buffer).hash(&hash).execute().await?
+        },
+        Err(e) => return Err(e)
+    };
+    audit.record(storage_client, "append", container, blob, &res.request_id).await?;
+    crate::debug_print(res, debug);
+    Ok(())
+}
+
+// Create the append blob if it does not exist, such as on the first append. The create is conditional on no blob
+// (If-None-Match: *), so that blocks appended by another writer in between are never truncated.
+// Returns whether it is created. Recorded to the audit log if given, which is not given for the audit blob itself.
+pub async fn create_append_blob(storage_client: &StorageClient, container: &str, blob: &str, audit: Option<&AuditLog>)
+    -> Result<bool, Box<dyn Error + Send + Sync>>
+{
+    let res = storage_client
+        .as_container_client(container)
+        .as_blob_client(blob)
+        .put_append_blob()
+        .if_match_condition(IfMatchCondition::NotMatch("*".into()))
+        .execute()
+        .await;
+    match res {
+        Ok(res) => {
+            if let Some(audit) = audit {
+                audit.record(storage_client, "put-append", container, blob, &res.request_id).await?;
+            }
+            Ok(true)
+        },
+        Err(e) if matches!(transport::error_status(e.as_ref()),
+            Some(StatusCode::CONFLICT | StatusCode::PRECONDITION_FAILED)) => Ok(false),
+        Err(e) => Err(e)
+    }
+}
+
+// Metadata of a blob with the hash of its content if given, and the expiry if given
+fn blob_metadata(hash: HashAlgorithm, digest: Option<String>, expires: Option<DateTime<Utc>>) -> Metadata {
+    let mut metadata = Metadata::new();
+    if let Some(digest) = digest {
+        metadata.insert(hash.metadata_name(), digest);
+    }
+    if let Some(expires) = expires {
+        metadata.insert(expire::EXPIRES, expires.to_rfc3339());
+    }
+    metadata
+}
+
+// Put all files under the local directory as block blobs, named by the naming policy from the prefix
+// and the relative path. Files are put concurrently, and blocks of each file are staged one by one.
+// Returns the manifest entries of the uploaded files.
+pub async fn put_dir(storage_client: &StorageClient, dir: &Path, container: &str, prefix: &str,
+    naming: &dyn NamingPolicy, transforms: &Pipeline, sign: Option<&Configs>, blocks: Blocks, hash: HashAlgorithm,
+    expires: Option<DateTime<Utc>>, concurrency: usize, progress: &Progress, audit: &AuditLog, debug: bool)
+    -> Result<Vec<ManifestEntry>, Box<dyn Error + Send + Sync>>
+{
+    let mut files = Vec::new();
+    walk(dir, &mut files)?;
+    files.sort();
+    if debug {
+        println!("{} files under {:?}", files.len(), dir);
+    }
+    progress.start_total(files.len(), total_size(&files));
+
+    let entries: Vec<ManifestEntry> = stream::iter(files.iter())
+        .map(|path| async move {
+            let blob = blob_name(naming, prefix, path.strip_prefix(dir)?)?;
+            let blocks = Blocks { concurrency: 1, ..blocks };
+            let entry = put_file(storage_client, path, container, &blob, transforms, sign, blocks, hash, expires,
+                progress, audit, debug).await?;
+            println!(" {} -> {}", path.display(), blob);
+            Ok::<_, Box<dyn Error + Send + Sync>>(entry)
+        })
+        .buffer_unordered(concurrency.max(1))
+        .try_collect()
+        .await?;
+
+    progress.finish();
+    println!("Put {} files to '{}/{}'", entries.len(), container, prefix);
+    Ok(entries)
+}
+
+// Total size of local files
+pub fn total_size(files: &[PathBuf]) -> u64 {
+    files.iter().filter_map(|path| fs::metadata(path).ok()).map(|metadata| metadata.len()).sum()
+}
+
+// Create the container with the public access level ("none", "blob" or "container") if it does not exist,
+// so that first-boot devices can upload without provisioning. Returns whether it is created.
+pub async fn create_container(storage_client: &StorageClient, container: &str, public_access: &str, audit: &AuditLog)
+    -> Result<bool, Box<dyn Error + Send + Sync>>
+{
+    let public_access = match public_access {
+        "" | "none" => PublicAccess::None,
+        "blob" => PublicAccess::Blob,
+        "container" => PublicAccess::Container,
+        v => return Err(anyhow!("Invalid public access '{}'. Specify none, blob or container", v).into())
+    };
+    match storage_client.as_container_client(container).create().public_access(public_access).execute().await {
+        Ok(res) => {
+            audit.record(storage_client, "create-container", container, "", &res.request_id).await?;
+            println!("Created container '{}'", container);
+            Ok(true)
+        },
+        Err(e) if transport::error_status(e.as_ref()) == Some(StatusCode::CONFLICT) => Ok(false),
+        Err(e) => Err(e)
+    }
+}
+
+// Size of a local file, or the total size of files under a local directory
+pub fn local_size(path: &Path) -> Result<u64, Box<dyn Error + Send + Sync>> {
+    if !path.is_dir() {
+        return Ok(fs::metadata(path).map_err(|e| anyhow!("Cannot read {:?}: {}", path, e))?.len());
+    }
+    let mut files = Vec::new();
+    walk(path, &mut files)?;
+    Ok(total_size(&files))
+}
+
+// Blob name of a relative local path by the naming policy. The path is separated by '/' on any platform.
+pub fn blob_name(naming: &dyn NamingPolicy, prefix: &str, relative: &Path)
+    -> Result<String, Box<dyn Error + Send + Sync>>
+{
+    let names: Vec<_> = relative.iter().map(|name| name.to_string_lossy()).collect();
+    naming.blob_name(prefix, &names.join("/"))
+}
+
+// Collect regular files under the directory recursively
+pub fn walk(dir: &Path, files: &mut Vec<PathBuf>) -> Result<(), Box<dyn Error + Send + Sync>> {
+    for entry in fs::read_dir(dir).map_err(|e| anyhow!("Cannot read directory {:?}: {}", dir, e))? {
+        let path = entry?.path();
+        if path.is_dir() {
+            walk(&path, files)?;
+        } else if path.is_file() {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use crate::mock;
+
+    const BLOCKS: Blocks = Blocks { size: 1024, concurrency: 2, resume: false, hashes: false };
+
+    // Local file of 3 blocks, and the path of its blob
+    fn local_file() -> (PathBuf, String, Vec<u8>) {
+        let blob = format!("azst-test-{}", uuid::Uuid::new_v4());
+        let path = env::temp_dir().join(&blob);
+        let data: Vec<u8> = (0..2500u32).map(|i| (i % 251) as u8).collect();
+        fs::write(&path, &data).unwrap();
+        (path, blob, data)
+    }
+
+    // Requests to put the file, by the function of put_file or put_stream
+    async fn put(hash: HashAlgorithm, stream: bool) -> (Vec<u8>, Vec<mock::Recorded>) {
+        let (storage_client, http_client) = mock::storage_client(|_| mock::status(StatusCode::CREATED));
+        let (path, blob, data) = local_file();
+        let audit = AuditLog::new(&Configs::default()).unwrap();
+        let result = match stream {
+            false => put_file(&storage_client, &path, "test", &blob, &Pipeline::new(), None, BLOCKS, hash, None,
+                &Progress::new(false), &audit, false).await,
+            true => put_stream(&storage_client, &path, "test", &blob, BLOCKS, hash, None, &audit, false).await
+        };
+        fs::remove_file(&path).unwrap();
+        result.unwrap();
+        (data, http_client.requests())
+    }
+
+    fn block_list(requests: &[mock::Recorded]) -> &mock::Recorded {
+        assert_eq!(requests.iter().filter(|request| request.has_query("comp=block")).count(), 3);
+        requests.iter().find(|request| request.has_query("comp=blocklist")).unwrap()
+    }
+
+    #[tokio::test]
+    async fn put_blocks_records_md5_of_whole_file() {
+        let (data, requests) = put(HashAlgorithm::Md5, false).await;
+        let block_list = block_list(&requests);
+        assert_eq!(block_list.header("x-ms-meta-azst_md5"), Some(HashAlgorithm::Md5.digest(&data).as_str()));
+    }
+
+    #[tokio::test]
+    async fn put_blocks_records_hash_of_algorithm() {
+        let (data, requests) = put(HashAlgorithm::Sha256, false).await;
+        let block_list = block_list(&requests);
+        assert_eq!(block_list.header("x-ms-meta-azst_sha256"), Some(HashAlgorithm::Sha256.digest(&data).as_str()));
+        assert_eq!(block_list.header("x-ms-meta-azst_md5"), None);
+    }
+
+    #[tokio::test]
+    async fn read_blocks_until_eof() {
+        let data: Vec<u8> = (0..2500u32).map(|i| (i % 251) as u8).collect();
+        let blocks: Vec<Vec<u8>> = read_blocks(Box::new(io::Cursor::new(data.clone())), 1024, 1)
+            .try_collect()
+            .await
+            .unwrap();
+        assert_eq!(blocks.iter().map(Vec::len).collect::<Vec<_>>(), vec![1024, 1024, 452]);
+        assert_eq!(blocks.concat(), data);
+    }
+
+    #[tokio::test]
+    async fn put_stream_records_md5_of_whole_stream() {
+        let (data, requests) = put(HashAlgorithm::Md5, true).await;
+        let block_list = block_list(&requests);
+        assert_eq!(block_list.header("x-ms-meta-azst_md5"), Some(HashAlgorithm::Md5.digest(&data).as_str()));
+    }
+}
@@ -0,0 +1,154 @@
+use azure_core::prelude::*;
+use azure_storage::blob::prelude::*;
+use azure_storage::core::prelude::*;
+
+use std::collections::HashSet;
+use std::error::Error;
+use anyhow::anyhow;
+use azst::Pipeline;
+use crate::block_hashes;
+use crate::hash::{self, HashAlgorithm, Hasher};
+use crate::manifest::ManifestEntry;
+
+// Get the blobs with the prefix, or the blob, and check their content by the hashes recorded on put,
+// without writing local files. The Content-MD5 is checked against the content as stored, and hashes in the metadata
+// (such as azst_sha256) against the content decoded by the transform pipeline.
+// Untransformed blobs are got by ranges of the block size with bounded memory.
+// With spot checks, only the number of random blocks of blobs with the hashes of their blocks in the sidecar
+// are got and checked, for a cheap partial verification of large blobs. Other blobs are checked as a whole.
+pub async fn verify(storage_client: &StorageClient, container: &str, blob: Option<&str>, prefix: &str,
+    transforms: &Pipeline, block_size: u64, spot_check: Option<usize>, debug: bool)
+    -> Result<(), Box<dyn Error + Send + Sync>>
+{
+    let blobs = crate::list_all_blobs(storage_client, container, Some(blob.unwrap_or(prefix))).await?;
+    let sidecars: HashSet<String> = blobs.iter()
+        .filter_map(|b| b.name.strip_suffix(block_hashes::SUFFIX))
+        .map(String::from)
+        .collect();
+    let blobs: Vec<Blob> = blobs.into_iter()
+        .filter(|b| blob.map_or(!b.name.ends_with('/'), |blob| b.name == blob))
+        .collect();
+    if let (Some(blob), true) = (blob, blobs.is_empty()) {
+        return Err(anyhow!("Blob '{}/{}' is not found", container, blob).into());
+    }
+
+    let container_client = storage_client.as_container_client(container);
+    let (mut verified, mut unverified, mut mismatched) = (0, 0, 0);
+    for blob in blobs.iter() {
+        let blob_client = container_client.as_blob_client(blob.name.as_str());
+        if let (Some(samples), true) = (spot_check, sidecars.contains(&blob.name)) {
+            let sidecar = format!("{}{}", blob.name, block_hashes::SUFFIX);
+            let sidecar_client = container_client.as_blob_client(sidecar.as_str());
+            let failed = block_hashes::spot_check(&blob_client, &sidecar_client, blob.properties.content_length, samples)
+                .await?;
+            if failed.is_empty() {
+                if debug {
+                    println!(" OK {} ({} blocks)", blob.name, samples);
+                }
+                verified += 1;
+            } else {
+                let failed: Vec<String> = failed.iter().map(|i| format!("block {}", i)).collect();
+                println!(" MISMATCH {}: {}", blob.name, failed.join(", "));
+                mismatched += 1;
+            }
+            continue;
+        }
+
+        let expected = hash::blob_digests(blob, false);
+        if expected.is_empty() {
+            println!(" UNVERIFIED {}: no hash recorded", blob.name);
+            unverified += 1;
+            continue;
+        }
+
+        let actual = match transforms.is_empty() {
+            true => ranged_digests(&blob_client, blob.properties.content_length, &expected, block_size).await?,
+            false => {
+                let data = blob_client.get().execute().await?.data.to_vec();
+                let md5 = HashAlgorithm::Md5.digest(&data);
+                let decoded = transforms.decode(data)?;
+                expected.iter()
+                    .map(|(algorithm, _)| match algorithm {
+                        HashAlgorithm::Md5 => md5.clone(),
+                        _ => algorithm.digest(&decoded)
+                    })
+                    .collect()
+            }
+        };
+
+        let failed: Vec<&str> = expected.iter().zip(actual.iter())
+            .filter(|((_, expected), actual)| expected != *actual)
+            .map(|((algorithm, _), _)| algorithm.name())
+            .collect();
+        let names: Vec<&str> = expected.iter().map(|(algorithm, _)| algorithm.name()).collect();
+        if failed.is_empty() {
+            if debug {
+                println!(" OK {} ({})", blob.name, names.join(", "));
+            }
+            verified += 1;
+        } else {
+            println!(" MISMATCH {}: {}", blob.name, failed.join(", "));
+            mismatched += 1;
+        }
+    }
+
+    println!("{} blobs verified, {} without hash, {} mismatched in '{}/{}'", verified, unverified, mismatched,
+        container, blob.unwrap_or(prefix));
+    if mismatched > 0 {
+        return Err(anyhow!("{} blobs do not match their hashes", mismatched).into());
+    }
+    Ok(())
+}
+
+// Read back the blobs just put, and check their size and SHA-256 hash against the local content in the entries,
+// as end-to-end verification beyond the Content-MD5 checked by the service on put.
+// The size is checked by the properties first. Blobs with transforms are got and decoded as a whole.
+pub async fn read_back(storage_client: &StorageClient, entries: &[ManifestEntry], transforms: &Pipeline,
+    block_size: u64, debug: bool) -> Result<(), Box<dyn Error + Send + Sync>>
+{
+    for entry in entries.iter() {
+        let blob_client = storage_client
+            .as_container_client(entry.container.as_str())
+            .as_blob_client(entry.blob.as_str());
+        let expected = [(HashAlgorithm::Sha256, entry.sha256.clone())];
+
+        let actual = match transforms.is_empty() {
+            true => {
+                let size = blob_client.get_properties().execute().await?.blob.properties.content_length;
+                if size != entry.size {
+                    return Err(anyhow!("Read-back of '{}/{}' failed: size is {} instead of {}", entry.container,
+                        entry.blob, size, entry.size).into());
+                }
+                ranged_digests(&blob_client, size, &expected, block_size).await?.remove(0)
+            },
+            false => {
+                let data = blob_client.get().execute().await?.data.to_vec();
+                HashAlgorithm::Sha256.digest(&transforms.decode(data)?)
+            }
+        };
+        if actual != entry.sha256 {
+            return Err(anyhow!("Read-back of '{}/{}' failed: sha256 is {} instead of {}", entry.container,
+                entry.blob, actual, entry.sha256).into());
+        }
+        if debug {
+            println!(" read back {}/{}: OK", entry.container, entry.blob);
+        }
+    }
+    println!("{} blobs verified by read-back", entries.len());
+    Ok(())
+}
+
+// Hashes of the content of the blob by the algorithms, got by ranges one by one
+async fn ranged_digests(blob_client: &BlobClient, size: u64, expected: &[(HashAlgorithm, String)], block_size: u64)
+    -> Result<Vec<String>, Box<dyn Error + Send + Sync>>
+{
+    let mut hashers: Vec<Hasher> = expected.iter().map(|(algorithm, _)| Hasher::new(*algorithm)).collect();
+    for start in (0..size).step_by(block_size.max(1) as usize) {
+        let end = (start + block_size).min(size);
+        let data = blob_client.get().range(Range::new(start, end)).execute().await?.data;
+        for hasher in hashers.iter_mut() {
+            hasher.update(&data);
+        }
+    }
+    Ok(hashers.into_iter().map(Hasher::finalize).collect())
+}
@@ -0,0 +1,73 @@
+use std::error::Error;
+use std::path::Path;
+use std::sync::Mutex;
+use wasmtime::{Engine, Instance, Memory, Module, Store};
+use crate::naming::NamingPolicy;
+use crate::transform::Transform;
+
+/// Transform and naming policy implemented by a WASM module.
+///
+/// The module exports `memory`, `alloc(len: i32) -> i32` to allocate an input buffer, and any of the hooks below.
+/// Each hook takes the pointer and length of the input, and returns the pointer and length of the output
+/// packed as `(ptr << 32) | len` in an i64.
+///
+/// - `encode`, `decode`: Transform of blob content
+/// - `blob_name`: Blob name from "<prefix>\n<relative path>"
+pub struct WasmPlugin {
+    name: String,
+    instance: Mutex<(Store<()>, Instance, Memory)>,
+}
+
+impl WasmPlugin {
+    pub fn from_file(path: &Path) -> Result<WasmPlugin, Box<dyn Error + Send + Sync>> {
+        let engine = Engine::default();
+        let module = Module::from_file(&engine, path)
+            .map_err(|e| format!("Cannot load WASM module {:?}: {}", path, e))?;
+        let mut store = Store::new(&engine, ());
+        let instance = Instance::new(&mut store, &module, &[])?;
+        let memory = instance.get_memory(&mut store, "memory")
+            .ok_or_else(|| format!("WASM module {:?} does not export memory", path))?;
+        Ok(WasmPlugin {
+            name: path.display().to_string(),
+            instance: Mutex::new((store, instance, memory)),
+        })
+    }
+
+    // Call a hook with the input, and take the output
+    fn call(&self, hook: &str, input: &[u8]) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+        let mut guard = self.instance.lock().map_err(|_| "WASM plugin is poisoned")?;
+        let (store, instance, memory) = &mut *guard;
+
+        let alloc = instance.get_typed_func::<i32, i32, _>(&mut *store, "alloc")?;
+        let function = instance.get_typed_func::<(i32, i32), i64, _>(&mut *store, hook)?;
+
+        let ptr = alloc.call(&mut *store, input.len() as i32)?;
+        memory.write(&mut *store, ptr as u32 as usize, input)?;
+        let packed = function.call(&mut *store, (ptr, input.len() as i32))? as u64;
+
+        let mut output = vec![0u8; (packed & 0xffff_ffff) as usize];
+        memory.read(&*store, (packed >> 32) as usize, &mut output)?;
+        Ok(output)
+    }
+}
+
+impl Transform for WasmPlugin {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn encode(&self, data: Vec<u8>) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+        self.call("encode", &data)
+    }
+
+    fn decode(&self, data: Vec<u8>) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+        self.call("decode", &data)
+    }
+}
+
+impl NamingPolicy for WasmPlugin {
+    fn blob_name(&self, prefix: &str, relative_path: &str) -> Result<String, Box<dyn Error + Send + Sync>> {
+        let name = self.call("blob_name", format!("{}\n{}", prefix, relative_path).as_bytes())?;
+        Ok(String::from_utf8(name).map_err(|_| "Blob name is not UTF-8")?)
+    }
+}
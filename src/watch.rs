@@ -0,0 +1,137 @@
+use azure_storage::core::prelude::*;
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::SystemTime;
+use std::error::Error;
+use anyhow::anyhow;
+use azst::{NamingPolicy, Pipeline};
+use tokio::time::{self, Duration};
+use crate::after_upload::AfterUpload;
+use crate::audit::AuditLog;
+use crate::hash::HashAlgorithm;
+use crate::progress::Progress;
+use crate::reload::Reload;
+use crate::upload::{self, Blocks};
+use crate::verify;
+
+// Directory under the watched directory which uploaded files are moved to
+pub const UPLOADED_DIR: &str = "uploaded";
+
+// Suffixes of files still being written, which are renamed to their final names when closed
+const TEMP_SUFFIXES: &[&str] = &[".tmp", ".part", ".partial", "~"];
+
+// Whether the file is written by the tmp-then-rename pattern and not handed off yet
+fn is_temporary(name: &str) -> bool {
+    name.starts_with('.') || TEMP_SUFFIXES.iter().any(|suffix| name.ends_with(suffix))
+}
+
+// Watch the local directory by polling at the interval, and put files handed off into it, named by the naming
+// policy from the prefix. Files are put only when closed: hidden files and files with temporary suffixes are
+// skipped until renamed to their final names, and files written in place are put when their size and
+// modification time are unchanged for an interval. Put files are verified by read-back before they are deleted,
+// moved or truncated. Files left are not put again unless modified. Subdirectories are not watched.
+// Puts all files once if the interval is 0. The config is reloaded on SIGHUP before the next poll.
+pub async fn watch(storage_client: &Arc<StorageClient>, dir: &Path, container: &str, prefix: &str,
+    naming: &dyn NamingPolicy, transforms: &Pipeline, blocks: Blocks, hash: HashAlgorithm, interval: u64,
+    after_upload: &AfterUpload, reload: &mut Reload, progress: &Progress, audit: &AuditLog, debug: bool)
+    -> Result<(), Box<dyn Error + Send + Sync>>
+{
+    let mut storage_client = storage_client.clone();
+    // Size and modification time of files at the last poll, and of files put and left
+    let mut seen: HashMap<PathBuf, (u64, SystemTime)> = HashMap::new();
+    let mut put: HashMap<PathBuf, (u64, SystemTime)> = HashMap::new();
+
+    loop {
+        if let Some(reloaded) = reload.poll().await {
+            storage_client = reloaded;
+        }
+        let current = scan(dir)?;
+        let mut ready: Vec<_> = current.iter()
+            .filter(|(path, state)| interval == 0 || seen.get(*path) == Some(*state))
+            .filter(|(path, state)| put.get(*path) != Some(*state))
+            .map(|(path, _)| path.clone())
+            .collect();
+        ready.sort();
+        if debug {
+            println!("{} files under {:?}, {} ready", current.len(), dir, ready.len());
+        }
+
+        for path in ready {
+            let result = async {
+                let blob = upload::blob_name(naming, prefix, path.strip_prefix(dir)?)?;
+                let entries = [upload::put_file(&storage_client, &path, container, &blob, transforms, None, blocks,
+                    hash, None, progress, audit, debug).await?];
+                println!(" {} -> {}", path.display(), blob);
+                if *after_upload != AfterUpload::Keep {
+                    verify::read_back(&storage_client, &entries, transforms, blocks.size, debug).await?;
+                    after_upload.apply(&entries, dir, debug)?;
+                }
+                Ok::<_, Box<dyn Error + Send + Sync>>(())
+            }.await;
+            match result {
+                // Truncated files are left with the new size and modification time
+                Ok(()) => if let Ok(metadata) = fs::metadata(&path) {
+                    put.insert(path, (metadata.len(), metadata.modified()?));
+                },
+                Err(e) if interval > 0 => eprintln!("Cannot put {:?}: {}", path, e),
+                Err(e) => return Err(e)
+            }
+        }
+
+        put.retain(|path, _| current.contains_key(path));
+        seen = current;
+        if interval == 0 {
+            return Ok(());
+        }
+        time::sleep(Duration::from_secs(interval)).await;
+    }
+}
+
+// Size and modification time of files handed off into the directory
+fn scan(dir: &Path) -> Result<HashMap<PathBuf, (u64, SystemTime)>, Box<dyn Error + Send + Sync>> {
+    let mut files = HashMap::new();
+    for entry in fs::read_dir(dir).map_err(|e| anyhow!("Cannot read {:?}: {}", dir, e))? {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+        match entry.file_name().to_str() {
+            Some(name) if metadata.is_file() && !is_temporary(name) => {
+                files.insert(entry.path(), (metadata.len(), metadata.modified()?));
+            },
+            _ => {}
+        }
+    }
+    Ok(files)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    #[test]
+    fn temporary_files() {
+        for name in [".report.csv", "report.csv.tmp", "report.csv.part", "report.csv.partial", "report.csv~"] {
+            assert!(is_temporary(name), "{}", name);
+        }
+        for name in ["report.csv", "report.tmp.csv", "partial.csv"] {
+            assert!(!is_temporary(name), "{}", name);
+        }
+    }
+
+    #[test]
+    fn scan_skips_temporary_files_and_directories() {
+        let dir = env::temp_dir().join(format!("azst-watch-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(dir.join("uploaded")).unwrap();
+        fs::write(dir.join("report.csv"), b"a,b").unwrap();
+        fs::write(dir.join("report.csv.tmp"), b"a,b,c").unwrap();
+        fs::write(dir.join(".report.csv"), b"a").unwrap();
+
+        let files = scan(&dir).unwrap();
+        assert_eq!(files.keys().collect::<Vec<_>>(), vec![&dir.join("report.csv")]);
+        assert_eq!(files[&dir.join("report.csv")].0, 3);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}